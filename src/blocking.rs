@@ -0,0 +1,279 @@
+//! Blocking sockets, for use from plain threads without any async runtime
+
+use crate::{Capabilities, RecvMeta, Transmit, UdpSocketState};
+use std::{
+    io::{self, IoSliceMut},
+    net::{SocketAddr, ToSocketAddrs},
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+/// UDP socket which blocks the calling thread until datagrams can be sent or received
+///
+/// Batches are sent and received exactly as by the asynchronous sockets, including GSO, GRO
+/// and ECN. The socket waits for readiness with `poll(2)`, bounded by the read and write
+/// timeouts, and may be shared between threads.
+#[derive(Debug)]
+pub struct UdpSocket {
+    io: std::net::UdpSocket,
+    inner: UdpSocketState,
+    read_timeout: RwLock<Option<Duration>>,
+    write_timeout: RwLock<Option<Duration>>,
+}
+
+impl UdpSocket {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::from_std(std::net::UdpSocket::bind(addr)?)
+    }
+
+    pub fn from_std(socket: std::net::UdpSocket) -> io::Result<Self> {
+        UdpSocketState::configure((&socket).into())?;
+        Ok(Self {
+            io: socket,
+            inner: UdpSocketState::new(),
+            read_timeout: RwLock::new(None),
+            write_timeout: RwLock::new(None),
+        })
+    }
+
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let mut last_err = None;
+
+        for addr in addr.to_socket_addrs()? {
+            match self.inner.connect((&self.io).into(), addr) {
+                Ok(()) => return Ok(()),
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not connect to any of the addresses",
+            )
+        }))
+    }
+
+    /// Dissolves the association set up by [`UdpSocket::connect`]
+    pub fn disconnect(&self) -> io::Result<()> {
+        self.inner.disconnect((&self.io).into())
+    }
+
+    /// Reports IPv4 peers of a dual-stack socket as `SocketAddr::V4` instead of IPv4-mapped IPv6
+    /// addresses, and maps IPv4 destinations back when sending
+    pub fn set_canonicalize_mapped_ipv4(&self, enabled: bool) -> io::Result<()> {
+        self.inner
+            .set_canonicalize_mapped_ipv4((&self.io).into(), enabled)
+    }
+
+    /// Sets how long receiving may block before failing with [`io::ErrorKind::TimedOut`]
+    ///
+    /// `None`, the default, blocks until a datagram arrives. A zero timeout fails right away
+    /// when nothing was received.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+        *self.read_timeout.write().unwrap() = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        *self.read_timeout.read().unwrap()
+    }
+
+    /// Sets how long sending may block before failing with [`io::ErrorKind::TimedOut`]
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) {
+        *self.write_timeout.write().unwrap() = timeout;
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        *self.write_timeout.read().unwrap()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.io.peer_addr()
+    }
+
+    pub fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> io::Result<usize> {
+        let addr = match addr.to_socket_addrs()?.next() {
+            Some(addr) => addr,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no addresses to send data to",
+                ));
+            }
+        };
+
+        self.block(Interest::Writable, deadline(self.write_timeout()), || {
+            self.io.send_to(buf, addr)
+        })
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.block(Interest::Readable, deadline(self.read_timeout()), || {
+            self.io.recv_from(buf)
+        })
+    }
+
+    /// Sends a batch of datagrams, returning how many transmits were sent
+    pub fn send(&self, capabilities: &Capabilities, transmits: &[Transmit]) -> io::Result<usize> {
+        self.block(Interest::Writable, deadline(self.write_timeout()), || {
+            self.inner.send((&self.io).into(), capabilities, transmits)
+        })
+    }
+
+    /// Receives a batch of datagrams, returning how many buffers were filled
+    pub fn recv(&self, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> io::Result<usize> {
+        self.block(Interest::Readable, deadline(self.read_timeout()), || {
+            self.inner.recv((&self.io).into(), bufs, meta)
+        })
+    }
+
+    /// Sends a batch of datagrams like [`UdpSocket::send`], but with `timeout` instead of the
+    /// write timeout
+    pub fn send_timeout(
+        &self,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+        timeout: Duration,
+    ) -> io::Result<usize> {
+        self.block(Interest::Writable, deadline(Some(timeout)), || {
+            self.inner.send((&self.io).into(), capabilities, transmits)
+        })
+    }
+
+    /// Receives a batch of datagrams like [`UdpSocket::recv`], but with `timeout` instead of
+    /// the read timeout
+    pub fn recv_timeout(
+        &self,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+        timeout: Duration,
+    ) -> io::Result<usize> {
+        self.block(Interest::Readable, deadline(Some(timeout)), || {
+            self.inner.recv((&self.io).into(), bufs, meta)
+        })
+    }
+
+    /// Receives a batch of datagrams like [`UdpSocket::recv`], failing with
+    /// [`io::ErrorKind::TimedOut`] if none arrived by `deadline`
+    ///
+    /// Datagrams which are already queued are returned even if the deadline has passed.
+    pub fn recv_deadline(
+        &self,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+        deadline: Instant,
+    ) -> io::Result<usize> {
+        self.block(Interest::Readable, Some(deadline), || {
+            self.inner.recv((&self.io).into(), bufs, meta)
+        })
+    }
+
+    pub fn get_ref(&self) -> &std::net::UdpSocket {
+        &self.io
+    }
+
+    /// Retries `f` whenever the socket becomes ready, until it stops failing with
+    /// [`io::ErrorKind::WouldBlock`] or `deadline` passed
+    fn block<R>(
+        &self,
+        interest: Interest,
+        deadline: Option<Instant>,
+        mut f: impl FnMut() -> io::Result<R>,
+    ) -> io::Result<R> {
+        loop {
+            match f() {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return res,
+            }
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if timeout == Some(Duration::ZERO) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting on the socket",
+                ));
+            }
+            poll(&self.io, interest, timeout)?;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Interest {
+    Readable,
+    Writable,
+}
+
+/// The deadline of an operation which may block for `timeout`, `None` if it may block forever
+fn deadline(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.and_then(|timeout| Instant::now().checked_add(timeout))
+}
+
+/// Waits until `socket` is ready for `interest` or `timeout` elapsed
+///
+/// Errors pending on the socket also wake it up, to be reported by the next operation.
+#[cfg(unix)]
+fn poll(
+    socket: &std::net::UdpSocket,
+    interest: Interest,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut fd = libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events: match interest {
+            Interest::Readable => libc::POLLIN,
+            Interest::Writable => libc::POLLOUT,
+        },
+        revents: 0,
+    };
+    let rc = unsafe { libc::poll(&mut fd, 1, poll_timeout(timeout)) };
+    match rc {
+        -1 => match io::Error::last_os_error() {
+            // Interrupted by a signal, the caller retries with the remaining time
+            e if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+            e => Err(e),
+        },
+        _ => Ok(()),
+    }
+}
+
+#[cfg(windows)]
+fn poll(
+    socket: &std::net::UdpSocket,
+    interest: Interest,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    use std::os::windows::io::AsRawSocket;
+    use windows_sys::Win32::Networking::WinSock;
+
+    let mut fd = WinSock::WSAPOLLFD {
+        fd: socket.as_raw_socket() as _,
+        events: match interest {
+            Interest::Readable => WinSock::POLLRDNORM,
+            Interest::Writable => WinSock::POLLWRNORM,
+        },
+        revents: 0,
+    };
+    let rc = unsafe { WinSock::WSAPoll(&mut fd, 1, poll_timeout(timeout)) };
+    match rc {
+        WinSock::SOCKET_ERROR => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Converts a timeout to milliseconds, rounding up so that waiting never ends early
+fn poll_timeout(timeout: Option<Duration>) -> i32 {
+    match timeout {
+        Some(timeout) => {
+            let millis = timeout.as_nanos().div_ceil(1_000_000);
+            millis.min(i32::MAX as u128) as i32
+        }
+        None => -1,
+    }
+}
@@ -0,0 +1,31 @@
+use std::{io, net::SocketAddr};
+
+use super::{interfaces::interface_addrs, Transmit, UdpSockRef};
+
+/// Builds one transmit of `contents` to `port` per broadcast capable interface, with the
+/// source address and the index of the interface to send it from
+pub(crate) fn transmits(contents: &[u8], port: u16) -> io::Result<Vec<(Transmit, u32)>> {
+    Ok(interface_addrs()?
+        .into_iter()
+        .filter_map(|interface| {
+            let broadcast = interface.broadcast?;
+            let transmit = Transmit {
+                destination: Some(SocketAddr::new(broadcast.into(), port)),
+                ecn: None,
+                contents: contents.to_vec(),
+                segment_size: None,
+                src_ip: Some(interface.addr),
+            };
+            Some((transmit, interface.index))
+        })
+        .collect())
+}
+
+/// Sets whether `socket` may send datagrams to broadcast addresses (`SO_BROADCAST`)
+pub(crate) fn set(socket: UdpSockRef<'_>, enabled: bool) -> io::Result<()> {
+    socket.0.set_broadcast(enabled)
+}
+
+pub(crate) fn get(socket: UdpSockRef<'_>) -> io::Result<bool> {
+    socket.0.broadcast()
+}
@@ -0,0 +1,133 @@
+use std::{io, mem, os::unix::io::AsRawFd, time::Duration};
+
+use super::{imp, UdpSockRef};
+
+/// `SO_BUSY_POLL`, `SO_PREFER_BUSY_POLL` and `SO_BUSY_POLL_BUDGET`
+///
+/// `libc` does not export them for Linux, and their values differ between architectures, so
+/// they are only known for the architectures listed here.
+const OPTIONS: Option<Options> = if cfg!(any(target_arch = "sparc", target_arch = "sparc64")) {
+    Some(Options {
+        busy_poll: 0x30,
+        prefer_busy_poll: 0x48,
+        budget: 0x49,
+    })
+} else if cfg!(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "loongarch64",
+    target_arch = "csky",
+    target_arch = "hexagon",
+    target_arch = "m68k"
+)) {
+    Some(Options {
+        busy_poll: 46,
+        prefer_busy_poll: 69,
+        budget: 70,
+    })
+} else {
+    None
+};
+
+#[derive(Clone, Copy)]
+struct Options {
+    busy_poll: libc::c_int,
+    prefer_busy_poll: libc::c_int,
+    budget: libc::c_int,
+}
+
+fn options() -> io::Result<Options> {
+    OPTIONS.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "busy polling options are unknown on this architecture",
+        )
+    })
+}
+
+/// Busy polling of a socket, which trades CPU time for receive latency
+///
+/// The kernel options are only changed where they are `Some`. Raising [`Self::busy_poll`] or
+/// [`Self::budget`] above their current value requires `CAP_NET_ADMIN`. The default changes no
+/// kernel option and does not spin. On architectures whose option numbers are not known,
+/// changing or reading the kernel options fails with [`io::ErrorKind::Unsupported`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BusyPollConfig {
+    /// How long the kernel polls the device queue for datagrams when receiving
+    /// (`SO_BUSY_POLL`), with microsecond resolution
+    pub busy_poll: Option<Duration>,
+    /// Whether busy polling is preferred over interrupt driven processing of the device queue
+    /// (`SO_PREFER_BUSY_POLL`), since Linux 5.11
+    pub prefer_busy_poll: Option<bool>,
+    /// The number of packets processed by one busy poll (`SO_BUSY_POLL_BUDGET`), since Linux 5.11
+    pub budget: Option<u16>,
+    /// How long receiving keeps retrying without blocking before it waits on the runtime reactor
+    ///
+    /// This is the latency versus CPU knob of the runtime sockets: a spinning task is polled
+    /// again as soon as the executor gets to it, which keeps a CPU busy, in exchange for not
+    /// waiting for the reactor to notice new datagrams. Zero waits on the reactor right away.
+    pub spin: Duration,
+}
+
+/// Applies the kernel options of `config` to `socket`
+pub(crate) fn set(socket: UdpSockRef<'_>, config: &BusyPollConfig) -> io::Result<()> {
+    let fd = socket.0.as_raw_fd();
+    if config.busy_poll.is_none() && config.prefer_busy_poll.is_none() && config.budget.is_none() {
+        return Ok(());
+    }
+    let options = options()?;
+    if let Some(busy_poll) = config.busy_poll {
+        let usecs = busy_poll.as_micros().min(libc::c_int::MAX as u128) as libc::c_int;
+        imp::set_socket_option(&fd, libc::SOL_SOCKET, options.busy_poll, usecs)?;
+    }
+    if let Some(prefer) = config.prefer_busy_poll {
+        let value = libc::c_int::from(prefer);
+        imp::set_socket_option(&fd, libc::SOL_SOCKET, options.prefer_busy_poll, value)?;
+    }
+    if let Some(budget) = config.budget {
+        let value = libc::c_int::from(budget);
+        imp::set_socket_option(&fd, libc::SOL_SOCKET, options.budget, value)?;
+    }
+    Ok(())
+}
+
+/// Reads the kernel options of `socket`, leaving [`BusyPollConfig::spin`] zero
+///
+/// The kernel does not report the budget, which is returned as `None`.
+pub(crate) fn get(socket: UdpSockRef<'_>) -> io::Result<BusyPollConfig> {
+    let fd = socket.0.as_raw_fd();
+    let options = options()?;
+    Ok(BusyPollConfig {
+        busy_poll: Some(Duration::from_micros(option(fd, options.busy_poll)? as u64)),
+        prefer_busy_poll: Some(option(fd, options.prefer_busy_poll)? != 0),
+        budget: None,
+        spin: Duration::ZERO,
+    })
+}
+
+fn option(fd: libc::c_int, name: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of_val(&value) as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            name,
+            &mut value as *mut _ as _,
+            &mut len,
+        )
+    };
+    match rc == 0 {
+        true => Ok(value),
+        false => Err(io::Error::last_os_error()),
+    }
+}
@@ -32,7 +32,7 @@ impl<'a> Encoder<'a> {
     /// # Panics
     /// - If insufficient buffer space remains.
     /// - If `T` has stricter alignment requirements than `cmsghdr`
-    pub fn push<T: Copy + ?Sized>(&mut self, level: libc::c_int, ty: libc::c_int, value: T) {
+    pub fn push<T: Copy>(&mut self, level: libc::c_int, ty: libc::c_int, value: T) {
         assert!(mem::align_of::<T>() <= mem::align_of::<libc::cmsghdr>());
         let space = unsafe { libc::CMSG_SPACE(mem::size_of_val(&value) as _) as usize };
         #[allow(clippy::unnecessary_cast)] // hdr.msg_controllen defined as size_t
@@ -55,6 +55,39 @@ impl<'a> Encoder<'a> {
         self.cmsg = unsafe { libc::CMSG_NXTHDR(self.hdr, cmsg).as_mut() };
     }
 
+    /// Append a control message holding several values, such as the descriptors of `SCM_RIGHTS`.
+    ///
+    /// # Panics
+    /// - If insufficient buffer space remains.
+    /// - If `T` has stricter alignment requirements than `cmsghdr`
+    pub fn push_slice<T: Copy>(&mut self, level: libc::c_int, ty: libc::c_int, values: &[T]) {
+        assert!(mem::align_of::<T>() <= mem::align_of::<libc::cmsghdr>());
+        let data_len = mem::size_of_val(values);
+        let space = unsafe { libc::CMSG_SPACE(data_len as _) as usize };
+        #[allow(clippy::unnecessary_cast)] // hdr.msg_controllen defined as size_t
+        {
+            assert!(
+                self.hdr.msg_controllen as usize >= self.len + space,
+                "control message buffer too small. Required: {}, Available: {}",
+                self.len + space,
+                self.hdr.msg_controllen
+            );
+        }
+        let cmsg = self.cmsg.take().expect("no control buffer space remaining");
+        cmsg.cmsg_level = level;
+        cmsg.cmsg_type = ty;
+        cmsg.cmsg_len = unsafe { libc::CMSG_LEN(data_len as _) } as _;
+        unsafe {
+            ptr::copy_nonoverlapping(
+                values.as_ptr(),
+                libc::CMSG_DATA(cmsg) as *mut T,
+                values.len(),
+            );
+        }
+        self.len += space;
+        self.cmsg = unsafe { libc::CMSG_NXTHDR(self.hdr, cmsg).as_mut() };
+    }
+
     /// Finishes appending control messages to the buffer
     pub fn finish(self) {
         // Delegates to the `Drop` impl
@@ -84,6 +117,19 @@ pub unsafe fn decode<T: Copy>(cmsg: &libc::cmsghdr) -> T {
     ptr::read(libc::CMSG_DATA(cmsg) as *const T)
 }
 
+/// # Safety
+///
+/// `cmsg` must refer to a cmsg containing a payload of zero or more values of type `T`
+pub unsafe fn decode_slice<T: Copy>(cmsg: &libc::cmsghdr) -> Vec<T> {
+    assert!(mem::align_of::<T>() <= mem::align_of::<libc::cmsghdr>());
+    #[allow(clippy::unnecessary_cast)] // cmsg.cmsg_len defined as size_t
+    let data_len = (cmsg.cmsg_len as usize).saturating_sub(libc::CMSG_LEN(0) as usize);
+    let data = libc::CMSG_DATA(cmsg) as *const T;
+    (0..data_len / mem::size_of::<T>())
+        .map(|i| ptr::read(data.add(i)))
+        .collect()
+}
+
 pub struct Iter<'a> {
     hdr: &'a libc::msghdr,
     cmsg: Option<&'a libc::cmsghdr>,
@@ -0,0 +1,292 @@
+use std::{
+    io::{self, IoSliceMut},
+    mem::{self, MaybeUninit},
+    net::{IpAddr, SocketAddr},
+    os::fd::{AsRawFd, BorrowedFd},
+};
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+use super::{cmsg, imp, EcnCodepoint};
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Length of the type, code, checksum, identifier and sequence number fields
+const ECHO_HEADER_LEN: usize = 8;
+
+/// An ICMP or ICMPv6 echo message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcmpEcho {
+    /// Identifier of the echo session
+    ///
+    /// Linux replaces this with the identifier of the sending socket, which is the port of its
+    /// [`local_addr`](crate::IcmpSocket::local_addr), and only delivers replies carrying it.
+    pub identifier: u16,
+    pub sequence: u16,
+    pub payload: Vec<u8>,
+}
+
+impl IcmpEcho {
+    /// Encodes an echo request, for ICMPv6 if `ipv6` is set
+    ///
+    /// The checksum is filled in for ICMP. The kernel computes it for ICMPv6, as it covers the
+    /// IPv6 pseudo-header.
+    pub fn encode_request(&self, ipv6: bool) -> Vec<u8> {
+        let ty = match ipv6 {
+            true => ICMPV6_ECHO_REQUEST,
+            false => ICMP_ECHO_REQUEST,
+        };
+        let mut packet = Vec::with_capacity(ECHO_HEADER_LEN + self.payload.len());
+        packet.extend_from_slice(&[ty, 0, 0, 0]);
+        packet.extend_from_slice(&self.identifier.to_be_bytes());
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&self.payload);
+        if !ipv6 {
+            let checksum = checksum(&packet);
+            packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+        }
+        packet
+    }
+
+    /// Decodes an echo reply, for ICMPv6 if `ipv6` is set
+    ///
+    /// Returns `None` if `packet` is not an echo reply, e.g. because it reports an error.
+    pub fn decode_reply(packet: &[u8], ipv6: bool) -> Option<Self> {
+        let expected = match ipv6 {
+            true => ICMPV6_ECHO_REPLY,
+            false => ICMP_ECHO_REPLY,
+        };
+        if packet.len() < ECHO_HEADER_LEN || packet[0] != expected || packet[1] != 0 {
+            return None;
+        }
+        Some(Self {
+            identifier: u16::from_be_bytes([packet[4], packet[5]]),
+            sequence: u16::from_be_bytes([packet[6], packet[7]]),
+            payload: packet[ECHO_HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+/// The internet checksum of RFC 1071
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|chunk| match *chunk {
+            [a, b] => u32::from(u16::from_be_bytes([a, b])),
+            [a] => u32::from(u16::from_be_bytes([a, 0])),
+            _ => unreachable!(),
+        })
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// An ICMP message to be sent, usually an encoded [`IcmpEcho`] request
+#[derive(Debug, Clone)]
+pub struct IcmpTransmit {
+    /// The host this message should be sent to
+    pub destination: IpAddr,
+    /// Explicit congestion notification bits to set on the packet
+    pub ecn: Option<EcnCodepoint>,
+    /// Contents of the message, starting with the ICMP header
+    pub contents: Vec<u8>,
+    /// The source IP address to use for the packet
+    pub src_ip: Option<IpAddr>,
+}
+
+/// Metadata of an ICMP message received through an ICMP socket
+#[derive(Debug, Copy, Clone)]
+pub struct IcmpRecvMeta {
+    /// The host the message was received from
+    pub addr: IpAddr,
+    /// Length of the message, starting with the ICMP header
+    pub len: usize,
+    pub ecn: Option<EcnCodepoint>,
+    /// The destination IP address which was encoded in this message
+    pub dst_ip: Option<IpAddr>,
+    /// The TTL or hop limit the message arrived with
+    pub ttl: Option<u8>,
+}
+
+impl Default for IcmpRecvMeta {
+    /// Constructs a value with arbitrary fields, intended to be overwritten
+    fn default() -> Self {
+        Self {
+            addr: IpAddr::from([0, 0, 0, 0]),
+            len: 0,
+            ecn: None,
+            dst_ip: None,
+            ttl: None,
+        }
+    }
+}
+
+/// Creates a non-blocking, unprivileged ICMP socket bound to `addr`
+///
+/// On Linux this requires the group of the process to be within `net.ipv4.ping_group_range`,
+/// and fails with [`io::ErrorKind::PermissionDenied`] otherwise.
+pub(crate) fn bind(addr: IpAddr) -> io::Result<Socket> {
+    let socket = match addr {
+        IpAddr::V4(_) => Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4))?,
+        IpAddr::V6(_) => Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::ICMPV6))?,
+    };
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::new(addr, 0).into())?;
+    configure(&socket, addr.is_ipv6())?;
+    Ok(socket)
+}
+
+pub(crate) fn local_addr(io: BorrowedFd<'_>) -> io::Result<SocketAddr> {
+    SockRef::from(&io)
+        .local_addr()?
+        .as_socket()
+        .ok_or_else(|| io::Error::other("not an IP socket"))
+}
+
+fn configure(socket: &Socket, ipv6: bool) -> io::Result<()> {
+    if ipv6 {
+        imp::set_socket_option(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_RECVTCLASS,
+            imp::OPTION_ON,
+        )?;
+        imp::set_socket_option(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_RECVPKTINFO,
+            imp::OPTION_ON,
+        )?;
+        imp::set_socket_option(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_RECVHOPLIMIT,
+            imp::OPTION_ON,
+        )?;
+        return Ok(());
+    }
+
+    if let Err(err) =
+        imp::set_socket_option(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, imp::OPTION_ON)
+    {
+        tracing::debug!("Ignoring error setting IP_RECVTOS on socket: {err:?}",);
+    }
+    #[cfg(target_os = "linux")]
+    imp::set_socket_option(socket, libc::IPPROTO_IP, libc::IP_PKTINFO, imp::OPTION_ON)?;
+    #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+    imp::set_socket_option(
+        socket,
+        libc::IPPROTO_IP,
+        libc::IP_RECVDSTADDR,
+        imp::OPTION_ON,
+    )?;
+    imp::set_socket_option(socket, libc::IPPROTO_IP, libc::IP_RECVTTL, imp::OPTION_ON)
+}
+
+/// Sends messages from `transmits`, returning the number of messages sent
+///
+/// Errors are reported rather than swallowed, as they usually say something about the path
+/// being probed.
+pub(crate) fn send(io: BorrowedFd<'_>, transmits: &[IcmpTransmit]) -> io::Result<usize> {
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    let mut iov: libc::iovec = unsafe { mem::zeroed() };
+    let mut ctrl = cmsg::Aligned([0u8; CMSG_LEN]);
+    let mut sent = 0;
+
+    while sent < transmits.len() {
+        let transmit = &transmits[sent];
+        let dst_addr = socket2::SockAddr::from(SocketAddr::new(transmit.destination, 0));
+        iov.iov_base = transmit.contents.as_ptr() as *const _ as *mut _;
+        iov.iov_len = transmit.contents.len();
+        hdr.msg_name = dst_addr.as_ptr() as *mut _;
+        hdr.msg_namelen = dst_addr.len();
+        hdr.msg_iov = &mut iov;
+        hdr.msg_iovlen = 1;
+        hdr.msg_control = ctrl.0.as_mut_ptr() as _;
+        hdr.msg_controllen = CMSG_LEN as _;
+        let mut encoder = unsafe { cmsg::Encoder::new(&mut hdr) };
+        imp::encode_ip_cmsgs(
+            &mut encoder,
+            &dst_addr,
+            transmit.ecn,
+            transmit.src_ip,
+            cfg!(target_os = "macos"),
+            0,
+        );
+        encoder.finish();
+
+        let n = unsafe { libc::sendmsg(io.as_raw_fd(), &hdr, 0) };
+        if n == -1 {
+            let e = io::Error::last_os_error();
+            match e.kind() {
+                io::ErrorKind::Interrupted => {}
+                _ if sent != 0 => return Ok(sent),
+                _ => return Err(e),
+            }
+        } else {
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+/// Receives a single message into `bufs[0]`, or nothing if there are no buffers
+pub(crate) fn recv(
+    io: BorrowedFd<'_>,
+    bufs: &mut [IoSliceMut<'_>],
+    meta: &mut [IcmpRecvMeta],
+) -> io::Result<usize> {
+    if bufs.is_empty() || meta.is_empty() {
+        return Ok(0);
+    }
+    let mut name = MaybeUninit::<libc::sockaddr_storage>::uninit();
+    let mut ctrl = cmsg::Aligned(MaybeUninit::<[u8; CMSG_LEN]>::uninit());
+    let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
+    hdr.msg_name = name.as_mut_ptr() as _;
+    hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+    hdr.msg_iov = &mut bufs[0] as *mut IoSliceMut<'_> as *mut libc::iovec;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = ctrl.0.as_mut_ptr() as _;
+    hdr.msg_controllen = CMSG_LEN as _;
+    let n = loop {
+        let n = unsafe { libc::recvmsg(io.as_raw_fd(), &mut hdr, 0) };
+        if n == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        break n as usize;
+    };
+
+    let addr = imp::decode_name(&name).ip();
+    let cmsgs = unsafe { imp::decode_ip_cmsgs(&hdr) };
+    #[allow(unused_mut)] // only mutable on Apple platforms
+    let mut len = n;
+    // Unlike Linux, Apple platforms prepend the IPv4 header to received messages
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    if addr.is_ipv4() {
+        let buf = &mut bufs[0][..n];
+        let header_len = buf.first().map_or(0, |b| usize::from(b & 0x0f) * 4).min(n);
+        buf.copy_within(header_len.., 0);
+        len -= header_len;
+    }
+
+    meta[0] = IcmpRecvMeta {
+        addr,
+        len,
+        ecn: cmsgs.ecn,
+        dst_ip: cmsgs.dst_ip,
+        ttl: cmsgs.ttl,
+    };
+    Ok(1)
+}
+
+/// Room for the ECN, pktinfo and TTL control messages
+const CMSG_LEN: usize = 128;
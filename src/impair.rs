@@ -0,0 +1,606 @@
+use crate::rng::Rng;
+use crate::runtime::AsyncUdpSocket;
+use crate::{timer, Capabilities, EcnCodepoint, RecvMeta, Transmit};
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fmt,
+    future::poll_fn,
+    io::{self, IoSliceMut},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+/// Number of datagrams held back in each direction before sending blocks or receiving drops
+const QUEUE_LEN: usize = 4096;
+
+/// Impairments applied to the datagrams travelling in one direction through an
+/// [`ImpairedSocket`]
+///
+/// The default configuration passes every datagram through immediately and unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImpairmentConfig {
+    /// Probability in `0.0..=1.0` that a datagram is dropped
+    pub loss: f64,
+    /// Bursty loss on top of [`Self::loss`], following a Gilbert-Elliott model
+    pub burst_loss: Option<GilbertElliott>,
+    /// Delay added to every datagram
+    pub delay: Duration,
+    /// Upper bound of a uniformly distributed delay added on top of `delay`
+    pub jitter: Duration,
+    /// Probability that a datagram is held back by [`Self::reorder_delay`], so that datagrams
+    /// after it overtake it
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+    /// Probability that a datagram is passed on twice
+    pub duplicate: f64,
+    /// Probability that a datagram is cut to a random, shorter length
+    pub truncate: f64,
+    /// Probability that an ECN-capable datagram is marked as having experienced congestion
+    pub ce_mark: f64,
+    /// Clears the ECN codepoint of every datagram, like a router which does not support ECN
+    pub strip_ecn: bool,
+}
+
+impl Default for ImpairmentConfig {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            burst_loss: None,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            reorder: 0.0,
+            reorder_delay: Duration::ZERO,
+            duplicate: 0.0,
+            truncate: 0.0,
+            ce_mark: 0.0,
+            strip_ecn: false,
+        }
+    }
+}
+
+/// A two-state Markov chain modelling loss which comes in bursts
+///
+/// The channel moves between a good and a bad state before every datagram, and drops it with
+/// the loss probability of the state it ends up in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GilbertElliott {
+    /// Probability of moving from the good to the bad state
+    pub p: f64,
+    /// Probability of moving from the bad back to the good state
+    pub r: f64,
+    /// Probability that a datagram is dropped in the good state
+    pub loss_good: f64,
+    /// Probability that a datagram is dropped in the bad state
+    pub loss_bad: f64,
+}
+
+/// Wraps a socket and injects faults into the datagrams it sends and receives
+///
+/// This allows soak-testing against real kernels without privileges to configure the network
+/// itself. Sent and received datagrams are impaired by separate [`ImpairmentConfig`]s, which
+/// can be changed at any time. The random decisions are drawn from a generator seeded by
+/// [`ImpairedSocket::new`].
+///
+/// Delayed datagrams are handed to the wrapped socket whenever the socket is polled in either
+/// direction, so a task should keep receiving while datagrams are delayed on the way out. When
+/// the wrapped socket is not writable, a receiving task also wakes the task waiting to send.
+pub struct ImpairedSocket<S> {
+    socket: S,
+    capabilities: Arc<Capabilities>,
+    state: Mutex<State>,
+}
+
+struct State {
+    rng: Rng,
+    send: Direction,
+    recv: Direction,
+    next_seq: u64,
+    /// The task waiting for [`AsyncUdpSocket::poll_send`] or [`AsyncUdpSocket::poll_writable`]
+    send_waker: Option<Waker>,
+    /// The waker last used to flush from the receive path, reused while the tasks stay the same
+    flush_waker: Option<(Arc<Wakers>, Waker)>,
+}
+
+/// The configuration and queue of one direction
+#[derive(Default)]
+struct Direction {
+    config: ImpairmentConfig,
+    /// Whether the Gilbert-Elliott channel is in its bad state
+    bad: bool,
+    queue: BinaryHeap<Datagram>,
+    deadline: timer::Deadline,
+}
+
+impl<S: AsyncUdpSocket> ImpairedSocket<S> {
+    /// Wraps `socket`, whose `capabilities` are used to send the datagrams which were held back
+    pub fn new(socket: S, capabilities: Arc<Capabilities>, seed: u64) -> Self {
+        Self {
+            socket,
+            capabilities,
+            state: Mutex::new(State {
+                rng: Rng::new(seed),
+                send: Direction::default(),
+                recv: Direction::default(),
+                next_seq: 0,
+                send_waker: None,
+                flush_waker: None,
+            }),
+        }
+    }
+
+    /// Sets the impairments of datagrams sent from now on
+    pub fn set_send_config(&self, config: ImpairmentConfig) {
+        self.state.lock().unwrap().send.config = config;
+    }
+
+    /// Sets the impairments of datagrams received from now on
+    pub fn set_recv_config(&self, config: ImpairmentConfig) {
+        self.state.lock().unwrap().recv.config = config;
+    }
+
+    pub fn send_config(&self) -> ImpairmentConfig {
+        self.state.lock().unwrap().send.config.clone()
+    }
+
+    pub fn recv_config(&self) -> ImpairmentConfig {
+        self.state.lock().unwrap().recv.config.clone()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.socket
+    }
+
+    /// Returns the wrapped socket, dropping any datagrams which are still held back
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+
+    pub async fn send(
+        &self,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_send(cx, capabilities, transmits)).await
+    }
+
+    pub async fn recv(
+        &self,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_recv(cx, bufs, meta)).await
+    }
+
+    /// Hands every datagram whose delay has passed to the wrapped socket
+    ///
+    /// Returns `Pending` if the wrapped socket cannot take all of them yet. Otherwise arranges
+    /// for the task to be woken when the next one is due.
+    fn flush(&self, cx: &mut Context<'_>, state: &mut State) -> Poll<io::Result<()>> {
+        loop {
+            let now = Instant::now();
+            let mut due = Vec::new();
+            while due.len() < crate::BATCH_SIZE
+                && state.send.queue.peek().is_some_and(|d| d.at <= now)
+            {
+                due.push(state.send.queue.pop().unwrap());
+            }
+            if due.is_empty() {
+                if let Some(next) = state.send.queue.peek().map(|d| d.at) {
+                    state.send.deadline.wake_at(next, cx.waker());
+                }
+                return Poll::Ready(Ok(()));
+            }
+
+            match self.poll_send_datagrams(cx, &self.capabilities, &due) {
+                Poll::Ready(Ok(n)) if n > 0 => state.send.queue.extend(due.drain(n..)),
+                Poll::Ready(Ok(_)) => {
+                    state.send.queue.extend(due);
+                    return Poll::Ready(Ok(()));
+                }
+                // The datagrams are lost, like those of a failed send
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => {
+                    state.send.queue.extend(due);
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+
+    /// Flushes on behalf of a receiving task
+    ///
+    /// The wrapped socket keeps a single waker per direction, so a task waiting to send is woken
+    /// along with the receiving one instead of being displaced by it.
+    fn flush_from_recv(&self, cx: &mut Context<'_>, state: &mut State) -> Poll<io::Result<()>> {
+        let Some(send) = state.send_waker.clone() else {
+            return self.flush(cx, state);
+        };
+        let waker = match &state.flush_waker {
+            Some((wakers, waker))
+                if wakers.recv.will_wake(cx.waker()) && wakers.send.will_wake(&send) =>
+            {
+                waker.clone()
+            }
+            _ => {
+                let wakers = Arc::new(Wakers {
+                    recv: cx.waker().clone(),
+                    send,
+                });
+                let waker = Waker::from(wakers.clone());
+                state.flush_waker = Some((wakers, waker.clone()));
+                waker
+            }
+        };
+        self.flush(&mut Context::from_waker(&waker), state)
+    }
+
+    /// Remembers the task polling to send while it waits
+    fn park_sender<T>(cx: &Context<'_>, state: &mut State, poll: Poll<T>) -> Poll<T> {
+        state.send_waker = match poll.is_pending() {
+            true => Some(cx.waker().clone()),
+            false => None,
+        };
+        poll
+    }
+
+    fn poll_send_datagrams(
+        &self,
+        cx: &mut Context<'_>,
+        capabilities: &Capabilities,
+        datagrams: &[Datagram],
+    ) -> Poll<io::Result<usize>> {
+        let transmits = datagrams
+            .iter()
+            .take(crate::BATCH_SIZE)
+            .map(|datagram| Transmit {
+                destination: Some(datagram.addr),
+                ecn: datagram.ecn,
+                contents: datagram.contents.clone(),
+                segment_size: None,
+                src_ip: datagram.local_ip,
+            })
+            .collect::<Vec<_>>();
+        self.socket.poll_send(cx, capabilities, &transmits)
+    }
+
+    fn poll_send_locked(
+        &self,
+        cx: &mut Context<'_>,
+        state: &mut State,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.flush(cx, state))?;
+        if state.send.queue.len() >= QUEUE_LEN {
+            // `flush` arranged a wake-up for when the next datagram is due
+            return Poll::Pending;
+        }
+
+        let destination = match transmits.iter().any(|t| t.destination.is_none()) {
+            true => Some(self.socket.peer_addr()?),
+            false => None,
+        };
+        let now = Instant::now();
+        let checkpoint = state.checkpoint();
+        let mut datagrams = Vec::new();
+        for transmit in transmits {
+            let addr = transmit.destination.or(destination).unwrap();
+            let segment_size = match transmit.segment_size {
+                Some(size) if size > 0 => size,
+                _ => transmit.contents.len().max(1),
+            };
+            for segment in transmit.contents.chunks(segment_size) {
+                datagrams.extend(state.impair(
+                    Dir::Send,
+                    addr,
+                    transmit.src_ip,
+                    transmit.ecn,
+                    segment,
+                    now,
+                ));
+            }
+        }
+
+        let (mut due, delayed): (Vec<_>, Vec<_>) = datagrams.into_iter().partition(|d| d.at <= now);
+        let mut sent = 0;
+        while sent < due.len() {
+            match self.poll_send_datagrams(cx, capabilities, &due[sent..]) {
+                Poll::Ready(Ok(n)) if n > 0 => sent += n,
+                Poll::Ready(Err(e)) if sent == 0 => {
+                    state.rollback(checkpoint);
+                    return Poll::Ready(Err(e));
+                }
+                Poll::Pending if sent == 0 => {
+                    state.rollback(checkpoint);
+                    return Poll::Pending;
+                }
+                // The rest is sent by the next `flush`
+                _ => break,
+            }
+        }
+
+        let room = QUEUE_LEN - state.send.queue.len();
+        let held_back = due.drain(sent..).chain(delayed).take(room);
+        state.send.queue.extend(held_back);
+        if let Some(next) = state.send.queue.peek().map(|d| d.at) {
+            state.send.deadline.wake_at(next, cx.waker());
+        }
+        Poll::Ready(Ok(transmits.len()))
+    }
+}
+
+/// Wakes both the receiving task which flushed and the task waiting to send
+struct Wakers {
+    recv: Waker,
+    send: Waker,
+}
+
+impl Wake for Wakers {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.recv.wake_by_ref();
+        self.send.wake_by_ref();
+    }
+}
+
+impl State {
+    /// Applies the impairments of `direction` to a datagram, returning the copies which survive
+    fn impair(
+        &mut self,
+        direction: Dir,
+        addr: SocketAddr,
+        local_ip: Option<IpAddr>,
+        ecn: Option<EcnCodepoint>,
+        contents: &[u8],
+        now: Instant,
+    ) -> Vec<Datagram> {
+        let rng = &mut self.rng;
+        let direction = match direction {
+            Dir::Send => &mut self.send,
+            Dir::Recv => &mut self.recv,
+        };
+        let config = &direction.config;
+        if let Some(model) = &config.burst_loss {
+            direction.bad = match direction.bad {
+                true => !rng.chance(model.r),
+                false => rng.chance(model.p),
+            };
+            let loss = match direction.bad {
+                true => model.loss_bad,
+                false => model.loss_good,
+            };
+            if rng.chance(loss) {
+                return Vec::new();
+            }
+        }
+        if rng.chance(config.loss) {
+            return Vec::new();
+        }
+
+        let ecn = match ecn {
+            _ if config.strip_ecn => None,
+            Some(EcnCodepoint::Ect0 | EcnCodepoint::Ect1) if rng.chance(config.ce_mark) => {
+                Some(EcnCodepoint::Ce)
+            }
+            ecn => ecn,
+        };
+        let len = match !contents.is_empty() && rng.chance(config.truncate) {
+            true => (rng.next_u64() % contents.len() as u64) as usize,
+            false => contents.len(),
+        };
+        let copies = match rng.chance(config.duplicate) {
+            true => 2,
+            false => 1,
+        };
+        (0..copies)
+            .map(|_| {
+                let mut delay = config.delay;
+                if !config.jitter.is_zero() {
+                    delay += config.jitter.mul_f64(rng.next_f64());
+                }
+                if rng.chance(config.reorder) {
+                    delay += config.reorder_delay;
+                }
+                self.next_seq += 1;
+                Datagram {
+                    at: now + delay,
+                    seq: self.next_seq,
+                    addr,
+                    local_ip,
+                    ecn,
+                    contents: contents[..len].to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    /// Captures the random state of the send direction, so that transmits which could not be
+    /// sent are impaired the same way when they are retried
+    fn checkpoint(&self) -> (Rng, bool, u64) {
+        (self.rng.clone(), self.send.bad, self.next_seq)
+    }
+
+    fn rollback(&mut self, (rng, bad, next_seq): (Rng, bool, u64)) {
+        self.rng = rng;
+        self.send.bad = bad;
+        self.next_seq = next_seq;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Dir {
+    Send,
+    Recv,
+}
+
+impl<S: AsyncUdpSocket> AsyncUdpSocket for ImpairedSocket<S> {
+    /// Impairs the transmits and sends the ones which are not delayed right away
+    ///
+    /// Transmits with a [`Transmit::segment_size`] are split into separate datagrams, each of
+    /// which is impaired on its own. Blocks while the wrapped socket is not writable, or once
+    /// too many datagrams are held back.
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        let poll = self.poll_send_locked(cx, &mut state, capabilities, transmits);
+        Self::park_sender(cx, &mut state, poll)
+    }
+
+    /// Impairs the datagrams received by the wrapped socket
+    ///
+    /// Coalesced datagrams are split up so that each is impaired on its own, and are returned
+    /// one per buffer.
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        if let Poll::Ready(Err(e)) = self.flush_from_recv(cx, &mut state) {
+            return Poll::Ready(Err(e));
+        }
+        loop {
+            let now = Instant::now();
+            let mut count = 0;
+            for (buf, meta) in bufs.iter_mut().zip(meta.iter_mut()) {
+                let datagram = match state.recv.queue.peek() {
+                    Some(datagram) if datagram.at <= now => state.recv.queue.pop().unwrap(),
+                    _ => break,
+                };
+                let len = datagram.contents.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram.contents[..len]);
+                *meta = RecvMeta {
+                    addr: datagram.addr,
+                    len,
+                    stride: len,
+                    ecn: datagram.ecn,
+                    dst_ip: datagram.local_ip,
+                };
+                count += 1;
+            }
+            if count > 0 {
+                return Poll::Ready(Ok(count));
+            }
+
+            let n = match self.socket.poll_recv(cx, bufs, meta) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => {
+                    if let Some(next) = state.recv.queue.peek().map(|d| d.at) {
+                        state.recv.deadline.wake_at(next, cx.waker());
+                    }
+                    return Poll::Pending;
+                }
+            };
+            for (buf, meta) in bufs.iter().zip(meta.iter()).take(n) {
+                let stride = match meta.stride {
+                    0 => meta.len.max(1),
+                    stride => stride,
+                };
+                for segment in buf[..meta.len].chunks(stride) {
+                    let datagrams =
+                        state.impair(Dir::Recv, meta.addr, meta.dst_ip, meta.ecn, segment, now);
+                    let room = QUEUE_LEN - state.recv.queue.len();
+                    state.recv.queue.extend(datagrams.into_iter().take(room));
+                }
+            }
+        }
+    }
+
+    /// Passes on due datagrams, then waits until the wrapped socket is writable and there is
+    /// room to hold back more datagrams
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock().unwrap();
+        let poll = match self.flush(cx, &mut state) {
+            Poll::Ready(Ok(())) if state.send.queue.len() >= QUEUE_LEN => Poll::Pending,
+            Poll::Ready(Ok(())) => self.socket.poll_writable(cx),
+            poll => poll,
+        };
+        Self::park_sender(cx, &mut state, poll)
+    }
+
+    /// Waits until a held back datagram is due or the wrapped socket is readable
+    ///
+    /// Datagrams received by the wrapped socket may all be dropped, in which case
+    /// [`try_recv`](AsyncUdpSocket::try_recv) fails with [`io::ErrorKind::WouldBlock`] after all.
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock().unwrap();
+        if let Poll::Ready(Err(e)) = self.flush_from_recv(cx, &mut state) {
+            return Poll::Ready(Err(e));
+        }
+        match state.recv.queue.peek().map(|d| d.at) {
+            Some(at) if at <= Instant::now() => return Poll::Ready(Ok(())),
+            Some(at) => state.recv.deadline.wake_at(at, cx.waker()),
+            None => {}
+        }
+        self.socket.poll_readable(cx)
+    }
+
+    fn try_send(&self, capabilities: &Capabilities, transmits: &[Transmit]) -> io::Result<usize> {
+        crate::runtime::try_poll(|cx| self.poll_send(cx, capabilities, transmits))
+    }
+
+    fn try_recv(&self, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> io::Result<usize> {
+        crate::runtime::try_poll(|cx| self.poll_recv(cx, bufs, meta))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for ImpairedSocket<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImpairedSocket")
+            .field("socket", &self.socket)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A held back datagram, ordered by the time it is due and then by the order it arrived in
+///
+/// `addr` is the destination of sent datagrams and the source of received ones, and
+/// `local_ip` is the source or destination IP on this side, if known.
+struct Datagram {
+    at: Instant,
+    seq: u64,
+    addr: SocketAddr,
+    local_ip: Option<IpAddr>,
+    ecn: Option<EcnCodepoint>,
+    contents: Vec<u8>,
+}
+
+// Ordered so that the next datagram to pass on is at the top of the max-heap
+impl Ord for Datagram {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+impl PartialOrd for Datagram {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Datagram {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Datagram {}
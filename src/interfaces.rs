@@ -0,0 +1,93 @@
+use std::{
+    ffi::CStr,
+    io,
+    net::{IpAddr, Ipv4Addr},
+    ptr,
+};
+
+/// An address of a local network interface, as listed by [`interface_addrs`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceAddr {
+    /// The name of the interface, e.g. `eth0`
+    pub name: String,
+    /// The index of the interface
+    pub index: u32,
+    /// The address assigned to the interface
+    pub addr: IpAddr,
+    /// The broadcast address of the subnet of [`Self::addr`], if the interface supports
+    /// broadcast
+    pub broadcast: Option<Ipv4Addr>,
+}
+
+/// Lists the addresses of the local network interfaces which are up
+pub fn interface_addrs() -> io::Result<Vec<InterfaceAddr>> {
+    let mut ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addrs = Vec::new();
+    let mut cursor = ifaddrs;
+    while let Some(ifaddr) = unsafe { cursor.as_ref() } {
+        cursor = ifaddr.ifa_next;
+        if ifaddr.ifa_flags & libc::IFF_UP as libc::c_uint == 0 {
+            continue;
+        }
+        let Some(addr) = (unsafe { decode_addr(ifaddr.ifa_addr) }) else {
+            continue;
+        };
+        let broadcast = match ifaddr.ifa_flags & libc::IFF_BROADCAST as libc::c_uint {
+            0 => None,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            _ => unsafe { decode_addr(ifaddr.ifa_ifu) },
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            _ => unsafe { decode_addr(ifaddr.ifa_dstaddr) },
+        };
+        let broadcast = match broadcast {
+            Some(IpAddr::V4(broadcast)) => Some(broadcast),
+            _ => None,
+        };
+        addrs.push(InterfaceAddr {
+            name: unsafe { CStr::from_ptr(ifaddr.ifa_name) }
+                .to_string_lossy()
+                .into_owned(),
+            index: unsafe { libc::if_nametoindex(ifaddr.ifa_name) },
+            addr,
+            broadcast,
+        });
+    }
+
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    Ok(addrs)
+}
+
+/// Whether `ip` is still assigned to a local interface, so a socket bound to it or a
+/// [`Transmit::src_ip`] taken from a received [`RecvMeta::dst_ip`](crate::RecvMeta::dst_ip) can
+/// still be used
+///
+/// Unspecified addresses are always valid, as the system picks the source address for them.
+/// IPv4-mapped IPv6 addresses are checked as IPv4 addresses.
+pub fn is_local_addr(ip: IpAddr) -> io::Result<bool> {
+    let ip = ip.to_canonical();
+    if ip.is_unspecified() {
+        return Ok(true);
+    }
+    Ok(interface_addrs()?
+        .iter()
+        .any(|interface| interface.addr == ip))
+}
+
+/// Decodes an IPv4 or IPv6 address, ignoring other families such as link layer addresses
+unsafe fn decode_addr(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    match addr.as_ref()?.sa_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = &*(addr as *const libc::sockaddr_in);
+            Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)).into())
+        }
+        libc::AF_INET6 => {
+            let addr = &*(addr as *const libc::sockaddr_in6);
+            Some(IpAddr::from(addr.sin6_addr.s6_addr))
+        }
+        _ => None,
+    }
+}
@@ -27,6 +27,12 @@ use std::{
 
 use tracing::warn;
 
+#[cfg(any(unix, windows))]
+pub mod blocking;
+#[cfg(unix)]
+mod broadcast;
+#[cfg(target_os = "linux")]
+mod busy_poll;
 #[cfg(unix)]
 mod cmsg;
 #[cfg(unix)]
@@ -42,14 +48,108 @@ mod imp;
 #[path = "fallback.rs"]
 mod imp;
 
+#[cfg(unix)]
+mod icmp;
+mod impair;
+#[cfg(unix)]
+mod interfaces;
+#[cfg(any(unix, windows))]
+mod multicast;
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+mod netlink;
+mod pcap;
 mod proto;
+mod replay;
+mod rng;
 mod runtime;
+#[cfg(feature = "futures")]
+mod stream;
+mod timer;
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+mod udplite;
+#[cfg(unix)]
+mod unix_datagram;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+mod virtual_net;
 
+#[cfg(target_os = "linux")]
+pub use busy_poll::BusyPollConfig;
+#[cfg(unix)]
+pub use icmp::{IcmpEcho, IcmpRecvMeta, IcmpTransmit};
 pub use imp::UdpSocketState;
+pub use impair::{GilbertElliott, ImpairedSocket, ImpairmentConfig};
+#[cfg(unix)]
+pub use interfaces::{interface_addrs, is_local_addr, InterfaceAddr};
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+pub use netlink::{AddressChange, NetlinkEvent, RouteChange};
+pub use pcap::{CaptureSocket, PacketDirection, PcapngWriter};
 pub use proto::{EcnCodepoint, Transmit};
-#[cfg(not(feature = "metal-io"))]
-pub use runtime::AsyncUdpSocket;
-pub use runtime::UdpSocket;
+pub use replay::{ReplayFilter, ReplaySocket, ReplayTiming};
+pub use runtime::split::{RecvHalf, ReuniteError, SendHalf};
+pub use runtime::{default_runtime, AsyncTimer, AsyncUdpSocket, Runtime};
+#[cfg(feature = "futures")]
+pub use stream::{UdpSink, UdpStream};
+#[cfg(unix)]
+pub use unix_datagram::{
+    UnixCredentials, UnixDatagramState, UnixRecvMeta, UnixSocketAddr, UnixTransmit,
+};
+pub use virtual_net::{VirtualLinkConfig, VirtualNetwork, VirtualUdpSocket};
+
+#[cfg(feature = "runtime-async-std")]
+pub use runtime::async_std::AsyncStdRuntime;
+#[cfg(feature = "runtime-smol")]
+pub use runtime::smol::SmolRuntime;
+#[cfg(feature = "runtime-tokio")]
+pub use runtime::tokio::TokioRuntime;
+
+#[cfg(feature = "runtime-async-std")]
+pub use runtime::async_std;
+#[cfg(feature = "metal-io")]
+pub use runtime::metal_io;
+#[cfg(feature = "runtime-smol")]
+pub use runtime::smol;
+#[cfg(feature = "runtime-tokio")]
+pub use runtime::tokio;
+
+// The sockets of the only enabled runtime are also available at the top level. With several
+// runtimes, which cargo feature unification easily leads to, they must be named by their module.
+#[cfg(all(
+    feature = "runtime-async-std",
+    not(any(
+        feature = "runtime-smol",
+        feature = "runtime-tokio",
+        feature = "metal-io"
+    ))
+))]
+pub use runtime::async_std::*;
+#[cfg(all(
+    feature = "metal-io",
+    not(any(
+        feature = "runtime-smol",
+        feature = "runtime-async-std",
+        feature = "runtime-tokio"
+    ))
+))]
+pub use runtime::metal_io::*;
+#[cfg(all(
+    feature = "runtime-smol",
+    not(any(
+        feature = "runtime-async-std",
+        feature = "runtime-tokio",
+        feature = "metal-io"
+    ))
+))]
+pub use runtime::smol::*;
+#[cfg(all(
+    feature = "runtime-tokio",
+    not(any(
+        feature = "runtime-smol",
+        feature = "runtime-async-std",
+        feature = "metal-io"
+    ))
+))]
+pub use runtime::tokio::*;
 
 /// Number of UDP packets to send/receive at a time
 pub const BATCH_SIZE: usize = imp::BATCH_SIZE;
@@ -59,6 +159,14 @@ pub const BATCH_SIZE: usize = imp::BATCH_SIZE;
 pub struct Capabilities {
     max_gso_segments: AtomicUsize,
     gro_segments: usize,
+    send_ecn: bool,
+    recv_ecn: bool,
+    recv_dst_ip: bool,
+    send_src_ip: bool,
+    timestamp_modes: TimestampModes,
+    send_batching: bool,
+    recv_batching: bool,
+    batch_size: usize,
 }
 
 impl Capabilities {
@@ -84,6 +192,59 @@ impl Capabilities {
     pub fn gro_segments(&self) -> usize {
         self.gro_segments
     }
+
+    /// Whether ECN bits set in [`Transmit::ecn`] are applied to outgoing datagrams
+    #[inline]
+    pub fn send_ecn(&self) -> bool {
+        self.send_ecn
+    }
+
+    /// Whether the ECN bits of incoming datagrams are reported in [`RecvMeta::ecn`]
+    #[inline]
+    pub fn recv_ecn(&self) -> bool {
+        self.recv_ecn
+    }
+
+    /// Whether the destination IP address of incoming datagrams is reported in
+    /// [`RecvMeta::dst_ip`]
+    #[inline]
+    pub fn recv_dst_ip(&self) -> bool {
+        self.recv_dst_ip
+    }
+
+    /// Whether [`Transmit::src_ip`] is honored when sending datagrams
+    ///
+    /// On FreeBSD this only holds for IPv4 sockets bound to an unspecified address.
+    #[inline]
+    pub fn send_src_ip(&self) -> bool {
+        self.send_src_ip
+    }
+
+    /// The kernel timestamping modes which can be enabled on a socket
+    #[inline]
+    pub fn timestamp_modes(&self) -> TimestampModes {
+        self.timestamp_modes
+    }
+
+    /// Whether multiple datagrams are sent with a single system call (e.g. `sendmmsg`)
+    #[inline]
+    pub fn send_batching(&self) -> bool {
+        self.send_batching
+    }
+
+    /// Whether multiple datagrams are received with a single system call (e.g. `recvmmsg`)
+    #[inline]
+    pub fn recv_batching(&self) -> bool {
+        self.recv_batching
+    }
+
+    /// The maximum number of datagrams sent or received with a single system call
+    ///
+    /// This is equal to [`BATCH_SIZE`].
+    #[inline]
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
 }
 
 impl Default for Capabilities {
@@ -92,6 +253,17 @@ impl Default for Capabilities {
     }
 }
 
+/// Kernel timestamping modes supported on a certain platform
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TimestampModes {
+    /// Software receive timestamps with microsecond resolution (`SO_TIMESTAMP`)
+    pub microseconds: bool,
+    /// Software receive timestamps with nanosecond resolution (`SO_TIMESTAMPNS`)
+    pub nanoseconds: bool,
+    /// Configurable software and hardware timestamps (`SO_TIMESTAMPING`)
+    pub timestamping: bool,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct RecvMeta {
     pub addr: SocketAddr,
@@ -115,6 +287,19 @@ impl Default for RecvMeta {
     }
 }
 
+/// Converts an IPv4 address to an IPv4-mapped IPv6 address, as used by dual-stack sockets
+fn to_mapped(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) => SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port()),
+        SocketAddr::V6(_) => addr,
+    }
+}
+
+/// Converts an IPv4-mapped IPv6 address back to a plain IPv4 address
+fn to_canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
 /// Log at most 1 IO error per minute
 const IO_ERROR_LOG_INTERVAL: Duration = std::time::Duration::from_secs(60);
 
@@ -140,6 +325,14 @@ fn log_sendmsg_error(
     }
 }
 
+/// The error for a [`Transmit`] without a destination on a socket which is not connected
+fn not_connected() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotConnected,
+        "no destination and the socket is not connected",
+    )
+}
+
 /// A borrowed UDP socket
 ///
 /// On Unix, constructible via `From<T: AsRawFd>`. On Windows, constructible via `From<T:
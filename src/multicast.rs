@@ -0,0 +1,244 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use super::UdpSockRef;
+
+/// Joins `group` on the interface with the address `interface`, receiving from any source
+///
+/// [`Ipv4Addr::UNSPECIFIED`] lets the system pick the interface.
+pub(crate) fn join_v4(
+    socket: UdpSockRef<'_>,
+    group: Ipv4Addr,
+    interface: Ipv4Addr,
+) -> io::Result<()> {
+    check_group(group.is_multicast())?;
+    #[cfg(target_os = "linux")]
+    only_joined_groups(&socket);
+    socket.0.join_multicast_v4(&group, &interface)
+}
+
+pub(crate) fn leave_v4(
+    socket: UdpSockRef<'_>,
+    group: Ipv4Addr,
+    interface: Ipv4Addr,
+) -> io::Result<()> {
+    check_group(group.is_multicast())?;
+    socket.0.leave_multicast_v4(&group, &interface)
+}
+
+/// Joins `group` on the interface with the index `interface`, receiving from any source
+///
+/// `0` lets the system pick the interface.
+pub(crate) fn join_v6(socket: UdpSockRef<'_>, group: &Ipv6Addr, interface: u32) -> io::Result<()> {
+    check_group(group.is_multicast())?;
+    #[cfg(target_os = "linux")]
+    only_joined_groups(&socket);
+    socket.0.join_multicast_v6(group, interface)
+}
+
+pub(crate) fn leave_v6(socket: UdpSockRef<'_>, group: &Ipv6Addr, interface: u32) -> io::Result<()> {
+    check_group(group.is_multicast())?;
+    socket.0.leave_multicast_v6(group, interface)
+}
+
+/// Joins `group` on the interface with the address `interface`, receiving only from `source`
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+pub(crate) fn join_ssm_v4(
+    socket: UdpSockRef<'_>,
+    source: Ipv4Addr,
+    group: Ipv4Addr,
+    interface: Ipv4Addr,
+) -> io::Result<()> {
+    check_group(group.is_multicast())?;
+    #[cfg(target_os = "linux")]
+    only_joined_groups(&socket);
+    socket.0.join_ssm_v4(&source, &group, &interface)
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+pub(crate) fn leave_ssm_v4(
+    socket: UdpSockRef<'_>,
+    source: Ipv4Addr,
+    group: Ipv4Addr,
+    interface: Ipv4Addr,
+) -> io::Result<()> {
+    check_group(group.is_multicast())?;
+    socket.0.leave_ssm_v4(&source, &group, &interface)
+}
+
+/// Joins `group` on the interface with the index `interface`, receiving only from `source`
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+pub(crate) fn join_ssm_v6(
+    socket: UdpSockRef<'_>,
+    source: &Ipv6Addr,
+    group: &Ipv6Addr,
+    interface: u32,
+) -> io::Result<()> {
+    check_group(group.is_multicast())?;
+    #[cfg(target_os = "linux")]
+    only_joined_groups(&socket);
+    source_membership_v6(
+        socket,
+        libc::MCAST_JOIN_SOURCE_GROUP,
+        source,
+        group,
+        interface,
+    )
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+pub(crate) fn leave_ssm_v6(
+    socket: UdpSockRef<'_>,
+    source: &Ipv6Addr,
+    group: &Ipv6Addr,
+    interface: u32,
+) -> io::Result<()> {
+    check_group(group.is_multicast())?;
+    source_membership_v6(
+        socket,
+        libc::MCAST_LEAVE_SOURCE_GROUP,
+        source,
+        group,
+        interface,
+    )
+}
+
+/// Sets the interface multicast datagrams are sent from, by its address
+pub(crate) fn set_if_v4(socket: UdpSockRef<'_>, interface: Ipv4Addr) -> io::Result<()> {
+    socket.0.set_multicast_if_v4(&interface)
+}
+
+/// Sets the interface multicast datagrams are sent from, by its index
+pub(crate) fn set_if_v6(socket: UdpSockRef<'_>, interface: u32) -> io::Result<()> {
+    socket.0.set_multicast_if_v6(interface)
+}
+
+/// Sets whether multicast datagrams sent by this host are looped back to its own members
+pub(crate) fn set_loop_v4(socket: UdpSockRef<'_>, enabled: bool) -> io::Result<()> {
+    socket.0.set_multicast_loop_v4(enabled)
+}
+
+pub(crate) fn set_loop_v6(socket: UdpSockRef<'_>, enabled: bool) -> io::Result<()> {
+    socket.0.set_multicast_loop_v6(enabled)
+}
+
+/// Sets the time to live of sent multicast datagrams, `1` keeping them on the local network
+pub(crate) fn set_ttl_v4(socket: UdpSockRef<'_>, ttl: u32) -> io::Result<()> {
+    socket.0.set_multicast_ttl_v4(ttl)
+}
+
+pub(crate) fn set_hops_v6(socket: UdpSockRef<'_>, hops: u32) -> io::Result<()> {
+    socket.0.set_multicast_hops_v6(hops)
+}
+
+/// Limits the groups a socket receives to those joined on it, before it joins its first one
+///
+/// Linux delivers datagrams of every group any socket on the host joined to all sockets bound
+/// to the wildcard address and the group's port. Other platforms only deliver the groups joined
+/// on the socket itself. Sockets which never join a group keep the Linux behavior. Set even for
+/// IPv6 sockets to account for IPv4-mapped addresses, and ignored where unsupported.
+#[cfg(target_os = "linux")]
+fn only_joined_groups(socket: &UdpSockRef<'_>) {
+    let _ = crate::imp::set_socket_option(
+        &*socket.0,
+        libc::IPPROTO_IP,
+        libc::IP_MULTICAST_ALL,
+        crate::imp::OPTION_OFF,
+    );
+    let _ = crate::imp::set_socket_option(
+        &*socket.0,
+        libc::IPPROTO_IPV6,
+        IPV6_MULTICAST_ALL,
+        crate::imp::OPTION_OFF,
+    );
+}
+
+// Not exported by `libc` for all Linux targets
+#[cfg(target_os = "linux")]
+const IPV6_MULTICAST_ALL: libc::c_int = 29;
+
+fn check_group(is_multicast: bool) -> io::Result<()> {
+    match is_multicast {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a multicast group address",
+        )),
+    }
+}
+
+// The protocol independent `MCAST_*` options, as `socket2` only offers source-specific
+// membership for IPv4
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+fn source_membership_v6(
+    socket: UdpSockRef<'_>,
+    name: libc::c_int,
+    source: &Ipv6Addr,
+    group: &Ipv6Addr,
+    interface: u32,
+) -> io::Result<()> {
+    use std::{mem, net::SocketAddrV6, os::unix::io::AsRawFd, ptr};
+
+    let mut req = unsafe { mem::zeroed::<libc::group_source_req>() };
+    req.gsr_interface = interface;
+    let group = socket2::SockAddr::from(SocketAddrV6::new(*group, 0, 0, 0));
+    let source = socket2::SockAddr::from(SocketAddrV6::new(*source, 0, 0, 0));
+    // The struct is packed on some platforms, so its fields are written through raw pointers
+    unsafe {
+        ptr::copy_nonoverlapping(
+            group.as_ptr() as *const u8,
+            ptr::addr_of_mut!(req.gsr_group) as *mut u8,
+            group.len() as usize,
+        );
+        ptr::copy_nonoverlapping(
+            source.as_ptr() as *const u8,
+            ptr::addr_of_mut!(req.gsr_source) as *mut u8,
+            source.len() as usize,
+        );
+    }
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.0.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            name,
+            &req as *const _ as _,
+            mem::size_of_val(&req) as _,
+        )
+    };
+    match rc == 0 {
+        true => Ok(()),
+        false => Err(io::Error::last_os_error()),
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::VecDeque,
+    io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::{AsRawFd, BorrowedFd},
+};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+const NLMSG_HEADER_LEN: usize = 16;
+const IFADDRMSG_LEN: usize = 8;
+const RTMSG_LEN: usize = 12;
+const RTATTR_HEADER_LEN: usize = 4;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_TABLE: u16 = 15;
+
+/// Large enough for the messages the kernel batches into one datagram
+const BUFFER_SIZE: usize = 32 * 1024;
+
+/// A change of the local addresses or routes, as reported by the kernel through `NETLINK_ROUTE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetlinkEvent {
+    AddressAdded(AddressChange),
+    AddressRemoved(AddressChange),
+    RouteAdded(RouteChange),
+    RouteRemoved(RouteChange),
+}
+
+/// An address assigned to or removed from an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressChange {
+    /// The index of the interface
+    pub index: u32,
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+}
+
+/// A route added to or removed from a routing table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteChange {
+    /// The destination network, which is unspecified for default routes
+    pub destination: IpAddr,
+    pub prefix_len: u8,
+    pub gateway: Option<IpAddr>,
+    /// The index of the outgoing interface
+    pub index: Option<u32>,
+    /// The routing table, e.g. `RT_TABLE_MAIN` (254) or `RT_TABLE_LOCAL` (255)
+    pub table: u32,
+}
+
+impl NetlinkEvent {
+    /// Decodes the address and route messages of a netlink datagram, skipping all others
+    ///
+    /// Truncated messages and attributes end decoding, the events decoded until then are
+    /// returned.
+    pub fn decode(mut datagram: &[u8]) -> Vec<Self> {
+        let mut events = Vec::new();
+        while datagram.len() >= NLMSG_HEADER_LEN {
+            let len = u32_at(datagram, 0) as usize;
+            if len < NLMSG_HEADER_LEN || len > datagram.len() {
+                break;
+            }
+            let payload = &datagram[NLMSG_HEADER_LEN..len];
+            let event = match u16_at(datagram, 4) {
+                libc::RTM_NEWADDR => decode_addr(payload).map(Self::AddressAdded),
+                libc::RTM_DELADDR => decode_addr(payload).map(Self::AddressRemoved),
+                libc::RTM_NEWROUTE => decode_route(payload).map(Self::RouteAdded),
+                libc::RTM_DELROUTE => decode_route(payload).map(Self::RouteRemoved),
+                _ => None,
+            };
+            events.extend(event);
+            datagram = &datagram[align(len).min(datagram.len())..];
+        }
+        events
+    }
+}
+
+/// Creates a non-blocking netlink socket subscribed to IPv4 and IPv6 address and route changes
+pub(crate) fn bind() -> io::Result<Socket> {
+    let socket = Socket::new(
+        Domain::from(libc::AF_NETLINK),
+        // `Type::RAW` requires the `all` feature of `socket2`
+        Type::from(libc::SOCK_RAW | libc::SOCK_NONBLOCK),
+        Some(Protocol::from(libc::NETLINK_ROUTE)),
+    )?;
+    let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as _;
+    addr.nl_groups = (libc::RTMGRP_IPV4_IFADDR
+        | libc::RTMGRP_IPV6_IFADDR
+        | libc::RTMGRP_IPV4_ROUTE
+        | libc::RTMGRP_IPV6_ROUTE) as u32;
+    let addr = unsafe {
+        let mut storage: libc::sockaddr_storage = mem::zeroed();
+        (&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_nl).write(addr);
+        SockAddr::new(storage, mem::size_of::<libc::sockaddr_nl>() as _)
+    };
+    socket.bind(&addr)?;
+    Ok(socket)
+}
+
+/// The events received by a `NetlinkMonitor` which were not handed out yet
+///
+/// Shared by the monitors of all runtimes, which only differ in how they wait for the socket.
+#[derive(Debug)]
+pub(crate) struct Events {
+    buf: Vec<u8>,
+    pending: VecDeque<NetlinkEvent>,
+}
+
+impl Events {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: vec![0; BUFFER_SIZE],
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<NetlinkEvent> {
+        self.pending.pop_front()
+    }
+
+    /// Receives a single datagram from `io` and queues the events it holds
+    ///
+    /// Fails with `ENOBUFS` when the kernel dropped events because they were not received fast
+    /// enough. Events are lost then, so the current addresses and routes should be looked up
+    /// again, the addresses e.g. with [`interface_addrs`](crate::interface_addrs).
+    pub(crate) fn recv(&mut self, io: BorrowedFd<'_>) -> io::Result<()> {
+        loop {
+            let n = unsafe {
+                libc::recv(
+                    io.as_raw_fd(),
+                    self.buf.as_mut_ptr() as _,
+                    self.buf.len(),
+                    0,
+                )
+            };
+            if n == -1 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            self.pending
+                .extend(NetlinkEvent::decode(&self.buf[..n as usize]));
+            return Ok(());
+        }
+    }
+}
+
+fn decode_addr(payload: &[u8]) -> Option<AddressChange> {
+    if payload.len() < IFADDRMSG_LEN {
+        return None;
+    }
+    let family = payload[0];
+    let (mut address, mut local) = (None, None);
+    for (ty, value) in attributes(&payload[IFADDRMSG_LEN..]) {
+        match ty {
+            IFA_ADDRESS => address = decode_ip(family, value),
+            IFA_LOCAL => local = decode_ip(family, value),
+            _ => {}
+        }
+    }
+    Some(AddressChange {
+        index: u32_at(payload, 4),
+        // IFA_ADDRESS holds the peer of point-to-point links, IFA_LOCAL the local address
+        addr: local.or(address)?,
+        prefix_len: payload[1],
+    })
+}
+
+fn decode_route(payload: &[u8]) -> Option<RouteChange> {
+    if payload.len() < RTMSG_LEN {
+        return None;
+    }
+    let family = payload[0];
+    let mut destination = match family as libc::c_int {
+        libc::AF_INET => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        libc::AF_INET6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        _ => return None,
+    };
+    let (mut gateway, mut index) = (None, None);
+    let mut table = u32::from(payload[4]);
+    for (ty, value) in attributes(&payload[RTMSG_LEN..]) {
+        match ty {
+            RTA_DST => destination = decode_ip(family, value)?,
+            RTA_GATEWAY => gateway = decode_ip(family, value),
+            RTA_OIF if value.len() >= 4 => index = Some(u32_at(value, 0)),
+            RTA_TABLE if value.len() >= 4 => table = u32_at(value, 0),
+            _ => {}
+        }
+    }
+    Some(RouteChange {
+        destination,
+        prefix_len: payload[1],
+        gateway,
+        index,
+        table,
+    })
+}
+
+/// Iterates over the type and value of the route attributes in `buf`
+fn attributes(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if buf.len() < RTATTR_HEADER_LEN {
+            return None;
+        }
+        let len = u16_at(buf, 0) as usize;
+        if len < RTATTR_HEADER_LEN || len > buf.len() {
+            return None;
+        }
+        let attribute = (u16_at(buf, 2), &buf[RTATTR_HEADER_LEN..len]);
+        buf = &buf[align(len).min(buf.len())..];
+        Some(attribute)
+    })
+}
+
+fn decode_ip(family: u8, value: &[u8]) -> Option<IpAddr> {
+    match family as libc::c_int {
+        libc::AF_INET => Some(IpAddr::from(<[u8; 4]>::try_from(value).ok()?)),
+        libc::AF_INET6 => Some(IpAddr::from(<[u8; 16]>::try_from(value).ok()?)),
+        _ => None,
+    }
+}
+
+/// Rounds `len` up to the 4 byte alignment of netlink messages and attributes
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
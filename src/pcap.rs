@@ -0,0 +1,764 @@
+use crate::runtime::AsyncUdpSocket;
+use crate::{Capabilities, EcnCodepoint, RecvMeta, Transmit};
+use std::{
+    fmt,
+    future::poll_fn,
+    io::{self, IoSliceMut, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, warn};
+
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+/// Raw IPv4 or IPv6 packets without a link-layer header
+const LINKTYPE_RAW: u16 = 101;
+
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_EPB_FLAGS: u16 = 2;
+
+const IPPROTO_UDP: u8 = 17;
+/// Hop limit written into synthesized packets, as the real one is not known when sending
+const DEFAULT_TTL: u8 = 64;
+
+/// Whether a captured packet was received or sent
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PacketDirection {
+    Inbound,
+    Outbound,
+}
+
+/// Writes UDP datagrams as raw IP packets into a pcapng capture
+///
+/// The capture consists of a single section with a single interface of link type
+/// `LINKTYPE_RAW` and nanosecond timestamps, so that it can be read by Wireshark and tcpdump.
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header and interface description to `writer`
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let mut shb = Vec::with_capacity(16);
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_ne_bytes());
+        shb.extend_from_slice(&1u16.to_ne_bytes());
+        shb.extend_from_slice(&0u16.to_ne_bytes());
+        // The section length is not known in advance
+        shb.extend_from_slice(&(-1i64).to_ne_bytes());
+        write_block(&mut writer, SECTION_HEADER_BLOCK, &shb)?;
+
+        let mut idb = Vec::with_capacity(20);
+        idb.extend_from_slice(&LINKTYPE_RAW.to_ne_bytes());
+        idb.extend_from_slice(&0u16.to_ne_bytes());
+        // No limit on the captured length
+        idb.extend_from_slice(&0u32.to_ne_bytes());
+        push_option(&mut idb, OPT_IF_TSRESOL, &[9]);
+        push_option(&mut idb, OPT_ENDOFOPT, &[]);
+        write_block(&mut writer, INTERFACE_DESCRIPTION_BLOCK, &idb)?;
+        Ok(Self { writer })
+    }
+
+    /// Writes a UDP datagram from `src` to `dst` as a synthesized IP packet
+    ///
+    /// The ECN codepoint is written into the TOS or traffic class field. The UDP checksum is
+    /// filled in, so that the packet passes validation. Payloads which do not fit the length
+    /// fields of the headers are skipped.
+    pub fn write_datagram(
+        &mut self,
+        timestamp: SystemTime,
+        direction: PacketDirection,
+        src: SocketAddr,
+        dst: SocketAddr,
+        ecn: Option<EcnCodepoint>,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        match ip_packet(src, dst, ecn, payload) {
+            Some(packet) => self.write_packet(timestamp, direction, &packet),
+            None => {
+                debug!(
+                    "datagram of {} bytes is too large for a UDP packet, it is not captured",
+                    payload.len()
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes a raw IP packet
+    pub fn write_packet(
+        &mut self,
+        timestamp: SystemTime,
+        direction: PacketDirection,
+        packet: &[u8],
+    ) -> io::Result<()> {
+        let nanos = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let len = u32::try_from(packet.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "packet is too large"))?;
+
+        let mut epb = Vec::with_capacity(32 + packet.len());
+        // Interface ID
+        epb.extend_from_slice(&0u32.to_ne_bytes());
+        epb.extend_from_slice(&((nanos >> 32) as u32).to_ne_bytes());
+        epb.extend_from_slice(&(nanos as u32).to_ne_bytes());
+        // Captured and original length
+        epb.extend_from_slice(&len.to_ne_bytes());
+        epb.extend_from_slice(&len.to_ne_bytes());
+        epb.extend_from_slice(packet);
+        pad(&mut epb);
+        let flags: u32 = match direction {
+            PacketDirection::Inbound => 0b01,
+            PacketDirection::Outbound => 0b10,
+        };
+        push_option(&mut epb, OPT_EPB_FLAGS, &flags.to_ne_bytes());
+        push_option(&mut epb, OPT_ENDOFOPT, &[]);
+        write_block(&mut self.writer, ENHANCED_PACKET_BLOCK, &epb)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> fmt::Debug for PcapngWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PcapngWriter").finish_non_exhaustive()
+    }
+}
+
+/// Writes a block with the given body, which must be padded to 32 bits
+fn write_block(writer: &mut impl Write, ty: u32, body: &[u8]) -> io::Result<()> {
+    let len = (12 + body.len()) as u32;
+    writer.write_all(&ty.to_ne_bytes())?;
+    writer.write_all(&len.to_ne_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&len.to_ne_bytes())
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_ne_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_ne_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+/// Builds an IPv4 or IPv6 packet carrying a UDP datagram
+///
+/// IPv4-mapped addresses are unmapped, and if only one side is IPv6 the other one is mapped,
+/// as a dual-stack socket would. Returns `None` if the payload overflows a length field.
+fn ip_packet(
+    src: SocketAddr,
+    dst: SocketAddr,
+    ecn: Option<EcnCodepoint>,
+    payload: &[u8],
+) -> Option<Vec<u8>> {
+    let ecn = ecn.map_or(0, |ecn| ecn as u8);
+    let udp_len = u16::try_from(8 + payload.len()).ok()?;
+    let mut packet = Vec::with_capacity(40 + usize::from(udp_len));
+    let pseudo_header = match (src.ip().to_canonical(), dst.ip().to_canonical()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            let total_len = 20u16.checked_add(udp_len)?;
+            packet.extend_from_slice(&[0x45, ecn]);
+            packet.extend_from_slice(&total_len.to_be_bytes());
+            // Identification, and don't fragment
+            packet.extend_from_slice(&[0, 0, 0x40, 0]);
+            packet.extend_from_slice(&[DEFAULT_TTL, IPPROTO_UDP, 0, 0]);
+            packet.extend_from_slice(&src_ip.octets());
+            packet.extend_from_slice(&dst_ip.octets());
+            let header_checksum = !fold(sum(0, &packet));
+            packet[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+            sum(sum(0, &packet[12..20]), &[0, IPPROTO_UDP])
+        }
+        (src_ip, dst_ip) => {
+            let src_ip = to_ipv6(src_ip);
+            let dst_ip = to_ipv6(dst_ip);
+            let first_word = 6u32 << 28 | u32::from(ecn) << 20;
+            packet.extend_from_slice(&first_word.to_be_bytes());
+            packet.extend_from_slice(&udp_len.to_be_bytes());
+            packet.extend_from_slice(&[IPPROTO_UDP, DEFAULT_TTL]);
+            packet.extend_from_slice(&src_ip.octets());
+            packet.extend_from_slice(&dst_ip.octets());
+            sum(sum(0, &packet[8..40]), &[0, IPPROTO_UDP])
+        }
+    };
+
+    let udp = packet.len();
+    packet.extend_from_slice(&src.port().to_be_bytes());
+    packet.extend_from_slice(&dst.port().to_be_bytes());
+    packet.extend_from_slice(&udp_len.to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(payload);
+    let sum = sum(pseudo_header + u32::from(udp_len), &packet[udp..]);
+    // A zero checksum means that none was computed, so it is sent as all ones instead
+    let checksum = match !fold(sum) {
+        0 => 0xffff,
+        checksum => checksum,
+    };
+    packet[udp + 6..udp + 8].copy_from_slice(&checksum.to_be_bytes());
+    Some(packet)
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) if ip == Ipv4Addr::UNSPECIFIED => Ipv6Addr::UNSPECIFIED,
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Adds `data` to a running one's complement sum, as used by the internet checksum
+fn sum(mut sum: u32, data: &[u8]) -> u32 {
+    for chunk in data.chunks(2) {
+        let word = match *chunk {
+            [a, b] => u16::from_be_bytes([a, b]),
+            [a] => u16::from_be_bytes([a, 0]),
+            _ => unreachable!(),
+        };
+        sum += u32::from(word);
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// Wraps a socket and records every datagram it sends and receives into a pcapng capture
+///
+/// Each datagram is written as a synthesized IP/UDP packet carrying its real ECN codepoint,
+/// addresses and ports, timestamped when it was handed to or returned from the wrapped socket.
+/// GSO and GRO buffers are expanded into one packet per segment. Where the local IP address is
+/// not known from [`Transmit::src_ip`] or [`RecvMeta::dst_ip`], the address the socket is bound
+/// to is used.
+///
+/// If writing the capture fails, a warning is logged and capturing stops, while the socket
+/// keeps working. Datagrams whose addresses cannot be looked up are left out of the capture.
+pub struct CaptureSocket<S> {
+    socket: S,
+    capture: Mutex<Option<PcapngWriter<Box<dyn Write + Send>>>>,
+}
+
+impl<S: AsyncUdpSocket> CaptureSocket<S> {
+    pub fn new(socket: S, writer: impl Write + Send + 'static) -> io::Result<Self> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        Ok(Self {
+            socket,
+            capture: Mutex::new(Some(PcapngWriter::new(writer)?)),
+        })
+    }
+
+    /// Flushes the underlying writer of the capture
+    pub fn flush(&self) -> io::Result<()> {
+        match &mut *self.capture.lock().unwrap() {
+            Some(capture) => capture.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.socket
+    }
+
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+
+    pub async fn send(
+        &self,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_send(cx, capabilities, transmits)).await
+    }
+
+    pub async fn recv(
+        &self,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_recv(cx, bufs, meta)).await
+    }
+
+    /// Records the datagrams of `transmits`, which the wrapped socket has sent
+    fn capture_sent(&self, transmits: &[Transmit]) {
+        let local_addr = match self.socket.local_addr() {
+            Ok(addr) => addr,
+            Err(e) => {
+                debug!(
+                    "failed to look up the local address, sent datagrams are not captured: {}",
+                    e
+                );
+                return;
+            }
+        };
+        for transmit in transmits {
+            let dst = match transmit
+                .destination
+                .map_or_else(|| self.socket.peer_addr(), Ok)
+            {
+                Ok(dst) => dst,
+                Err(e) => {
+                    debug!(
+                        "failed to look up the peer address, datagram is not captured: {}",
+                        e
+                    );
+                    continue;
+                }
+            };
+            let src = SocketAddr::new(
+                transmit.src_ip.unwrap_or(local_addr.ip()),
+                local_addr.port(),
+            );
+            let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+            self.capture(
+                PacketDirection::Outbound,
+                src,
+                dst,
+                transmit.ecn,
+                &transmit.contents,
+                segment_size,
+            );
+        }
+    }
+
+    /// Records the datagrams described by `meta`, which the wrapped socket has received
+    fn capture_received(&self, bufs: &[IoSliceMut<'_>], meta: &[RecvMeta]) {
+        let local_addr = match self.socket.local_addr() {
+            Ok(addr) => addr,
+            Err(e) => {
+                debug!(
+                    "failed to look up the local address, received datagrams are not captured: {}",
+                    e
+                );
+                return;
+            }
+        };
+        for (buf, meta) in bufs.iter().zip(meta) {
+            let dst = SocketAddr::new(meta.dst_ip.unwrap_or(local_addr.ip()), local_addr.port());
+            self.capture(
+                PacketDirection::Inbound,
+                meta.addr,
+                dst,
+                meta.ecn,
+                &buf[..meta.len],
+                meta.stride,
+            );
+        }
+    }
+
+    /// Writes the segments of a datagram, giving up on the capture after the first error
+    fn capture(
+        &self,
+        direction: PacketDirection,
+        src: SocketAddr,
+        dst: SocketAddr,
+        ecn: Option<EcnCodepoint>,
+        contents: &[u8],
+        segment_size: usize,
+    ) {
+        let mut capture = self.capture.lock().unwrap();
+        let writer = match &mut *capture {
+            Some(writer) => writer,
+            None => return,
+        };
+        let timestamp = SystemTime::now();
+        let segments = match segment_size {
+            size if size == 0 || contents.is_empty() => vec![contents],
+            size => contents.chunks(size).collect(),
+        };
+        for segment in segments {
+            if let Err(e) = writer.write_datagram(timestamp, direction, src, dst, ecn, segment) {
+                warn!("failed to write packet capture, capturing stops: {}", e);
+                *capture = None;
+                return;
+            }
+        }
+    }
+}
+
+impl<S: AsyncUdpSocket> AsyncUdpSocket for CaptureSocket<S> {
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        let n = ready!(self.socket.poll_send(cx, capabilities, transmits))?;
+        self.capture_sent(&transmits[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let n = ready!(self.socket.poll_recv(cx, bufs, meta))?;
+        self.capture_received(bufs, &meta[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_writable(cx)
+    }
+
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_readable(cx)
+    }
+
+    fn try_send(&self, capabilities: &Capabilities, transmits: &[Transmit]) -> io::Result<usize> {
+        let n = self.socket.try_send(capabilities, transmits)?;
+        self.capture_sent(&transmits[..n]);
+        Ok(n)
+    }
+
+    fn try_recv(&self, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> io::Result<usize> {
+        let n = self.socket.try_recv(bufs, meta)?;
+        self.capture_received(bufs, &meta[..n]);
+        Ok(n)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for CaptureSocket<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CaptureSocket")
+            .field("socket", &self.socket)
+            .finish_non_exhaustive()
+    }
+}
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const SIMPLE_PACKET_BLOCK: u32 = 3;
+const OPT_IF_TSOFFSET: u16 = 14;
+
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_LOOP: u16 = 108;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+/// A UDP datagram read from a capture
+#[derive(Debug, Clone)]
+pub(crate) struct CapturedDatagram {
+    /// Time since the Unix epoch
+    pub(crate) timestamp: Duration,
+    pub(crate) src: SocketAddr,
+    pub(crate) dst: SocketAddr,
+    pub(crate) ecn: Option<EcnCodepoint>,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// Reads every UDP datagram from a pcap or pcapng capture, in the order they were captured
+///
+/// Packets which are not unfragmented UDP over IPv4 or IPv6, or whose link type is not
+/// understood, are skipped.
+pub(crate) fn read_datagrams(mut reader: impl Read) -> io::Result<Vec<CapturedDatagram>> {
+    let mut capture = Vec::new();
+    reader.read_to_end(&mut capture)?;
+    let magic = capture
+        .get(..4)
+        .ok_or_else(|| invalid_data("capture is too short"))?;
+    match u32::from_le_bytes(magic.try_into().unwrap()) {
+        SECTION_HEADER_BLOCK => read_pcapng(&capture),
+        _ => read_pcap(&capture),
+    }
+}
+
+fn read_pcap(capture: &[u8]) -> io::Result<Vec<CapturedDatagram>> {
+    let magic = u32::from_le_bytes(capture[..4].try_into().unwrap());
+    let (big_endian, nanos) = match magic {
+        PCAP_MAGIC_MICROS => (false, false),
+        PCAP_MAGIC_NANOS => (false, true),
+        _ if magic.swap_bytes() == PCAP_MAGIC_MICROS => (true, false),
+        _ if magic.swap_bytes() == PCAP_MAGIC_NANOS => (true, true),
+        _ => return Err(invalid_data("not a pcap or pcapng capture")),
+    };
+    let bytes = Bytes {
+        data: capture,
+        big_endian,
+    };
+    // The upper bits of the link type field carry FCS information
+    let linktype = bytes.u32(20)? as u16;
+
+    let mut datagrams = Vec::new();
+    let mut offset = 24;
+    while offset < capture.len() {
+        let seconds = u64::from(bytes.u32(offset)?);
+        let fraction = u64::from(bytes.u32(offset + 4)?);
+        let captured = bytes.u32(offset + 8)? as usize;
+        let data = bytes.slice(offset + 16, captured)?;
+        let timestamp = match nanos {
+            true => Duration::new(seconds, fraction as u32),
+            false => Duration::from_secs(seconds) + Duration::from_micros(fraction),
+        };
+        datagrams.extend(decode_link(linktype, data, timestamp));
+        offset += 16 + captured;
+    }
+    Ok(datagrams)
+}
+
+/// The link type and timestamp resolution of a pcapng interface
+struct Interface {
+    linktype: u16,
+    /// Ticks per second
+    resolution: u64,
+    offset: u64,
+}
+
+fn read_pcapng(capture: &[u8]) -> io::Result<Vec<CapturedDatagram>> {
+    let mut bytes = Bytes {
+        data: capture,
+        big_endian: false,
+    };
+    let mut interfaces = Vec::new();
+    let mut datagrams = Vec::new();
+    let mut offset = 0;
+    while offset < capture.len() {
+        if bytes.u32(offset).ok() == Some(SECTION_HEADER_BLOCK) {
+            // Every section has its own byte order and interfaces
+            let magic = bytes.slice(offset + 8, 4)?;
+            bytes.big_endian = match u32::from_le_bytes(magic.try_into().unwrap()) {
+                BYTE_ORDER_MAGIC => false,
+                magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                _ => return Err(invalid_data("invalid pcapng byte order magic")),
+            };
+            interfaces.clear();
+        }
+        let ty = bytes.u32(offset)?;
+        let len = bytes.u32(offset + 4)? as usize;
+        if len < 12 || len.next_multiple_of(4) != len {
+            return Err(invalid_data("invalid pcapng block length"));
+        }
+        let body = bytes.slice(offset + 8, len - 12)?;
+        let body_bytes = Bytes {
+            data: body,
+            big_endian: bytes.big_endian,
+        };
+        match ty {
+            INTERFACE_DESCRIPTION_BLOCK => interfaces.push(read_interface(&body_bytes)?),
+            ENHANCED_PACKET_BLOCK => {
+                let interface = interfaces
+                    .get(body_bytes.u32(0)? as usize)
+                    .ok_or_else(|| invalid_data("packet of an undescribed interface"))?;
+                let ticks = u64::from(body_bytes.u32(4)?) << 32 | u64::from(body_bytes.u32(8)?);
+                let captured = body_bytes.u32(12)? as usize;
+                let data = body_bytes.slice(20, captured)?;
+                let timestamp = ticks_to_duration(ticks, interface.resolution)
+                    .checked_add(Duration::from_secs(interface.offset))
+                    .ok_or_else(|| invalid_data("pcapng timestamp out of range"))?;
+                datagrams.extend(decode_link(interface.linktype, data, timestamp));
+            }
+            SIMPLE_PACKET_BLOCK => {
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| invalid_data("packet of an undescribed interface"))?;
+                let original = body_bytes.u32(0)? as usize;
+                let data = &body[4..][..original.min(body.len() - 4)];
+                // Simple packet blocks carry no timestamp
+                datagrams.extend(decode_link(interface.linktype, data, Duration::ZERO));
+            }
+            _ => {}
+        }
+        offset += len;
+    }
+    Ok(datagrams)
+}
+
+fn read_interface(body: &Bytes<'_>) -> io::Result<Interface> {
+    let mut interface = Interface {
+        linktype: body.u16(0)?,
+        resolution: 1_000_000,
+        offset: 0,
+    };
+    let mut offset = 8;
+    while offset + 4 <= body.data.len() {
+        let code = body.u16(offset)?;
+        let len = body.u16(offset + 2)? as usize;
+        let value = body.slice(offset + 4, len)?;
+        match code {
+            OPT_ENDOFOPT => break,
+            OPT_IF_TSRESOL if len == 1 => {
+                let exponent = u32::from(value[0] & 0x7f);
+                interface.resolution = match value[0] & 0x80 {
+                    0 => 10u64.checked_pow(exponent),
+                    _ => 2u64.checked_pow(exponent),
+                }
+                .ok_or_else(|| invalid_data("unsupported pcapng timestamp resolution"))?;
+            }
+            OPT_IF_TSOFFSET if len == 8 => interface.offset = body.u64(offset + 4)?,
+            _ => {}
+        }
+        offset += 4 + len.next_multiple_of(4);
+    }
+    Ok(interface)
+}
+
+fn ticks_to_duration(ticks: u64, resolution: u64) -> Duration {
+    let nanos = u128::from(ticks) * 1_000_000_000 / u128::from(resolution);
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+/// Strips the link-layer header off a packet and decodes the UDP datagram inside
+fn decode_link(linktype: u16, data: &[u8], timestamp: Duration) -> Option<CapturedDatagram> {
+    let packet = match linktype {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => data,
+        // The address family in host or network byte order
+        LINKTYPE_NULL | LINKTYPE_LOOP => data.get(4..)?,
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            // Skip VLAN tags
+            while matches!(data.get(offset..offset + 2)?, [0x81, 0x00] | [0x88, 0xa8]) {
+                offset += 4;
+            }
+            data.get(offset + 2..)?
+        }
+        LINKTYPE_LINUX_SLL => data.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => data.get(20..)?,
+        _ => return None,
+    };
+    decode_ip(packet, timestamp)
+}
+
+fn decode_ip(packet: &[u8], timestamp: Duration) -> Option<CapturedDatagram> {
+    let (src_ip, dst_ip, tos, udp): (IpAddr, IpAddr, u8, &[u8]) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(packet[0] & 0x0f) * 4;
+            let total_len = usize::from(u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]));
+            let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+            // More fragments, or a fragment offset
+            if fragment & 0x3fff != 0 || *packet.get(9)? != IPPROTO_UDP {
+                return None;
+            }
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            let end = total_len.min(packet.len());
+            (
+                Ipv4Addr::from(src).into(),
+                Ipv4Addr::from(dst).into(),
+                packet[1],
+                packet.get(header_len..end)?,
+            )
+        }
+        6 => {
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let tos = (u16::from_be_bytes([packet[0], packet[1]]) >> 4) as u8;
+            let payload_len = usize::from(u16::from_be_bytes([packet[4], packet[5]]));
+            let end = (40 + payload_len).min(packet.len());
+            let mut next_header = packet[6];
+            let mut offset = 40;
+            loop {
+                match next_header {
+                    IPPROTO_UDP => break,
+                    // Hop-by-hop, routing and destination options
+                    0 | 43 | 60 => {
+                        next_header = *packet.get(offset)?;
+                        offset += (usize::from(*packet.get(offset + 1)?) + 1) * 8;
+                    }
+                    // Fragments, or another protocol
+                    _ => return None,
+                }
+            }
+            (
+                Ipv6Addr::from(src).into(),
+                Ipv6Addr::from(dst).into(),
+                tos,
+                packet.get(offset..end)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let src_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+    let dst_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    let udp_len = usize::from(u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]));
+    Some(CapturedDatagram {
+        timestamp,
+        src: SocketAddr::new(src_ip, src_port),
+        dst: SocketAddr::new(dst_ip, dst_port),
+        ecn: EcnCodepoint::from_bits(tos),
+        payload: udp.get(8..udp_len.clamp(8, udp.len()))?.to_vec(),
+    })
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads integers of either byte order, failing on truncated input
+struct Bytes<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Bytes<'a> {
+    fn slice(&self, offset: usize, len: usize) -> io::Result<&'a [u8]> {
+        self.data
+            .get(offset..)
+            .and_then(|data| data.get(..len))
+            .ok_or_else(|| invalid_data("capture is truncated"))
+    }
+
+    fn u16(&self, offset: usize) -> io::Result<u16> {
+        let bytes = self.slice(offset, 2)?.try_into().unwrap();
+        Ok(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> io::Result<u32> {
+        let bytes = self.slice(offset, 4)?.try_into().unwrap();
+        Ok(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn u64(&self, offset: usize) -> io::Result<u64> {
+        let bytes = self.slice(offset, 8)?.try_into().unwrap();
+        Ok(match self.big_endian {
+            true => u64::from_be_bytes(bytes),
+            false => u64::from_le_bytes(bytes),
+        })
+    }
+}
@@ -30,7 +30,9 @@ impl EcnCodepoint {
 #[derive(Debug)]
 pub struct Transmit {
     /// The socket this datagram should be sent to
-    pub destination: SocketAddr,
+    ///
+    /// `None` sends the datagram to the peer of a connected socket.
+    pub destination: Option<SocketAddr>,
     /// Explicit congestion notification bits to set on the packet
     pub ecn: Option<EcnCodepoint>,
     /// Contents of the datagram
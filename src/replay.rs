@@ -0,0 +1,262 @@
+use crate::pcap::{self, CapturedDatagram};
+use crate::runtime::AsyncUdpSocket;
+use crate::{timer, Capabilities, RecvMeta, Transmit};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    future::poll_fn,
+    io::{self, BufReader, IoSliceMut, Read},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::Path,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// When a [`ReplaySocket`] delivers the datagrams of a capture
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ReplayTiming {
+    /// Keeps the intervals between the datagrams as they were captured, starting with the
+    /// first receive
+    #[default]
+    Original,
+    /// Delivers every datagram as soon as it is asked for
+    Asap,
+}
+
+/// Selects the datagrams of a capture to replay by their addresses and ports
+///
+/// The protocol of the 5-tuple is always UDP. Fields which are `None` match anything. IPv4
+/// addresses also match their IPv4-mapped IPv6 form.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayFilter {
+    pub src_ip: Option<IpAddr>,
+    pub src_port: Option<u16>,
+    pub dst_ip: Option<IpAddr>,
+    pub dst_port: Option<u16>,
+}
+
+impl ReplayFilter {
+    pub fn matches(&self, src: SocketAddr, dst: SocketAddr) -> bool {
+        ip_matches(self.src_ip, src.ip())
+            && matches(self.src_port, src.port())
+            && ip_matches(self.dst_ip, dst.ip())
+            && matches(self.dst_port, dst.port())
+    }
+}
+
+fn ip_matches(filter: Option<IpAddr>, ip: IpAddr) -> bool {
+    matches(filter.map(|ip| ip.to_canonical()), ip.to_canonical())
+}
+
+fn matches<T: PartialEq>(filter: Option<T>, value: T) -> bool {
+    match filter {
+        Some(filter) => filter == value,
+        None => true,
+    }
+}
+
+/// A socket which receives the UDP datagrams recorded in a pcap or pcapng capture
+///
+/// Each datagram is delivered with the ECN codepoint, source address and destination IP it was
+/// captured with. Everything sent through the socket is collected instead of leaving the host,
+/// to be compared against the expected output with [`ReplaySocket::take_sent`]. Once the
+/// capture is exhausted, receiving waits forever, like a socket on a quiet network.
+///
+/// The local address is the destination of the filter, completed by the destination of the
+/// first replayed datagram.
+pub struct ReplaySocket {
+    local_addr: SocketAddr,
+    timing: ReplayTiming,
+    state: Mutex<ReplayState>,
+}
+
+struct ReplayState {
+    datagrams: VecDeque<CapturedDatagram>,
+    first_timestamp: Duration,
+    /// When the first datagram was asked for
+    start: Option<Instant>,
+    sent: Vec<Transmit>,
+    deadline: timer::Deadline,
+}
+
+impl ReplaySocket {
+    /// Reads the capture from `capture`, keeping the datagrams which match `filter`
+    pub fn new(capture: impl Read, filter: ReplayFilter, timing: ReplayTiming) -> io::Result<Self> {
+        let datagrams = pcap::read_datagrams(capture)?
+            .into_iter()
+            .filter(|datagram| filter.matches(datagram.src, datagram.dst))
+            .collect::<VecDeque<_>>();
+        let first = datagrams.front();
+        let local_ip = filter
+            .dst_ip
+            .or(first.map(|datagram| datagram.dst.ip()))
+            .unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+        let local_port = filter
+            .dst_port
+            .or(first.map(|datagram| datagram.dst.port()))
+            .unwrap_or(0);
+        Ok(Self {
+            local_addr: SocketAddr::new(local_ip, local_port),
+            timing,
+            state: Mutex::new(ReplayState {
+                first_timestamp: first.map_or(Duration::ZERO, |datagram| datagram.timestamp),
+                datagrams,
+                start: None,
+                sent: Vec::new(),
+                deadline: timer::Deadline::default(),
+            }),
+        })
+    }
+
+    /// Reads the capture from the file at `path`
+    pub fn open(
+        path: impl AsRef<Path>,
+        filter: ReplayFilter,
+        timing: ReplayTiming,
+    ) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?), filter, timing)
+    }
+
+    /// The number of datagrams which have not been received yet
+    pub fn remaining(&self) -> usize {
+        self.state.lock().unwrap().datagrams.len()
+    }
+
+    /// Returns the transmits sent so far, in the order they were sent
+    pub fn take_sent(&self) -> Vec<Transmit> {
+        std::mem::take(&mut self.state.lock().unwrap().sent)
+    }
+
+    pub async fn send(
+        &self,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_send(cx, capabilities, transmits)).await
+    }
+
+    pub async fn recv(
+        &self,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_recv(cx, bufs, meta)).await
+    }
+}
+
+impl AsyncUdpSocket for ReplaySocket {
+    /// Collects the transmits, which never blocks
+    fn poll_send(
+        &self,
+        _cx: &mut Context<'_>,
+        _capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        state.sent.extend(transmits.iter().map(|transmit| Transmit {
+            destination: transmit.destination,
+            ecn: transmit.ecn,
+            contents: transmit.contents.clone(),
+            segment_size: transmit.segment_size,
+            src_ip: transmit.src_ip,
+        }));
+        Poll::Ready(Ok(transmits.len()))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let start = *state.start.get_or_insert(now);
+        let mut count = 0;
+        let mut next_due = None;
+        for (buf, meta) in bufs.iter_mut().zip(meta.iter_mut()) {
+            let datagram = match state.datagrams.front() {
+                Some(datagram) => datagram,
+                None => break,
+            };
+            if self.timing == ReplayTiming::Original {
+                let due = start + datagram.timestamp.saturating_sub(state.first_timestamp);
+                if due > now {
+                    next_due = Some(due);
+                    break;
+                }
+            }
+            let datagram = state.datagrams.pop_front().unwrap();
+            let len = datagram.payload.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram.payload[..len]);
+            *meta = RecvMeta {
+                addr: datagram.src,
+                len,
+                stride: len,
+                ecn: datagram.ecn,
+                dst_ip: Some(datagram.dst.ip()),
+            };
+            count += 1;
+        }
+        if count > 0 {
+            return Poll::Ready(Ok(count));
+        }
+
+        if let Some(due) = next_due {
+            state.deadline.wake_at(due, cx.waker());
+        }
+        Poll::Pending
+    }
+
+    /// Never waits, as sending never blocks
+    fn poll_writable(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Waits until the next datagram of the capture is due, forever once all were received
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let start = *state.start.get_or_insert(now);
+        let due = match state.datagrams.front() {
+            Some(_) if self.timing == ReplayTiming::Asap => now,
+            Some(datagram) => start + datagram.timestamp.saturating_sub(state.first_timestamp),
+            None => return Poll::Pending,
+        };
+        if due > now {
+            state.deadline.wake_at(due, cx.waker());
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn try_send(&self, capabilities: &Capabilities, transmits: &[Transmit]) -> io::Result<usize> {
+        crate::runtime::try_poll(|cx| self.poll_send(cx, capabilities, transmits))
+    }
+
+    fn try_recv(&self, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> io::Result<usize> {
+        crate::runtime::try_poll(|cx| self.poll_recv(cx, bufs, meta))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "replay sockets are not connected",
+        ))
+    }
+}
+
+impl fmt::Debug for ReplaySocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplaySocket")
+            .field("local_addr", &self.local_addr)
+            .field("timing", &self.timing)
+            .finish_non_exhaustive()
+    }
+}
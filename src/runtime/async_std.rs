@@ -1,18 +1,58 @@
-use crate::runtime::AsyncUdpSocket;
+#[cfg(unix)]
+use crate::icmp::{self, IcmpRecvMeta, IcmpTransmit};
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+use crate::netlink::{self, NetlinkEvent};
+use crate::runtime::rebind::{Direction, Rebindable};
+use crate::runtime::split::{self, RecvHalf, SendHalf};
+use crate::runtime::{self, AsyncTimer, AsyncUdpSocket, Runtime};
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+use crate::udplite;
+#[cfg(unix)]
+use crate::unix_datagram::{self, UnixDatagramState, UnixRecvMeta, UnixSocketAddr, UnixTransmit};
+#[cfg(target_os = "linux")]
+use crate::BusyPollConfig;
 use crate::{Capabilities, RecvMeta, Transmit, UdpSocketState};
 use async_io::Async;
 use async_std::net::ToSocketAddrs;
 use std::{
-    future::poll_fn,
+    future::{poll_fn, Future},
     io,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
+#[cfg(unix)]
+use std::{os::fd::AsFd, path::Path};
+
+/// The async-std runtime
+///
+/// Spawned tasks and wrapped sockets are driven by the global async-std executor and reactor.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AsyncStdRuntime;
+
+impl Runtime for AsyncStdRuntime {
+    fn new_timer(&self, deadline: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        Box::pin(async_io::Timer::at(deadline))
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        ::async_std::task::spawn(future);
+    }
+
+    fn wrap_udp_socket(&self, socket: std::net::UdpSocket) -> io::Result<Box<dyn AsyncUdpSocket>> {
+        Ok(Box::new(UdpSocket::from_std(socket)?))
+    }
+}
 
 #[derive(Debug)]
 pub struct UdpSocket {
-    io: Async<std::net::UdpSocket>,
+    io: Rebindable<Async<std::net::UdpSocket>>,
     inner: UdpSocketState,
+    /// Becomes readable when datagrams were received through io_uring
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    ring: Option<Async<std::os::fd::OwnedFd>>,
 }
 
 impl AsyncUdpSocket for UdpSocket {
@@ -22,12 +62,13 @@ impl AsyncUdpSocket for UdpSocket {
         capabilities: &Capabilities,
         transmits: &[Transmit],
     ) -> Poll<io::Result<usize>> {
-        loop {
-            ready!(self.io.poll_writable(cx))?;
-            if let Ok(res) = self.inner.send((&self.io).into(), capabilities, transmits) {
-                return Poll::Ready(Ok(res));
+        self.io.poll(cx, Direction::Send, |io, cx| loop {
+            ready!(io.poll_writable(cx))?;
+            match self.inner.send(io.into(), capabilities, transmits) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
             }
-        }
+        })
     }
 
     fn poll_recv(
@@ -36,20 +77,74 @@ impl AsyncUdpSocket for UdpSocket {
         bufs: &mut [io::IoSliceMut<'_>],
         meta: &mut [RecvMeta],
     ) -> Poll<io::Result<usize>> {
-        loop {
-            ready!(self.io.poll_readable(cx))?;
-            if let Ok(res) = self.inner.recv((&self.io).into(), bufs, meta) {
+        if let Some(res) = self.drain(bufs, meta) {
+            return Poll::Ready(res);
+        }
+        // Spinning only reads the current socket and skips a previous one, which `drain` above
+        // already found empty and dropped
+        #[cfg(target_os = "linux")]
+        if let Some(poll) = self
+            .io
+            .with(|io| self.inner.poll_recv_spinning(cx, io.into(), bufs, meta))
+        {
+            return poll;
+        }
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = &self.ring {
+            loop {
+                if let Ok(res) = self.io.with(|io| self.inner.recv(io.into(), bufs, meta)) {
+                    return Poll::Ready(Ok(res));
+                }
+                ready!(ring.poll_readable(cx))?;
+            }
+        }
+        self.io.poll(cx, Direction::Recv, |io, cx| loop {
+            ready!(io.poll_readable(cx))?;
+            if let Ok(res) = self.inner.recv(io.into(), bufs, meta) {
                 return Poll::Ready(Ok(res));
             }
+        })
+    }
+
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.io
+            .poll(cx, Direction::Send, |io, cx| io.poll_writable(cx))
+    }
+
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.io.draining().is_some() {
+            return Poll::Ready(Ok(()));
+        }
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = &self.ring {
+            return ring.poll_readable(cx);
         }
+        self.io
+            .poll(cx, Direction::Recv, |io, cx| io.poll_readable(cx))
+    }
+
+    fn try_send(&self, capabilities: &Capabilities, transmits: &[Transmit]) -> io::Result<usize> {
+        self.io
+            .with(|io| self.inner.send(io.into(), capabilities, transmits))
+    }
+
+    fn try_recv(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        if let Some(res) = self.drain(bufs, meta) {
+            return res;
+        }
+        self.io.with(|io| self.inner.recv(io.into(), bufs, meta))
     }
 
     fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.io.as_ref().local_addr()
+        self.io.with(|io| io.get_ref().local_addr())
     }
 
     fn peer_addr(&self) -> io::Result<SocketAddr> {
-        self.io.as_ref().peer_addr()
+        self.io.with(|io| io.get_ref().peer_addr())
     }
 }
 
@@ -59,13 +154,7 @@ impl UdpSocket {
 
         for addr in addr.to_socket_addrs().await? {
             match Async::<std::net::UdpSocket>::bind(addr) {
-                Ok(socket) => {
-                    UdpSocketState::configure((&socket).into())?;
-                    return Ok(Self {
-                        io: socket,
-                        inner: UdpSocketState::new(),
-                    });
-                }
+                Ok(socket) => return Self::new(socket),
                 Err(err) => last_err = Some(err),
             }
         }
@@ -78,11 +167,87 @@ impl UdpSocket {
         }))
     }
 
+    pub fn from_std(socket: std::net::UdpSocket) -> io::Result<Self> {
+        Self::new(Async::new(socket)?)
+    }
+
+    fn new(socket: Async<std::net::UdpSocket>) -> io::Result<Self> {
+        UdpSocketState::configure((&socket).into())?;
+        #[allow(unused_mut)] // only mutable with io_uring
+        let mut inner = UdpSocketState::new();
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        let ring = match inner.enable_io_uring((&socket).into()) {
+            Ok(()) => {
+                let fd = inner.io_uring_fd().unwrap().try_clone_to_owned()?;
+                Some(Async::new(fd)?)
+            }
+            Err(e) => {
+                crate::uring::log_unavailable(&e);
+                None
+            }
+        };
+        Ok(Self {
+            io: Rebindable::new(socket),
+            inner,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            ring,
+        })
+    }
+
+    /// Replaces the underlying socket with `socket` while the socket is in use, e.g. to move to
+    /// a new local address or port
+    ///
+    /// `socket` is configured like by [`UdpSocket::from_std`] and connected to the peer set by
+    /// [`UdpSocket::connect`], if any. The state kept by this socket carries over, such as
+    /// [`UdpSocket::set_canonicalize_mapped_ipv4`], but socket options such as multicast
+    /// memberships must be set again. The last tasks which polled for sending and for receiving are
+    /// woken to continue on `socket`, receiving after the datagrams still queued on the previous
+    /// socket. A receiver spinning for a busy polling spin time on Linux
+    /// only spins on `socket`. Fails when io_uring is in use.
+    pub fn rebind(&self, socket: std::net::UdpSocket) -> io::Result<()> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if self.ring.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "sockets using io_uring cannot be rebound",
+            ));
+        }
+        let socket = Async::new(socket)?;
+        UdpSocketState::configure((&socket).into())?;
+        self.inner.set_canonicalize_mapped_ipv4(
+            (&socket).into(),
+            self.inner.canonicalize_mapped_ipv4(),
+        )?;
+        if let Some(peer) = self.inner.peer() {
+            self.inner.connect((&socket).into(), peer)?;
+        }
+        self.io.replace(socket);
+        Ok(())
+    }
+
+    /// Receives the datagrams still queued on the socket replaced by [`UdpSocket::rebind`]
+    ///
+    /// Returns `None` once there are none left.
+    fn drain(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Option<io::Result<usize>> {
+        let io = self.io.draining()?;
+        match self.inner.recv((&*io).into(), bufs, meta) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.io.drained(&io);
+                None
+            }
+            res => Some(res),
+        }
+    }
+
     pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
         let mut last_err = None;
 
         for addr in addr.to_socket_addrs().await? {
-            match self.io.get_ref().connect(addr) {
+            match self.inner.connect((&*self.io.get()).into(), addr) {
                 Ok(()) => return Ok(()),
                 Err(err) => last_err = Some(err),
             }
@@ -96,6 +261,45 @@ impl UdpSocket {
         }))
     }
 
+    /// Dissolves the association set up by [`UdpSocket::connect`]
+    pub fn disconnect(&self) -> io::Result<()> {
+        self.inner.disconnect((&*self.io.get()).into())
+    }
+
+    /// Reports IPv4 peers of a dual-stack socket as `SocketAddr::V4` instead of IPv4-mapped IPv6
+    /// addresses, and maps IPv4 destinations back when sending
+    pub fn set_canonicalize_mapped_ipv4(&self, enabled: bool) -> io::Result<()> {
+        self.inner
+            .set_canonicalize_mapped_ipv4((&*self.io.get()).into(), enabled)
+    }
+
+    /// Configures busy polling, which may make receiving spin before waiting on the reactor
+    #[cfg(target_os = "linux")]
+    pub fn set_busy_poll(&self, config: &BusyPollConfig) -> io::Result<()> {
+        self.inner.set_busy_poll((&*self.io.get()).into(), config)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn busy_poll(&self) -> io::Result<BusyPollConfig> {
+        self.inner.busy_poll((&*self.io.get()).into())
+    }
+
+    #[cfg(unix)]
+    fn poll_send_on_interface(
+        &self,
+        cx: &mut Context<'_>,
+        transmit: &Transmit,
+        ifindex: u32,
+    ) -> Poll<io::Result<()>> {
+        self.io.poll(cx, Direction::Send, |io, cx| loop {
+            ready!(io.poll_writable(cx))?;
+            match self.inner.send_on_interface(io.into(), transmit, ifindex) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+        })
+    }
+
     pub async fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> io::Result<usize> {
         let addr = match addr.to_socket_addrs().await?.next() {
             Some(addr) => addr,
@@ -107,11 +311,35 @@ impl UdpSocket {
             }
         };
 
-        self.io.send_to(buf, addr).await
+        poll_fn(|cx| {
+            self.io.poll(cx, Direction::Send, |io, cx| loop {
+                ready!(io.poll_writable(cx))?;
+                match io.get_ref().send_to(buf, addr) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    res => return Poll::Ready(res),
+                }
+            })
+        })
+        .await
     }
 
     pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
-        self.io.recv_from(buf).await
+        poll_fn(|cx| {
+            if let Some(io) = self.io.draining() {
+                match io.get_ref().recv_from(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.io.drained(&io),
+                    res => return Poll::Ready(res),
+                }
+            }
+            self.io.poll(cx, Direction::Recv, |io, cx| loop {
+                ready!(io.poll_readable(cx))?;
+                match io.get_ref().recv_from(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    res => return Poll::Ready(res),
+                }
+            })
+        })
+        .await
     }
 
     pub async fn send(
@@ -129,4 +357,423 @@ impl UdpSocket {
     ) -> io::Result<usize> {
         poll_fn(|cx| self.poll_recv(cx, bufs, meta)).await
     }
+
+    /// Sends a batch of datagrams like [`UdpSocket::send`], failing with
+    /// [`io::ErrorKind::TimedOut`] if the socket did not become writable within `timeout`
+    pub async fn send_timeout(
+        &self,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+        timeout: Duration,
+    ) -> io::Result<usize> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => {
+                runtime::deadline(async_io::Timer::at(deadline), |cx| {
+                    self.poll_send(cx, capabilities, transmits)
+                })
+                .await
+            }
+            None => self.send(capabilities, transmits).await,
+        }
+    }
+
+    /// Receives a batch of datagrams like [`UdpSocket::recv`], failing with
+    /// [`io::ErrorKind::TimedOut`] if none arrived within `timeout`
+    pub async fn recv_timeout(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+        timeout: Duration,
+    ) -> io::Result<usize> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.recv_deadline(bufs, meta, deadline).await,
+            None => self.recv(bufs, meta).await,
+        }
+    }
+
+    /// Receives a batch of datagrams like [`UdpSocket::recv`], failing with
+    /// [`io::ErrorKind::TimedOut`] if none arrived by `deadline`
+    ///
+    /// Datagrams which are already queued are returned even if the deadline has passed. A batch
+    /// is taken from the socket all at once when the future completes, so dropping it never
+    /// loses datagrams.
+    pub async fn recv_deadline(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+        deadline: Instant,
+    ) -> io::Result<usize> {
+        runtime::deadline(async_io::Timer::at(deadline), |cx| {
+            self.poll_recv(cx, bufs, meta)
+        })
+        .await
+    }
+
+    /// Splits the socket into owned halves which can be used from different tasks
+    pub fn into_split(self) -> (SendHalf<Self>, RecvHalf<Self>) {
+        split::split(Arc::new(self))
+    }
+
+    /// Splits a shared socket into halves which can be used from different tasks
+    pub fn split(self: &Arc<Self>) -> (SendHalf<Self>, RecvHalf<Self>) {
+        split::split(self.clone())
+    }
+}
+
+runtime::impl_socket_options!(UdpSocket);
+
+/// UDP-Lite socket, whose checksum may only cover the start of each datagram
+///
+/// Sending, receiving and connecting work as for [`UdpSocket`], except that UDP-Lite does not
+/// support segmentation offload, so sending fails with [`io::ErrorKind::InvalidInput`] if a
+/// [`Transmit::segment_size`] is set.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+#[derive(Debug)]
+pub struct UdpLiteSocket {
+    socket: UdpSocket,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+impl AsyncUdpSocket for UdpLiteSocket {
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        if let Err(e) = udplite::check_transmits(transmits) {
+            return Poll::Ready(Err(e));
+        }
+        self.socket.poll_send(cx, capabilities, transmits)
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        self.socket.poll_recv(cx, bufs, meta)
+    }
+
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_writable(cx)
+    }
+
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_readable(cx)
+    }
+
+    fn try_send(&self, capabilities: &Capabilities, transmits: &[Transmit]) -> io::Result<usize> {
+        udplite::check_transmits(transmits)?;
+        self.socket.try_send(capabilities, transmits)
+    }
+
+    fn try_recv(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        self.socket.try_recv(bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+impl UdpLiteSocket {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::from_std(udplite::bind(addr)?)?,
+        })
+    }
+
+    /// Sets how many bytes, starting at the UDP-Lite header, are covered by the checksum of sent
+    /// datagrams
+    ///
+    /// `0` covers the whole datagram, which is the default. Otherwise the coverage must be at
+    /// least 8 bytes, to include the header.
+    pub fn set_send_checksum_coverage(&self, coverage: u16) -> io::Result<()> {
+        udplite::set_send_checksum_coverage((&*self.socket.io.get()).into(), coverage)
+    }
+
+    pub fn send_checksum_coverage(&self) -> io::Result<u16> {
+        udplite::send_checksum_coverage((&*self.socket.io.get()).into())
+    }
+
+    /// Sets the checksum coverage received datagrams need to have at least, others are dropped
+    ///
+    /// `0` accepts any coverage, which is the default.
+    pub fn set_recv_checksum_coverage(&self, coverage: u16) -> io::Result<()> {
+        udplite::set_recv_checksum_coverage((&*self.socket.io.get()).into(), coverage)
+    }
+
+    pub fn recv_checksum_coverage(&self) -> io::Result<u16> {
+        udplite::recv_checksum_coverage((&*self.socket.io.get()).into())
+    }
+
+    pub async fn send(
+        &self,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> io::Result<usize> {
+        udplite::check_transmits(transmits)?;
+        self.socket.send(capabilities, transmits).await
+    }
+
+    pub async fn recv(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        self.socket.recv(bufs, meta).await
+    }
+
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn into_inner(self) -> UdpSocket {
+        self.socket
+    }
+}
+
+/// Unix datagram socket sending and receiving batches of datagrams
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixDatagram {
+    io: Async<std::os::unix::net::UnixDatagram>,
+    inner: UnixDatagramState,
+}
+
+#[cfg(unix)]
+impl UnixDatagram {
+    pub fn from_std(socket: std::os::unix::net::UnixDatagram) -> io::Result<Self> {
+        Ok(Self {
+            io: Async::new(socket)?,
+            inner: UnixDatagramState::new(),
+        })
+    }
+
+    /// Send datagrams from `transmits`, or register to be woken if sending may succeed in the
+    /// future
+    pub fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        transmits: &[UnixTransmit],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.io.poll_writable(cx))?;
+            match self.inner.send(self.io.as_fd(), transmits) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    /// Receive datagrams, or register to be woken if receiving may succeed in the future
+    pub fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [UnixRecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.io.poll_readable(cx))?;
+            match self.inner.recv(self.io.as_fd(), bufs, meta) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    /// Creates a socket bound to the filesystem path `path`
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::bind_addr(&UnixSocketAddr::pathname(path))
+    }
+
+    /// Creates a socket bound to `addr`, which may be in the abstract namespace on Linux
+    pub fn bind_addr(addr: &UnixSocketAddr) -> io::Result<Self> {
+        Self::from_std(unix_datagram::bind(addr)?)
+    }
+
+    /// Creates a socket which is not bound to any address
+    pub fn unbound() -> io::Result<Self> {
+        Self::from_std(unix_datagram::unbound()?)
+    }
+
+    /// Creates an unnamed pair of connected sockets
+    pub fn pair() -> io::Result<(Self, Self)> {
+        let (a, b) = unix_datagram::pair()?;
+        Ok((Self::from_std(a)?, Self::from_std(b)?))
+    }
+
+    /// Connects the socket to the filesystem path `path`
+    pub fn connect<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.connect_addr(&UnixSocketAddr::pathname(path))
+    }
+
+    /// Connects the socket to `addr`, so transmits may leave out their destination
+    pub fn connect_addr(&self, addr: &UnixSocketAddr) -> io::Result<()> {
+        unix_datagram::connect(self.io.as_fd(), addr)
+    }
+
+    /// Sets the number of file descriptors that can be received with each datagram
+    ///
+    /// See [`UnixDatagramState::set_max_recv_fds`].
+    pub fn set_max_recv_fds(&self, max_fds: usize) {
+        self.inner.set_max_recv_fds(max_fds)
+    }
+
+    /// Enables receiving the credentials of the sender with every datagram, on Linux only
+    pub fn set_pass_credentials(&self, enabled: bool) -> io::Result<()> {
+        self.inner.set_pass_credentials(self.io.as_fd(), enabled)
+    }
+
+    pub fn local_addr(&self) -> io::Result<UnixSocketAddr> {
+        unix_datagram::local_addr(self.io.as_fd())
+    }
+
+    pub fn peer_addr(&self) -> io::Result<UnixSocketAddr> {
+        unix_datagram::peer_addr(self.io.as_fd())
+    }
+
+    pub async fn send(&self, transmits: &[UnixTransmit]) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_send(cx, transmits)).await
+    }
+
+    pub async fn recv(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [UnixRecvMeta],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_recv(cx, bufs, meta)).await
+    }
+}
+
+/// Unprivileged ICMP socket sending and receiving echo messages
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct IcmpSocket {
+    io: Async<socket2::Socket>,
+}
+
+#[cfg(unix)]
+impl IcmpSocket {
+    /// Creates an ICMP socket bound to `addr`
+    ///
+    /// The address family of `addr` selects between ICMP and ICMPv6. On Linux, this requires the
+    /// group of the process to be within `net.ipv4.ping_group_range`.
+    pub fn bind(addr: IpAddr) -> io::Result<Self> {
+        Ok(Self {
+            io: Async::new(icmp::bind(addr)?)?,
+        })
+    }
+
+    /// Send messages from `transmits`, or register to be woken if sending may succeed in the
+    /// future
+    pub fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        transmits: &[IcmpTransmit],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.io.poll_writable(cx))?;
+            match icmp::send(self.io.as_fd(), transmits) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    /// Receive a message, or register to be woken if receiving may succeed in the future
+    pub fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [IcmpRecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.io.poll_readable(cx))?;
+            match icmp::recv(self.io.as_fd(), bufs, meta) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    /// The local address of the socket, whose port is the echo identifier on Linux
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        icmp::local_addr(self.io.as_fd())
+    }
+
+    pub async fn send(&self, transmits: &[IcmpTransmit]) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_send(cx, transmits)).await
+    }
+
+    pub async fn recv(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [IcmpRecvMeta],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_recv(cx, bufs, meta)).await
+    }
+}
+
+/// Monitor of local address and route changes, through a Linux `NETLINK_ROUTE` socket
+///
+/// With the `futures` feature, the events are also available as a `Stream`.
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+#[derive(Debug)]
+pub struct NetlinkMonitor {
+    io: Async<socket2::Socket>,
+    events: netlink::Events,
+}
+
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+impl NetlinkMonitor {
+    /// Subscribes to IPv4 and IPv6 address and route changes
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            io: Async::new(netlink::bind()?)?,
+            events: netlink::Events::new(),
+        })
+    }
+
+    /// Receive the next event, or register to be woken if one may be received in the future
+    ///
+    /// Fails with `ENOBUFS` when the kernel dropped events because they were not received fast
+    /// enough, after which the current addresses and routes should be looked up again, the
+    /// addresses e.g. with [`interface_addrs`](crate::interface_addrs).
+    pub fn poll_next_event(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<NetlinkEvent>> {
+        loop {
+            if let Some(event) = self.events.pop() {
+                return Poll::Ready(Ok(event));
+            }
+            ready!(self.io.poll_readable(cx))?;
+            match self.events.recv(self.io.as_fd()) {
+                Err(e) if e.kind() != io::ErrorKind::WouldBlock => return Poll::Ready(Err(e)),
+                _ => {}
+            }
+        }
+    }
+
+    pub async fn next_event(&mut self) -> io::Result<NetlinkEvent> {
+        poll_fn(|cx| self.poll_next_event(cx)).await
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "netlink", feature = "futures"))]
+impl futures_core::Stream for NetlinkMonitor {
+    type Item = io::Result<NetlinkEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_event(cx).map(Some)
+    }
 }
@@ -1,40 +1,41 @@
 use crate::{Capabilities, RecvMeta, Transmit, UdpSocketState};
-use retty_io::{lazycell::AtomicLazyCell, Evented, Poll, PollOpt, Ready, Token};
+use mio::{event::Source, Interest, Registry, Token};
 use std::{
     io,
     net::{SocketAddr, ToSocketAddrs},
 };
 
+/// UDP socket to be driven by a [`mio::Poll`]
+///
+/// The socket is registered with a [`Registry`] like any other [`Source`]. Sending and receiving
+/// never block, but fail with [`io::ErrorKind::WouldBlock`] until the next readiness event.
 #[derive(Debug)]
 pub struct UdpSocket {
-    io: retty_io::net::UdpSocket,
+    io: mio::net::UdpSocket,
     inner: UdpSocketState,
-    peer: AtomicLazyCell<SocketAddr>,
 }
 
-impl Evented for UdpSocket {
+impl Source for UdpSocket {
     fn register(
-        &self,
-        poll: &Poll,
+        &mut self,
+        registry: &Registry,
         token: Token,
-        interest: Ready,
-        opts: PollOpt,
+        interests: Interest,
     ) -> io::Result<()> {
-        self.io.register(poll, token, interest, opts)
+        self.io.register(registry, token, interests)
     }
 
     fn reregister(
-        &self,
-        poll: &Poll,
+        &mut self,
+        registry: &Registry,
         token: Token,
-        interest: Ready,
-        opts: PollOpt,
+        interests: Interest,
     ) -> io::Result<()> {
-        self.io.reregister(poll, token, interest, opts)
+        self.io.reregister(registry, token, interests)
     }
 
-    fn deregister(&self, poll: &Poll) -> io::Result<()> {
-        self.io.deregister(poll)
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.io.deregister(registry)
     }
 }
 
@@ -44,15 +45,8 @@ impl UdpSocket {
         let addrs = addrs.to_socket_addrs()?;
 
         for addr in addrs {
-            match retty_io::net::UdpSocket::bind(&addr) {
-                Ok(socket) => {
-                    UdpSocketState::configure((&socket).into())?;
-                    return Ok(Self {
-                        io: socket,
-                        inner: UdpSocketState::new(),
-                        peer: AtomicLazyCell::new(),
-                    });
-                }
+            match mio::net::UdpSocket::bind(addr) {
+                Ok(socket) => return Self::new(socket),
                 Err(err) => last_err = Some(err),
             }
         }
@@ -65,18 +59,25 @@ impl UdpSocket {
         }))
     }
 
+    pub fn from_std(socket: std::net::UdpSocket) -> io::Result<Self> {
+        Self::new(mio::net::UdpSocket::from_std(socket))
+    }
+
+    fn new(socket: mio::net::UdpSocket) -> io::Result<Self> {
+        UdpSocketState::configure((&socket).into())?;
+        Ok(Self {
+            io: socket,
+            inner: UdpSocketState::new(),
+        })
+    }
+
     pub fn connect<A: ToSocketAddrs>(&self, addrs: A) -> io::Result<()> {
         let mut last_err = None;
         let addrs = addrs.to_socket_addrs()?;
 
         for addr in addrs {
-            match self.io.connect(addr) {
-                Ok(()) => {
-                    self.peer.fill(addr).map_err(|_| {
-                        io::Error::new(io::ErrorKind::AddrInUse, "peer address existed")
-                    })?;
-                    return Ok(());
-                }
+            match self.inner.connect((&self.io).into(), addr) {
+                Ok(()) => return Ok(()),
                 Err(err) => last_err = Some(err),
             }
         }
@@ -89,6 +90,18 @@ impl UdpSocket {
         }))
     }
 
+    /// Dissolves the association set up by [`UdpSocket::connect`]
+    pub fn disconnect(&self) -> io::Result<()> {
+        self.inner.disconnect((&self.io).into())
+    }
+
+    /// Reports IPv4 peers of a dual-stack socket as `SocketAddr::V4` instead of IPv4-mapped IPv6
+    /// addresses, and maps IPv4 destinations back when sending
+    pub fn set_canonicalize_mapped_ipv4(&self, enabled: bool) -> io::Result<()> {
+        self.inner
+            .set_canonicalize_mapped_ipv4((&self.io).into(), enabled)
+    }
+
     pub fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addrs: A) -> io::Result<usize> {
         let addr = match addrs.to_socket_addrs()?.next() {
             Some(addr) => addr,
@@ -100,45 +113,14 @@ impl UdpSocket {
             }
         };
 
-        self.io.send_to(buf, &addr)
+        self.io.send_to(buf, addr)
     }
 
     pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
         self.io.recv_from(buf)
     }
 
-    pub fn send(
-        &self,
-        #[allow(unused_variables)] capabilities: &Capabilities,
-        transmits: &[Transmit],
-    ) -> io::Result<usize> {
-        #[cfg(windows)]
-        {
-            let mut sent = 0;
-            for transmit in transmits {
-                match self.io.send_to(&transmit.contents, &transmit.destination) {
-                    Ok(_) => {
-                        sent += 1;
-                    }
-                    // We need to report that some packets were sent in this case, so we rely on
-                    // errors being either harmlessly transient (in the case of WouldBlock) or
-                    // recurring on the next call.
-                    Err(_) if sent != 0 => return Ok(sent),
-                    Err(e) => {
-                        if e.kind() == io::ErrorKind::WouldBlock {
-                            return Err(e);
-                        }
-
-                        // Other errors are ignored, since they will ususally be handled
-                        // by higher level retransmits and timeouts.
-                        //log_sendmsg_error(&self.epoch, &self.last_send_error, e, transmit);
-                        sent += 1;
-                    }
-                }
-            }
-            Ok(sent)
-        }
-        #[cfg(not(windows))]
+    pub fn send(&self, capabilities: &Capabilities, transmits: &[Transmit]) -> io::Result<usize> {
         self.inner.send((&self.io).into(), capabilities, transmits)
     }
 
@@ -147,19 +129,6 @@ impl UdpSocket {
         bufs: &mut [io::IoSliceMut<'_>],
         meta: &mut [RecvMeta],
     ) -> io::Result<usize> {
-        #[cfg(windows)]
-        {
-            let (len, addr) = self.io.recv_from(&mut bufs[0])?;
-            meta[0] = RecvMeta {
-                len,
-                stride: len,
-                addr,
-                ecn: None,
-                dst_ip: None,
-            };
-            Ok(1)
-        }
-        #[cfg(not(windows))]
         self.inner.recv((&self.io).into(), bufs, meta)
     }
 
@@ -168,8 +137,8 @@ impl UdpSocket {
     }
 
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
-        if let Some(peer) = self.peer.borrow() {
-            Ok(*peer)
+        if let Some(peer) = self.inner.peer() {
+            Ok(peer)
         } else {
             Err(io::Error::new(io::ErrorKind::AddrNotAvailable, ""))
         }
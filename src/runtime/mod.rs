@@ -1,34 +1,125 @@
-#[cfg(not(feature = "metal-io"))]
 use crate::{Capabilities, RecvMeta, Transmit};
-#[cfg(not(feature = "metal-io"))]
 use std::{
     fmt::Debug,
+    future::{poll_fn, Future},
     io::{self, IoSliceMut},
     net::SocketAddr,
+    pin::{pin, Pin},
+    sync::Arc,
     task::{Context, Poll},
+    time::Instant,
 };
 
+pub(crate) mod rebind;
+pub(crate) mod split;
+
+/// Sockets driven by the `smol` runtime
 #[cfg(feature = "runtime-smol")]
-mod smol;
-#[cfg(feature = "runtime-smol")]
-pub use self::smol::UdpSocket;
+pub mod smol;
 
+/// Sockets driven by the `async-std` runtime
 #[cfg(feature = "runtime-async-std")]
-mod async_std;
-#[cfg(feature = "runtime-async-std")]
-pub use self::async_std::UdpSocket;
+pub mod async_std;
 
+/// Sockets driven by the `tokio` runtime
 #[cfg(feature = "runtime-tokio")]
-mod tokio;
-#[cfg(feature = "runtime-tokio")]
-pub use self::tokio::UdpSocket;
+pub mod tokio;
 
+/// Non-blocking sockets to be registered with a `mio` event loop
 #[cfg(feature = "metal-io")]
-mod metal_io;
-#[cfg(feature = "metal-io")]
-pub use self::metal_io::UdpSocket;
+pub mod metal_io;
+
+/// Abstracts over an asynchronous runtime, so protocols can be written once for all of them
+///
+/// Implemented by [`TokioRuntime`](crate::TokioRuntime), [`SmolRuntime`](crate::SmolRuntime)
+/// and [`AsyncStdRuntime`](crate::AsyncStdRuntime), depending on the enabled features.
+pub trait Runtime: Send + Sync + Debug + 'static {
+    /// Construct a timer that will expire at `deadline`
+    fn new_timer(&self, deadline: Instant) -> Pin<Box<dyn AsyncTimer>>;
+
+    /// Drive `future` to completion in the background
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>);
+
+    /// Convert `socket` into the socket type used by this runtime
+    fn wrap_udp_socket(&self, socket: std::net::UdpSocket) -> io::Result<Box<dyn AsyncUdpSocket>>;
+
+    /// Look up the current time
+    ///
+    /// Allows simulating the flow of time for testing.
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Abstract implementation of an async timer for runtime independence
+pub trait AsyncTimer: Send + Debug + 'static {
+    /// Update the timer to expire at `deadline`
+    fn reset(self: Pin<&mut Self>, deadline: Instant);
+
+    /// Check whether the timer has expired, and register to be woken if not
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()>;
+}
+
+/// Polls `f` until it completes or `timer` expires, failing with [`io::ErrorKind::TimedOut`] in
+/// the latter case
+///
+/// `f` is polled first, so that a result which is ready wins over an expired timer. Dropping the
+/// future is safe as long as `f` only has an effect when it completes.
+pub(crate) async fn deadline<T>(
+    timer: impl AsyncTimer,
+    mut f: impl FnMut(&mut Context<'_>) -> Poll<io::Result<T>>,
+) -> io::Result<T> {
+    let mut timer = pin!(timer);
+    poll_fn(|cx| {
+        if let Poll::Ready(res) = f(cx) {
+            return Poll::Ready(res);
+        }
+        ready!(timer.as_mut().poll(cx));
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "deadline has elapsed",
+        )))
+    })
+    .await
+}
+
+/// Returns the runtime the caller is running on, as far as this can be detected
+///
+/// A tokio runtime is picked up when called from within one. Otherwise, the first of
+/// async-std and smol which is enabled is used, as both run their own executor threads.
+#[allow(unreachable_code)]
+pub fn default_runtime() -> Option<Arc<dyn Runtime>> {
+    #[cfg(feature = "runtime-tokio")]
+    {
+        if ::tokio::runtime::Handle::try_current().is_ok() {
+            return Some(Arc::new(self::tokio::TokioRuntime));
+        }
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    {
+        return Some(Arc::new(self::async_std::AsyncStdRuntime));
+    }
+
+    #[cfg(feature = "runtime-smol")]
+    {
+        return Some(Arc::new(self::smol::SmolRuntime));
+    }
+
+    None
+}
+
+#[cfg(any(feature = "runtime-smol", feature = "runtime-async-std"))]
+impl AsyncTimer for async_io::Timer {
+    fn reset(mut self: Pin<&mut Self>, deadline: Instant) {
+        self.set_at(deadline)
+    }
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        Future::poll(self.as_mut(), cx).map(|_| ())
+    }
+}
 
-#[cfg(not(feature = "metal-io"))]
 /// Abstract implementation of a UDP socket for runtime independence
 pub trait AsyncUdpSocket: Send + Debug + 'static {
     /// Send UDP datagrams from `transmits`, or register to be woken if sending may succeed in the
@@ -48,9 +139,338 @@ pub trait AsyncUdpSocket: Send + Debug + 'static {
         meta: &mut [RecvMeta],
     ) -> Poll<io::Result<usize>>;
 
+    /// Wait until sending may succeed, registering to be woken otherwise
+    ///
+    /// Readiness is a hint: a subsequent [`try_send`](Self::try_send) may still fail with
+    /// [`io::ErrorKind::WouldBlock`], after which readiness must be polled again.
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    /// Wait until receiving may succeed, registering to be woken otherwise
+    ///
+    /// Readiness is a hint: a subsequent [`try_recv`](Self::try_recv) may still fail with
+    /// [`io::ErrorKind::WouldBlock`], after which readiness must be polled again.
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    /// Send UDP datagrams from `transmits` without waiting, failing with
+    /// [`io::ErrorKind::WouldBlock`] if none can be sent right now
+    fn try_send(&self, capabilities: &Capabilities, transmits: &[Transmit]) -> io::Result<usize>;
+
+    /// Receive UDP datagrams without waiting, failing with [`io::ErrorKind::WouldBlock`] if none
+    /// are available right now
+    fn try_recv(&self, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> io::Result<usize>;
+
     /// Look up the local IP address and port used by this socket
     fn local_addr(&self) -> io::Result<SocketAddr>;
 
     /// Look up the peer IP address and port used by this socket
     fn peer_addr(&self) -> io::Result<SocketAddr>;
 }
+
+macro_rules! impl_async_udp_socket_for_pointer {
+    ($($pointer:ident: $bound:path),*) => {$(
+        impl<T: AsyncUdpSocket + $bound + ?Sized> AsyncUdpSocket for $pointer<T> {
+            fn poll_send(
+                &self,
+                cx: &mut Context<'_>,
+                capabilities: &Capabilities,
+                transmits: &[Transmit],
+            ) -> Poll<Result<usize, io::Error>> {
+                (**self).poll_send(cx, capabilities, transmits)
+            }
+
+            fn poll_recv(
+                &self,
+                cx: &mut Context<'_>,
+                bufs: &mut [IoSliceMut<'_>],
+                meta: &mut [RecvMeta],
+            ) -> Poll<io::Result<usize>> {
+                (**self).poll_recv(cx, bufs, meta)
+            }
+
+            fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                (**self).poll_writable(cx)
+            }
+
+            fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                (**self).poll_readable(cx)
+            }
+
+            fn try_send(
+                &self,
+                capabilities: &Capabilities,
+                transmits: &[Transmit],
+            ) -> io::Result<usize> {
+                (**self).try_send(capabilities, transmits)
+            }
+
+            fn try_recv(
+                &self,
+                bufs: &mut [IoSliceMut<'_>],
+                meta: &mut [RecvMeta],
+            ) -> io::Result<usize> {
+                (**self).try_recv(bufs, meta)
+            }
+
+            fn local_addr(&self) -> io::Result<SocketAddr> {
+                (**self).local_addr()
+            }
+
+            fn peer_addr(&self) -> io::Result<SocketAddr> {
+                (**self).peer_addr()
+            }
+        }
+    )*};
+}
+
+impl_async_udp_socket_for_pointer!(Arc: Sync, Box: Send);
+
+/// Implements the socket options which the `UdpSocket`s of all runtimes set the same way, on the
+/// socket in their `io` field
+#[cfg(any(
+    feature = "runtime-smol",
+    feature = "runtime-async-std",
+    feature = "runtime-tokio"
+))]
+macro_rules! impl_socket_options {
+    ($socket:ident) => {
+        impl $socket {
+            /// Joins the IPv4 multicast `group` on the interface with the address `interface`
+            ///
+            /// Datagrams from any source are received.
+            /// [`Ipv4Addr::UNSPECIFIED`](std::net::Ipv4Addr::UNSPECIFIED) lets the system pick the
+            /// interface.
+            ///
+            /// Once a socket joined a group, Linux no longer delivers it the groups which only other
+            /// sockets on the host joined, like other platforms.
+            pub fn join_multicast_v4(
+                &self,
+                group: std::net::Ipv4Addr,
+                interface: std::net::Ipv4Addr,
+            ) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::join_v4(io.into(), group, interface))
+            }
+
+            pub fn leave_multicast_v4(
+                &self,
+                group: std::net::Ipv4Addr,
+                interface: std::net::Ipv4Addr,
+            ) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::leave_v4(io.into(), group, interface))
+            }
+
+            /// Joins the IPv6 multicast `group` on the interface with the index `interface`
+            ///
+            /// Datagrams from any source are received. `0` lets the system pick the interface.
+            ///
+            /// Once a socket joined a group, Linux no longer delivers it the groups which only other
+            /// sockets on the host joined, like other platforms.
+            pub fn join_multicast_v6(
+                &self,
+                group: &std::net::Ipv6Addr,
+                interface: u32,
+            ) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::join_v6(io.into(), group, interface))
+            }
+
+            pub fn leave_multicast_v6(
+                &self,
+                group: &std::net::Ipv6Addr,
+                interface: u32,
+            ) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::leave_v6(io.into(), group, interface))
+            }
+
+            /// Joins the IPv4 multicast `group` on the interface with the address `interface`, only
+            /// receiving datagrams sent by `source`
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "macos",
+                target_os = "ios"
+            ))]
+            pub fn join_ssm_v4(
+                &self,
+                source: std::net::Ipv4Addr,
+                group: std::net::Ipv4Addr,
+                interface: std::net::Ipv4Addr,
+            ) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::join_ssm_v4(io.into(), source, group, interface))
+            }
+
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "macos",
+                target_os = "ios"
+            ))]
+            pub fn leave_ssm_v4(
+                &self,
+                source: std::net::Ipv4Addr,
+                group: std::net::Ipv4Addr,
+                interface: std::net::Ipv4Addr,
+            ) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::leave_ssm_v4(io.into(), source, group, interface))
+            }
+
+            /// Joins the IPv6 multicast `group` on the interface with the index `interface`, only
+            /// receiving datagrams sent by `source`
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "macos",
+                target_os = "ios"
+            ))]
+            pub fn join_ssm_v6(
+                &self,
+                source: &std::net::Ipv6Addr,
+                group: &std::net::Ipv6Addr,
+                interface: u32,
+            ) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::join_ssm_v6(io.into(), source, group, interface))
+            }
+
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "macos",
+                target_os = "ios"
+            ))]
+            pub fn leave_ssm_v6(
+                &self,
+                source: &std::net::Ipv6Addr,
+                group: &std::net::Ipv6Addr,
+                interface: u32,
+            ) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::leave_ssm_v6(io.into(), source, group, interface))
+            }
+
+            /// Sends IPv4 multicast datagrams from the interface with the address `interface`
+            pub fn set_multicast_if_v4(
+                &self,
+                interface: std::net::Ipv4Addr,
+            ) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::set_if_v4(io.into(), interface))
+            }
+
+            /// Sends IPv6 multicast datagrams from the interface with the index `interface`
+            pub fn set_multicast_if_v6(&self, interface: u32) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::set_if_v6(io.into(), interface))
+            }
+
+            /// Whether IPv4 multicast datagrams sent by this socket are delivered to members on this
+            /// host
+            pub fn set_multicast_loop_v4(&self, enabled: bool) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::set_loop_v4(io.into(), enabled))
+            }
+
+            /// Whether IPv6 multicast datagrams sent by this socket are delivered to members on this
+            /// host
+            pub fn set_multicast_loop_v6(&self, enabled: bool) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::set_loop_v6(io.into(), enabled))
+            }
+
+            /// The time to live of sent IPv4 multicast datagrams, `1` keeping them on the local
+            /// network
+            pub fn set_multicast_ttl_v4(&self, ttl: u32) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::set_ttl_v4(io.into(), ttl))
+            }
+
+            /// The hop limit of sent IPv6 multicast datagrams, `1` keeping them on the local
+            /// network
+            pub fn set_multicast_hops_v6(&self, hops: u32) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::multicast::set_hops_v6(io.into(), hops))
+            }
+
+            /// Whether datagrams may be sent to broadcast addresses (`SO_BROADCAST`)
+            #[cfg(unix)]
+            pub fn set_broadcast(&self, enabled: bool) -> std::io::Result<()> {
+                self.io
+                    .with(|io| $crate::broadcast::set(io.into(), enabled))
+            }
+
+            #[cfg(unix)]
+            pub fn broadcast(&self) -> std::io::Result<bool> {
+                self.io.with(|io| $crate::broadcast::get(io.into()))
+            }
+
+            /// Sends `contents` to `port` at the broadcast address of every local interface which
+            /// supports broadcast, from the address and through the index of that interface
+            ///
+            /// Returns the number of interfaces the datagram was sent on. Interfaces which fail are
+            /// skipped, their error is only returned if no interface succeeded. Requires
+            /// [`Self::set_broadcast`].
+            #[cfg(unix)]
+            pub async fn send_broadcast(
+                &self,
+                contents: &[u8],
+                port: u16,
+            ) -> std::io::Result<usize> {
+                let mut sent = 0;
+                let mut error = None;
+                for (transmit, ifindex) in $crate::broadcast::transmits(contents, port)? {
+                    match std::future::poll_fn(|cx| {
+                        self.poll_send_on_interface(cx, &transmit, ifindex)
+                    })
+                    .await
+                    {
+                        Ok(()) => sent += 1,
+                        Err(e) => {
+                            tracing::debug!("broadcast from {:?} failed: {e}", transmit.src_ip);
+                            error.get_or_insert(e);
+                        }
+                    }
+                }
+                match (sent, error) {
+                    (0, Some(e)) => Err(e),
+                    _ => Ok(sent),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(any(
+    feature = "runtime-smol",
+    feature = "runtime-async-std",
+    feature = "runtime-tokio"
+))]
+pub(crate) use impl_socket_options;
+
+/// Waker for polls which nobody waits on
+struct NoopWaker;
+
+impl std::task::Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Runs `poll` once without a task to wake, reporting `Pending` as [`io::ErrorKind::WouldBlock`]
+///
+/// Used by sockets whose readiness is not tied to a single OS socket, such that `try_*` is most
+/// easily expressed through their `poll_*` counterpart.
+pub(crate) fn try_poll<T>(
+    poll: impl FnOnce(&mut Context<'_>) -> Poll<io::Result<T>>,
+) -> io::Result<T> {
+    let waker = std::task::Waker::from(Arc::new(NoopWaker));
+    match poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(res) => res,
+        Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    task::{Context, Poll, Waker},
+};
+
+/// Which operation a task is waiting for
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Direction {
+    Send = 0,
+    Recv = 1,
+}
+
+/// The socket of a runtime `UdpSocket`, which may be replaced while it is in use
+///
+/// Every operation works on the socket that was current when it started, so one racing with
+/// [`Rebindable::replace`] finishes on the previous socket. Tasks waiting for the previous
+/// socket are woken once it is replaced, as the wakers they registered with the reactor may
+/// never fire. The previous socket is kept until the datagrams queued on it are received.
+///
+/// Like the readiness polling of the runtimes, only the task which polled last in each direction
+/// is woken, so a socket shared between several receiving tasks may leave all but one of them
+/// waiting for the previous socket after a replacement.
+#[derive(Debug)]
+pub(crate) struct Rebindable<T> {
+    sockets: RwLock<Sockets<T>>,
+    /// Whether [`Sockets::draining`] is set, so the lock is only taken while draining
+    draining: AtomicBool,
+    /// Incremented whenever the socket is replaced
+    generation: AtomicU64,
+    wakers: Mutex<[Option<Waker>; 2]>,
+}
+
+#[derive(Debug)]
+struct Sockets<T> {
+    current: Arc<T>,
+    draining: Option<Arc<T>>,
+}
+
+impl<T> Rebindable<T> {
+    pub(crate) fn new(io: T) -> Self {
+        Self {
+            sockets: RwLock::new(Sockets {
+                current: Arc::new(io),
+                draining: None,
+            }),
+            draining: AtomicBool::new(false),
+            generation: AtomicU64::new(0),
+            wakers: Mutex::new([None, None]),
+        }
+    }
+
+    /// The current socket
+    pub(crate) fn get(&self) -> Arc<T> {
+        self.sockets.read().unwrap().current.clone()
+    }
+
+    /// Calls `f` with the current socket, which cannot be replaced until `f` returns
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.sockets.read().unwrap().current)
+    }
+
+    /// The previous socket, if datagrams may still be queued on it
+    pub(crate) fn draining(&self) -> Option<Arc<T>> {
+        if !self.draining.load(Ordering::Acquire) {
+            return None;
+        }
+        self.sockets.read().unwrap().draining.clone()
+    }
+
+    /// Drops the previous socket `io`, once no more datagrams are queued on it
+    pub(crate) fn drained(&self, io: &Arc<T>) {
+        let mut sockets = self.sockets.write().unwrap();
+        if sockets
+            .draining
+            .as_ref()
+            .is_some_and(|draining| Arc::ptr_eq(draining, io))
+        {
+            sockets.draining = None;
+            self.draining.store(false, Ordering::Release);
+        }
+    }
+
+    /// Makes `io` the current socket and wakes the tasks waiting for the previous one
+    ///
+    /// The previous socket is drained before receiving from `io`. A socket which was still being
+    /// drained is dropped along with the datagrams queued on it.
+    pub(crate) fn replace(&self, io: T) {
+        {
+            let mut sockets = self.sockets.write().unwrap();
+            let previous = mem::replace(&mut sockets.current, Arc::new(io));
+            sockets.draining = Some(previous);
+            self.draining.store(true, Ordering::Release);
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let wakers = mem::take(&mut *self.wakers.lock().unwrap());
+        for waker in wakers.into_iter().flatten() {
+            waker.wake();
+        }
+    }
+
+    /// Polls `f` with the current socket, registering to be woken if it is replaced while `f`
+    /// is pending
+    pub(crate) fn poll<R>(
+        &self,
+        cx: &mut Context<'_>,
+        direction: Direction,
+        f: impl FnOnce(&T, &mut Context<'_>) -> Poll<R>,
+    ) -> Poll<R> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let poll = self.with(|io| f(io, cx));
+        if poll.is_pending() {
+            {
+                let mut wakers = self.wakers.lock().unwrap();
+                let waker = &mut wakers[direction as usize];
+                if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                    *waker = Some(cx.waker().clone());
+                }
+            }
+            // The socket was replaced after `f` picked it, but before the waker was registered
+            if self.generation.load(Ordering::SeqCst) != generation {
+                cx.waker().wake_by_ref();
+            }
+        }
+        poll
+    }
+}
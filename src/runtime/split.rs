@@ -0,0 +1,165 @@
+use crate::runtime::AsyncUdpSocket;
+use crate::{Capabilities, RecvMeta, Transmit};
+use std::{
+    error::Error,
+    fmt,
+    future::poll_fn,
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// The sending half of a UDP socket, created by `split` or `into_split`
+///
+/// Readiness for sending is tracked separately from readiness for receiving, so the two halves
+/// can be driven from different tasks without waking each other.
+#[derive(Debug)]
+pub struct SendHalf<S> {
+    socket: Arc<S>,
+}
+
+/// The receiving half of a UDP socket, created by `split` or `into_split`
+#[derive(Debug)]
+pub struct RecvHalf<S> {
+    socket: Arc<S>,
+}
+
+/// Error returned when trying to reunite two halves that do not originate from the same socket,
+/// or whose socket is still shared elsewhere
+pub struct ReuniteError<S>(pub SendHalf<S>, pub RecvHalf<S>);
+
+pub(crate) fn split<S>(socket: Arc<S>) -> (SendHalf<S>, RecvHalf<S>) {
+    (
+        SendHalf {
+            socket: socket.clone(),
+        },
+        RecvHalf { socket },
+    )
+}
+
+fn reunite<S>(send: SendHalf<S>, recv: RecvHalf<S>) -> Result<S, ReuniteError<S>> {
+    if !Arc::ptr_eq(&send.socket, &recv.socket) {
+        return Err(ReuniteError(send, recv));
+    }
+    drop(recv);
+    Arc::try_unwrap(send.socket).map_err(|socket| {
+        let (send, recv) = split(socket);
+        ReuniteError(send, recv)
+    })
+}
+
+impl<S: AsyncUdpSocket + Sync> SendHalf<S> {
+    pub fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        self.socket.poll_send(cx, capabilities, transmits)
+    }
+
+    pub async fn send(
+        &self,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_send(cx, capabilities, transmits)).await
+    }
+
+    pub fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_writable(cx)
+    }
+
+    pub fn try_send(
+        &self,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> io::Result<usize> {
+        self.socket.try_send(capabilities, transmits)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+}
+
+impl<S> SendHalf<S> {
+    /// Joins this half with its [`RecvHalf`] to get back the original socket
+    ///
+    /// Fails if the halves were not split from the same socket, or if the socket was split from
+    /// an `Arc` which is still alive.
+    pub fn reunite(self, other: RecvHalf<S>) -> Result<S, ReuniteError<S>> {
+        reunite(self, other)
+    }
+}
+
+impl<S: AsyncUdpSocket + Sync> RecvHalf<S> {
+    pub fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        self.socket.poll_recv(cx, bufs, meta)
+    }
+
+    pub async fn recv(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_recv(cx, bufs, meta)).await
+    }
+
+    pub fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_readable(cx)
+    }
+
+    pub fn try_recv(
+        &self,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        self.socket.try_recv(bufs, meta)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+}
+
+impl<S> RecvHalf<S> {
+    /// Joins this half with its [`SendHalf`] to get back the original socket
+    ///
+    /// Fails if the halves were not split from the same socket, or if the socket was split from
+    /// an `Arc` which is still alive.
+    pub fn reunite(self, other: SendHalf<S>) -> Result<S, ReuniteError<S>> {
+        reunite(other, self)
+    }
+}
+
+impl<S> fmt::Debug for ReuniteError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReuniteError").finish_non_exhaustive()
+    }
+}
+
+impl<S> fmt::Display for ReuniteError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tried to reunite halves that are not from the same socket or are still shared"
+        )
+    }
+}
+
+impl<S> Error for ReuniteError<S> {}
@@ -0,0 +1,216 @@
+use crate::{AsyncUdpSocket, Capabilities, RecvMeta, Transmit, BATCH_SIZE};
+use bytes::Bytes;
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::{
+    collections::VecDeque,
+    io::{self, IoSliceMut},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// Size of each receive buffer used by [`UdpStream::new`], large enough for any UDP payload or
+/// GRO batch
+const DEFAULT_BUFFER_SIZE: usize = u16::MAX as usize;
+
+/// A [`Stream`] of datagrams received from an [`AsyncUdpSocket`]
+///
+/// Up to [`BATCH_SIZE`] buffers, or the count given to [`UdpStream::with_buffers`], are filled with a single [`AsyncUdpSocket::poll_recv`] call.
+/// Buffers holding several GRO segments are split up, so every item is a single datagram whose
+/// [`RecvMeta::len`] and [`RecvMeta::stride`] equal the length of the [`Bytes`]. Empty datagrams
+/// are yielded as empty [`Bytes`].
+///
+/// The receive buffers are allocated on the first poll and reused, each datagram is copied out
+/// into a [`Bytes`] of its own size.
+#[derive(Debug)]
+pub struct UdpStream<S> {
+    socket: S,
+    buffer_size: usize,
+    buffer_count: usize,
+    storage: Vec<u8>,
+    meta: Vec<RecvMeta>,
+    pending: VecDeque<(RecvMeta, Bytes)>,
+}
+
+impl<S: AsyncUdpSocket> UdpStream<S> {
+    pub fn new(socket: S) -> Self {
+        Self::with_buffer_size(socket, DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Creates a stream receiving into buffers of `buffer_size` bytes
+    ///
+    /// When GRO is enabled, `buffer_size` must be large enough to hold
+    /// [`Capabilities::gro_segments`] datagrams.
+    pub fn with_buffer_size(socket: S, buffer_size: usize) -> Self {
+        Self::with_buffers(socket, buffer_size, BATCH_SIZE)
+    }
+
+    /// Creates a stream receiving into `buffer_count` buffers of `buffer_size` bytes
+    ///
+    /// `buffer_count` bounds how many datagrams are received with a single
+    /// [`AsyncUdpSocket::poll_recv`] call. It is clamped to `1..=BATCH_SIZE`, so a stream
+    /// allocates at most `BATCH_SIZE * buffer_size` bytes.
+    pub fn with_buffers(socket: S, buffer_size: usize, buffer_count: usize) -> Self {
+        let buffer_count = buffer_count.clamp(1, BATCH_SIZE);
+        Self {
+            socket,
+            buffer_size,
+            buffer_count,
+            storage: Vec::new(),
+            meta: vec![RecvMeta::default(); buffer_count],
+            pending: VecDeque::with_capacity(buffer_count),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.socket
+    }
+
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+}
+
+// The socket is never pinned, it is only polled through `&S`
+impl<S> Unpin for UdpStream<S> {}
+
+impl<S: AsyncUdpSocket> Stream for UdpStream<S> {
+    type Item = io::Result<(RecvMeta, Bytes)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(item) = this.pending.pop_front() {
+            return Poll::Ready(Some(Ok(item)));
+        }
+
+        if this.storage.is_empty() {
+            this.storage = vec![0; this.buffer_count * this.buffer_size];
+        }
+        let msg_count = {
+            let mut bufs = this
+                .storage
+                .chunks_mut(this.buffer_size)
+                .map(IoSliceMut::new)
+                .collect::<Vec<_>>();
+            match ready!(this.socket.poll_recv(cx, &mut bufs, &mut this.meta)) {
+                Ok(n) => n,
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        };
+
+        for (i, meta) in this.meta.iter().enumerate().take(msg_count) {
+            let start = i * this.buffer_size;
+            let buf = Bytes::copy_from_slice(&this.storage[start..start + meta.len]);
+            if buf.is_empty() {
+                this.pending.push_back((
+                    RecvMeta {
+                        len: 0,
+                        stride: 0,
+                        ..*meta
+                    },
+                    buf,
+                ));
+                continue;
+            }
+            let stride = meta.stride.max(1);
+            let mut offset = 0;
+            while offset < buf.len() {
+                let datagram = buf.slice(offset..(offset + stride).min(buf.len()));
+                offset += datagram.len();
+                this.pending.push_back((
+                    RecvMeta {
+                        len: datagram.len(),
+                        stride: datagram.len(),
+                        ..*meta
+                    },
+                    datagram,
+                ));
+            }
+        }
+
+        match this.pending.pop_front() {
+            Some(item) => Poll::Ready(Some(Ok(item))),
+            None => {
+                // The socket reported readiness without returning any datagrams
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// A [`Sink`] sending [`Transmit`]s through an [`AsyncUdpSocket`]
+///
+/// Up to [`BATCH_SIZE`] transmits are buffered and handed to [`AsyncUdpSocket::poll_send`]
+/// together, so they are sent with a single system call where the platform supports it.
+/// Flushing fails with [`io::ErrorKind::WriteZero`] if the socket is ready but accepts none of
+/// them.
+#[derive(Debug)]
+pub struct UdpSink<S> {
+    socket: S,
+    capabilities: Arc<Capabilities>,
+    buffer: Vec<Transmit>,
+}
+
+impl<S: AsyncUdpSocket> UdpSink<S> {
+    pub fn new(socket: S, capabilities: Arc<Capabilities>) -> Self {
+        Self {
+            socket,
+            capabilities,
+            buffer: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.socket
+    }
+
+    /// Returns the socket, dropping any transmits which have not been flushed yet
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+
+    fn poll_flush_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.buffer.is_empty() {
+            let n = ready!(self.socket.poll_send(cx, &self.capabilities, &self.buffer))?;
+            if n == 0 {
+                // Retrying would spin, the socket made no progress without being pending
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "socket accepted no transmits",
+                )));
+            }
+            self.buffer.drain(..n.min(self.buffer.len()));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+// The socket is never pinned, it is only polled through `&S`
+impl<S> Unpin for UdpSink<S> {}
+
+impl<S: AsyncUdpSocket> Sink<Transmit> for UdpSink<S> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.buffer.len() >= BATCH_SIZE {
+            ready!(this.poll_flush_buffer(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Transmit) -> io::Result<()> {
+        self.get_mut().buffer.push(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_buffer(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_buffer(cx)
+    }
+}
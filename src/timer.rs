@@ -0,0 +1,110 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{Arc, Condvar, Mutex, OnceLock},
+    task::Waker,
+    thread,
+    time::Instant,
+};
+
+/// Wakes `waker` once `deadline` has passed
+///
+/// Used by types which need to delay work without depending on the timer of a particular
+/// runtime. All deadlines are served by a single background thread, started on first use.
+fn wake_at(deadline: Instant, waker: Waker) {
+    let timer = TIMER.get_or_init(|| {
+        let timer = Arc::new(Timer::default());
+        let background = timer.clone();
+        thread::Builder::new()
+            .name("async-transport-timer".into())
+            .spawn(move || background.run())
+            .expect("failed to spawn timer thread");
+        timer
+    });
+    let mut entries = timer.entries.lock().unwrap();
+    let earliest = entries.peek().map(|entry| entry.deadline);
+    entries.push(Entry { deadline, waker });
+    match earliest {
+        // The timer thread already wakes up early enough
+        Some(earliest) if earliest <= deadline => {}
+        _ => timer.changed.notify_one(),
+    }
+}
+
+/// The deadline last registered with [`wake_at`] for one direction of a socket
+///
+/// Tasks poll again and again while they wait, so only deadlines which the registered one does
+/// not already cover are handed to the timer.
+#[derive(Debug, Default)]
+pub(crate) struct Deadline {
+    registered: Option<(Instant, Waker)>,
+}
+
+impl Deadline {
+    /// Wakes `waker` once `deadline` has passed, unless the same task is already woken earlier
+    pub(crate) fn wake_at(&mut self, deadline: Instant, waker: &Waker) {
+        if let Some((registered, registered_waker)) = &self.registered {
+            if *registered <= deadline
+                && *registered > Instant::now()
+                && registered_waker.will_wake(waker)
+            {
+                return;
+            }
+        }
+        self.registered = Some((deadline, waker.clone()));
+        wake_at(deadline, waker.clone());
+    }
+}
+
+static TIMER: OnceLock<Arc<Timer>> = OnceLock::new();
+
+#[derive(Default)]
+struct Timer {
+    entries: Mutex<BinaryHeap<Entry>>,
+    changed: Condvar,
+}
+
+impl Timer {
+    fn run(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            while entries.peek().is_some_and(|entry| entry.deadline <= now) {
+                entries.pop().unwrap().waker.wake();
+            }
+            entries = match entries.peek() {
+                Some(entry) => {
+                    let timeout = entry.deadline - now;
+                    self.changed.wait_timeout(entries, timeout).unwrap().0
+                }
+                None => self.changed.wait(entries).unwrap(),
+            };
+        }
+    }
+}
+
+struct Entry {
+    deadline: Instant,
+    waker: Waker,
+}
+
+// Ordered so that the earliest deadline is at the top of the max-heap
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for Entry {}
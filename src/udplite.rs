@@ -0,0 +1,102 @@
+use std::{io, mem, net::SocketAddr, os::unix::io::AsRawFd};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use super::{Transmit, UdpSockRef};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const UDPLITE_SEND_CSCOV: libc::c_int = 10;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const UDPLITE_RECV_CSCOV: libc::c_int = 11;
+#[cfg(target_os = "freebsd")]
+const UDPLITE_SEND_CSCOV: libc::c_int = 2;
+#[cfg(target_os = "freebsd")]
+const UDPLITE_RECV_CSCOV: libc::c_int = 4;
+
+/// Creates a UDP-Lite socket bound to `addr`
+///
+/// The result is handed to the runtimes as a [`std::net::UdpSocket`], which it behaves like
+/// apart from the checksum coverage.
+pub(crate) fn bind(addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(addr),
+        Type::DGRAM,
+        Some(Protocol::from(libc::IPPROTO_UDPLITE)),
+    )?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// Rejects transmits relying on segmentation offload, which UDP-Lite does not support
+///
+/// The kernel fails such sends with `EIO`, which would disable GSO for all sockets.
+pub(crate) fn check_transmits(transmits: &[Transmit]) -> io::Result<()> {
+    match transmits.iter().any(|t| t.segment_size.is_some()) {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "UDP-Lite does not support segmentation offload",
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Sets how many bytes, starting at the UDP-Lite header, are covered by the checksum of sent
+/// datagrams; `0` covers the whole datagram
+pub(crate) fn set_send_checksum_coverage(socket: UdpSockRef<'_>, coverage: u16) -> io::Result<()> {
+    set_coverage(socket, UDPLITE_SEND_CSCOV, coverage)
+}
+
+pub(crate) fn send_checksum_coverage(socket: UdpSockRef<'_>) -> io::Result<u16> {
+    coverage(socket, UDPLITE_SEND_CSCOV)
+}
+
+/// Sets the minimum checksum coverage received datagrams need to have; others are dropped
+pub(crate) fn set_recv_checksum_coverage(socket: UdpSockRef<'_>, coverage: u16) -> io::Result<()> {
+    set_coverage(socket, UDPLITE_RECV_CSCOV, coverage)
+}
+
+pub(crate) fn recv_checksum_coverage(socket: UdpSockRef<'_>) -> io::Result<u16> {
+    coverage(socket, UDPLITE_RECV_CSCOV)
+}
+
+fn set_coverage(socket: UdpSockRef<'_>, name: libc::c_int, coverage: u16) -> io::Result<()> {
+    // The header is always covered, so smaller values are rejected rather than rounded up
+    if coverage != 0 && coverage < 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "checksum coverage must include the 8 byte header",
+        ));
+    }
+    let value = libc::c_int::from(coverage);
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.0.as_raw_fd(),
+            libc::IPPROTO_UDPLITE,
+            name,
+            &value as *const _ as _,
+            mem::size_of_val(&value) as _,
+        )
+    };
+    match rc == 0 {
+        true => Ok(()),
+        false => Err(io::Error::last_os_error()),
+    }
+}
+
+fn coverage(socket: UdpSockRef<'_>, name: libc::c_int) -> io::Result<u16> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of_val(&value) as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            socket.0.as_raw_fd(),
+            libc::IPPROTO_UDPLITE,
+            name,
+            &mut value as *mut _ as _,
+            &mut len,
+        )
+    };
+    match rc == 0 {
+        true => Ok(value as u16),
+        false => Err(io::Error::last_os_error()),
+    }
+}
@@ -6,15 +6,25 @@ use std::{
     mem::{self, MaybeUninit},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     os::unix::io::AsRawFd,
-    sync::atomic::{AtomicU64, AtomicUsize},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
     time::Instant,
 };
+#[cfg(target_os = "linux")]
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use socket2::SockRef;
 
+#[cfg(target_os = "linux")]
+use super::{busy_poll, BusyPollConfig};
 use super::{
-    cmsg, log_sendmsg_error, Capabilities, EcnCodepoint, RecvMeta, Transmit, UdpSockRef,
-    IO_ERROR_LOG_INTERVAL,
+    cmsg, log_sendmsg_error, not_connected, to_canonical, to_mapped, Capabilities, EcnCodepoint,
+    RecvMeta, TimestampModes, Transmit, UdpSockRef, IO_ERROR_LOG_INTERVAL,
 };
 
 #[cfg(target_os = "freebsd")]
@@ -30,6 +40,18 @@ type IpTosTy = libc::c_int;
 pub struct UdpSocketState {
     epoch: Instant,
     last_send_error: AtomicU64,
+    canonicalize_mapped_ipv4: AtomicBool,
+    peer: RwLock<Option<SocketAddr>>,
+    /// The address the socket was bound to before it was connected, wildcard addresses included
+    bound: Mutex<Option<SocketAddr>>,
+    /// How long [`UdpSocketState::poll_recv_spinning`] spins, in nanoseconds
+    #[cfg(target_os = "linux")]
+    busy_poll_spin: AtomicU64,
+    /// When the current spin ends
+    #[cfg(target_os = "linux")]
+    spin_deadline: Mutex<Option<Instant>>,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    uring: Option<crate::uring::Uring>,
 }
 
 impl UdpSocketState {
@@ -38,6 +60,15 @@ impl UdpSocketState {
         Self {
             epoch: now.checked_sub(2 * IO_ERROR_LOG_INTERVAL).unwrap_or(now),
             last_send_error: AtomicU64::new(0),
+            canonicalize_mapped_ipv4: AtomicBool::new(false),
+            peer: RwLock::new(None),
+            bound: Mutex::new(None),
+            #[cfg(target_os = "linux")]
+            busy_poll_spin: AtomicU64::new(0),
+            #[cfg(target_os = "linux")]
+            spin_deadline: Mutex::new(None),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            uring: None,
         }
     }
 
@@ -45,17 +76,126 @@ impl UdpSocketState {
         init(sock.0)
     }
 
+    /// Reports IPv4 peers of a dual-stack IPv6 socket as [`SocketAddr::V4`]
+    ///
+    /// When enabled, [`RecvMeta::addr`] and [`RecvMeta::dst_ip`] hold plain IPv4 addresses
+    /// instead of IPv4-mapped IPv6 addresses, and IPv4 [`Transmit::destination`]s and
+    /// [`Transmit::src_ip`]s are mapped back before sending. This has no effect on IPv4 sockets.
+    pub fn set_canonicalize_mapped_ipv4(
+        &self,
+        sock: UdpSockRef<'_>,
+        enabled: bool,
+    ) -> io::Result<()> {
+        let is_ipv6 = sock.0.local_addr()?.family() == libc::AF_INET6 as libc::sa_family_t;
+        self.canonicalize_mapped_ipv4
+            .store(enabled && is_ipv6, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Whether IPv4-mapped IPv6 addresses are converted to plain IPv4 addresses
+    pub fn canonicalize_mapped_ipv4(&self) -> bool {
+        self.canonicalize_mapped_ipv4.load(Ordering::Relaxed)
+    }
+
+    /// Connects the socket to `addr`
+    ///
+    /// Afterwards, transmits without a [`Transmit::destination`] are sent to `addr`, and the
+    /// source address of received datagrams is no longer decoded since it is always `addr`.
+    pub fn connect(&self, sock: UdpSockRef<'_>, addr: SocketAddr) -> io::Result<()> {
+        let target = match self.canonicalize_mapped_ipv4() {
+            true => to_mapped(addr),
+            false => addr,
+        };
+        let mut peer = self.peer.write().unwrap();
+        if peer.is_none() {
+            *self.bound.lock().unwrap() = sock.0.local_addr()?.as_socket();
+        }
+        sock.0.connect(&target.into())?;
+        *peer = Some(addr);
+        Ok(())
+    }
+
+    /// Dissolves the association set up by [`UdpSocketState::connect`]
+    ///
+    /// Linux releases a port picked by the kernel when disconnecting, and resets an address
+    /// picked by the kernel to the wildcard address. The socket is bound to the address it had
+    /// before it was connected again, so it stays reachable on the same port. That fails with
+    /// [`io::ErrorKind::AddrInUse`] if another socket took the port in the meantime, in which
+    /// case the socket is disconnected, but unbound.
+    pub fn disconnect(&self, sock: UdpSockRef<'_>) -> io::Result<()> {
+        let mut peer = self.peer.write().unwrap();
+        let mut addr: libc::sockaddr = unsafe { mem::zeroed() };
+        addr.sa_family = libc::AF_UNSPEC as _;
+        let rc = unsafe {
+            libc::connect(
+                sock.0.as_raw_fd(),
+                &addr,
+                mem::size_of_val(&addr) as libc::socklen_t,
+            )
+        };
+        if rc == -1 {
+            let e = io::Error::last_os_error();
+            // BSDs report EAFNOSUPPORT, but dissolve the association nonetheless
+            if cfg!(target_os = "linux") || e.raw_os_error() != Some(libc::EAFNOSUPPORT) {
+                return Err(e);
+            }
+        }
+        *peer = None;
+        let bound = self.bound.lock().unwrap().take();
+        if let Some(bound) = bound.filter(|bound| bound.port() != 0) {
+            if sock.0.local_addr()?.as_socket().map(|addr| addr.port()) == Some(0) {
+                sock.0.bind(&bound.into())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The address the socket is connected to, if any
+    pub fn peer(&self) -> Option<SocketAddr> {
+        *self.peer.read().unwrap()
+    }
+
+    /// Sends and receives through io_uring from now on
+    ///
+    /// Datagrams are then received in the background, so [`UdpSocketState::recv`] must wait
+    /// for [`UdpSocketState::io_uring_fd`] to become readable instead of the socket. Sending
+    /// still waits for the socket to become writable. Fails without side effects where
+    /// io_uring is unavailable, in which case the socket keeps using `sendmmsg`/`recvmmsg`.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn enable_io_uring(&mut self, sock: UdpSockRef<'_>) -> io::Result<()> {
+        self.uring = Some(crate::uring::Uring::new(sock.0)?);
+        Ok(())
+    }
+
+    /// The file descriptor of the io_uring instance, if enabled
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn io_uring_fd(&self) -> Option<std::os::fd::BorrowedFd<'_>> {
+        self.uring.as_ref().map(|uring| uring.as_fd())
+    }
+
     pub fn send(
         &self,
         socket: UdpSockRef<'_>,
         capabilities: &Capabilities,
         transmits: &[Transmit],
     ) -> Result<usize, io::Error> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(uring) = &self.uring {
+            return uring.send(
+                &self.epoch,
+                &self.last_send_error,
+                self.canonicalize_mapped_ipv4(),
+                self.peer(),
+                transmits,
+            );
+        }
         send(
             capabilities,
             socket.0,
             &self.epoch,
             &self.last_send_error,
+            self.canonicalize_mapped_ipv4(),
+            self.peer(),
             transmits,
         )
     }
@@ -66,7 +206,131 @@ impl UdpSocketState {
         bufs: &mut [IoSliceMut<'_>],
         meta: &mut [RecvMeta],
     ) -> io::Result<usize> {
-        recv(socket.0, bufs, meta)
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(uring) = &self.uring {
+            return uring.recv(bufs, meta, self.canonicalize_mapped_ipv4(), self.peer());
+        }
+        recv(
+            socket.0,
+            bufs,
+            meta,
+            self.canonicalize_mapped_ipv4(),
+            self.peer(),
+        )
+    }
+
+    /// Sends a single datagram from the interface with the index `ifindex`
+    ///
+    /// The index is encoded along with [`Transmit::src_ip`], which must be set. IPv4 datagrams
+    /// only honor the index on Linux, other platforms pick the interface by the source address.
+    /// Unlike [`UdpSocketState::send`], errors are reported rather than swallowed, and
+    /// [`Transmit::segment_size`] is ignored.
+    pub fn send_on_interface(
+        &self,
+        socket: UdpSockRef<'_>,
+        transmit: &Transmit,
+        ifindex: u32,
+    ) -> io::Result<()> {
+        let (dst_addr, encode_dst_addr) =
+            destination(transmit, self.canonicalize_mapped_ipv4(), self.peer())?;
+        let dst_addr = socket2::SockAddr::from(dst_addr);
+        let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+        let mut iov: libc::iovec = unsafe { mem::zeroed() };
+        let mut ctrl = cmsg::Aligned([0u8; CMSG_LEN]);
+        iov.iov_base = transmit.contents.as_ptr() as *const _ as *mut _;
+        iov.iov_len = transmit.contents.len();
+        if encode_dst_addr {
+            hdr.msg_name = dst_addr.as_ptr() as *mut _;
+            hdr.msg_namelen = dst_addr.len();
+        }
+        hdr.msg_iov = &mut iov;
+        hdr.msg_iovlen = 1;
+        hdr.msg_control = ctrl.0.as_mut_ptr() as _;
+        hdr.msg_controllen = CMSG_LEN as _;
+        let mut encoder = unsafe { cmsg::Encoder::new(&mut hdr) };
+        encode_ip_cmsgs(
+            &mut encoder,
+            &dst_addr,
+            transmit.ecn,
+            transmit.src_ip,
+            true,
+            ifindex,
+        );
+        encoder.finish();
+
+        loop {
+            let n = unsafe { libc::sendmsg(socket.0.as_raw_fd(), &hdr, 0) };
+            if n == -1 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            return Ok(());
+        }
+    }
+
+    /// Applies the busy polling options of `config` to `socket`, and makes
+    /// [`UdpSocketState::poll_recv_spinning`] spin for [`BusyPollConfig::spin`]
+    #[cfg(target_os = "linux")]
+    pub fn set_busy_poll(&self, socket: UdpSockRef<'_>, config: &BusyPollConfig) -> io::Result<()> {
+        busy_poll::set(socket, config)?;
+        let spin = config.spin.as_nanos().min(u64::MAX as u128) as u64;
+        self.busy_poll_spin.store(spin, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The busy polling options of `socket`, and the time spent spinning
+    ///
+    /// [`BusyPollConfig::budget`] cannot be read back and is always `None`.
+    #[cfg(target_os = "linux")]
+    pub fn busy_poll(&self, socket: UdpSockRef<'_>) -> io::Result<BusyPollConfig> {
+        Ok(BusyPollConfig {
+            spin: self.busy_poll_spin(),
+            ..busy_poll::get(socket)?
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn busy_poll_spin(&self) -> Duration {
+        Duration::from_nanos(self.busy_poll_spin.load(Ordering::Relaxed))
+    }
+
+    /// Tries to receive, and while the spin time has not passed since the first attempt that
+    /// found nothing, arranges to be polled again right away
+    ///
+    /// The socket must be non-blocking. Each call makes a single attempt, so other tasks keep
+    /// running while a task spins. Returns `None` once the spin time has passed without
+    /// datagrams, or if no spin time is configured, after which the caller should wait for
+    /// readiness as usual.
+    #[cfg(target_os = "linux")]
+    pub fn poll_recv_spinning(
+        &self,
+        cx: &mut Context<'_>,
+        socket: UdpSockRef<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Option<Poll<io::Result<usize>>> {
+        let spin = self.busy_poll_spin();
+        if spin.is_zero() {
+            return None;
+        }
+        let mut deadline = self.spin_deadline.lock().unwrap();
+        match self.recv(socket, bufs, meta) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            res => {
+                *deadline = None;
+                return Some(Poll::Ready(res));
+            }
+        }
+        let now = Instant::now();
+        if *deadline.get_or_insert(now + spin) <= now {
+            *deadline = None;
+            return None;
+        }
+        cx.waker().wake_by_ref();
+        Some(Poll::Pending)
     }
 }
 
@@ -160,6 +424,8 @@ fn send(
     io: SockRef<'_>,
     epoch: &Instant,
     last_send_error: &AtomicU64,
+    map_ipv4: bool,
+    peer: Option<SocketAddr>,
     transmits: &[Transmit],
 ) -> io::Result<usize> {
     #[allow(unused_mut)] // only mutable on FeeBSD
@@ -185,24 +451,31 @@ fn send(
     // TODO: Replace this with uninit_array once it becomes MSRV-stable
     let mut addrs: [MaybeUninit<socket2::SockAddr>; BATCH_SIZE] =
         unsafe { MaybeUninit::uninit().assume_init() };
+    let mut num_transmits = transmits.len().min(BATCH_SIZE);
     for (i, transmit) in transmits.iter().enumerate().take(BATCH_SIZE) {
+        let (dst_addr, encode_dst_addr) = match destination(transmit, map_ipv4, peer) {
+            Ok(destination) => destination,
+            Err(e) if i == 0 => return Err(e),
+            // Sent the valid transmits, the next call reports the error
+            Err(_) => {
+                num_transmits = i;
+                break;
+            }
+        };
         let dst_addr = unsafe {
-            ptr::write(
-                addrs[i].as_mut_ptr(),
-                socket2::SockAddr::from(transmit.destination),
-            );
+            ptr::write(addrs[i].as_mut_ptr(), socket2::SockAddr::from(dst_addr));
             &*addrs[i].as_ptr()
         };
         prepare_msg(
             transmit,
             dst_addr,
+            encode_dst_addr,
             &mut msgs[i].msg_hdr,
             &mut iovecs[i],
             &mut cmsgs[i],
             encode_src_ip,
         );
     }
-    let num_transmits = transmits.len().min(BATCH_SIZE);
 
     loop {
         let n = unsafe { libc::sendmmsg(io.as_raw_fd(), msgs.as_mut_ptr(), num_transmits as _, 0) };
@@ -256,6 +529,8 @@ fn send(
     io: SockRef<'_>,
     epoch: &Instant,
     last_send_error: &AtomicU64,
+    map_ipv4: bool,
+    peer: Option<SocketAddr>,
     transmits: &[Transmit],
 ) -> io::Result<usize> {
     let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
@@ -264,10 +539,16 @@ fn send(
     let mut sent = 0;
 
     while sent < transmits.len() {
-        let addr = socket2::SockAddr::from(transmits[sent].destination);
+        let (addr, encode_addr) = match destination(&transmits[sent], map_ipv4, peer) {
+            Ok(destination) => destination,
+            Err(e) if sent == 0 => return Err(e),
+            Err(_) => return Ok(sent),
+        };
+        let addr = socket2::SockAddr::from(addr);
         prepare_msg(
             &transmits[sent],
             &addr,
+            encode_addr,
             &mut hdr,
             &mut iov,
             &mut ctrl,
@@ -302,7 +583,13 @@ fn send(
 }
 
 #[cfg(not(any(target_os = "macos", target_os = "ios")))]
-fn recv(io: SockRef<'_>, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> io::Result<usize> {
+fn recv(
+    io: SockRef<'_>,
+    bufs: &mut [IoSliceMut<'_>],
+    meta: &mut [RecvMeta],
+    map_ipv4: bool,
+    peer: Option<SocketAddr>,
+) -> io::Result<usize> {
     let mut names = [MaybeUninit::<libc::sockaddr_storage>::uninit(); BATCH_SIZE];
     let mut ctrls = [cmsg::Aligned(MaybeUninit::<[u8; CMSG_LEN]>::uninit()); BATCH_SIZE];
     let mut hdrs = unsafe { mem::zeroed::<[libc::mmsghdr; BATCH_SIZE]>() };
@@ -313,6 +600,7 @@ fn recv(io: SockRef<'_>, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) ->
             &mut names[i],
             &mut ctrls[i],
             &mut hdrs[i].msg_hdr,
+            peer.is_none(),
         );
     }
     let msg_count = loop {
@@ -335,17 +623,29 @@ fn recv(io: SockRef<'_>, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) ->
         break n;
     };
     for i in 0..(msg_count as usize) {
-        meta[i] = decode_recv(&names[i], &hdrs[i].msg_hdr, hdrs[i].msg_len as usize);
+        meta[i] = decode_recv(
+            &names[i],
+            &hdrs[i].msg_hdr,
+            hdrs[i].msg_len as usize,
+            map_ipv4,
+            peer,
+        );
     }
     Ok(msg_count as usize)
 }
 
 #[cfg(any(target_os = "macos", target_os = "ios"))]
-fn recv(io: SockRef<'_>, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> io::Result<usize> {
+fn recv(
+    io: SockRef<'_>,
+    bufs: &mut [IoSliceMut<'_>],
+    meta: &mut [RecvMeta],
+    map_ipv4: bool,
+    peer: Option<SocketAddr>,
+) -> io::Result<usize> {
     let mut name = MaybeUninit::<libc::sockaddr_storage>::uninit();
     let mut ctrl = cmsg::Aligned(MaybeUninit::<[u8; CMSG_LEN]>::uninit());
     let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
-    prepare_recv(&mut bufs[0], &mut name, &mut ctrl, &mut hdr);
+    prepare_recv(&mut bufs[0], &mut name, &mut ctrl, &mut hdr, peer.is_none());
     let n = loop {
         let n = unsafe { libc::recvmsg(io.as_raw_fd(), &mut hdr, 0) };
         if n == -1 {
@@ -360,7 +660,7 @@ fn recv(io: SockRef<'_>, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) ->
         }
         break n;
     };
-    meta[0] = decode_recv(&name, &hdr, n as usize);
+    meta[0] = decode_recv(&name, &hdr, n as usize, map_ipv4, peer);
     Ok(1)
 }
 
@@ -369,18 +669,26 @@ pub fn capabilities() -> Capabilities {
     Capabilities {
         max_gso_segments: AtomicUsize::new(gso::max_gso_segments()),
         gro_segments: gro::gro_segments(),
+        send_ecn: probe::send_ecn(),
+        recv_ecn: probe::recv_ecn(),
+        recv_dst_ip: probe::recv_dst_ip(),
+        send_src_ip: probe::send_src_ip(),
+        timestamp_modes: probe::timestamp_modes(),
+        send_batching: BATCH_SIZE > 1,
+        recv_batching: BATCH_SIZE > 1,
+        batch_size: BATCH_SIZE,
     }
 }
 
-const CMSG_LEN: usize = 88;
+pub(crate) const CMSG_LEN: usize = 88;
 
-fn prepare_msg(
+pub(crate) fn prepare_msg(
     transmit: &Transmit,
     dst_addr: &socket2::SockAddr,
+    encode_dst_addr: bool,
     hdr: &mut libc::msghdr,
     iov: &mut libc::iovec,
     ctrl: &mut cmsg::Aligned<[u8; CMSG_LEN]>,
-    #[allow(unused_variables)] // only used on FreeBSD & macOS
     encode_src_ip: bool,
 ) {
     iov.iov_base = transmit.contents.as_ptr() as *const _ as *mut _;
@@ -391,34 +699,80 @@ fn prepare_msg(
     // as per the POSIX spec. See the section on the sys/socket.h
     // header for details. The type is only mutable in the first
     // place because it is reused by recvmsg as well.
-    let name = dst_addr.as_ptr() as *mut libc::c_void;
-    let namelen = dst_addr.len();
-    hdr.msg_name = name as *mut _;
-    hdr.msg_namelen = namelen;
+    // Connected sockets send to their peer when no name is given
+    if encode_dst_addr {
+        let name = dst_addr.as_ptr() as *mut libc::c_void;
+        let namelen = dst_addr.len();
+        hdr.msg_name = name as *mut _;
+        hdr.msg_namelen = namelen;
+    } else {
+        hdr.msg_name = ptr::null_mut();
+        hdr.msg_namelen = 0;
+    }
     hdr.msg_iov = iov;
     hdr.msg_iovlen = 1;
 
     hdr.msg_control = ctrl.0.as_mut_ptr() as _;
     hdr.msg_controllen = CMSG_LEN as _;
     let mut encoder = unsafe { cmsg::Encoder::new(hdr) };
-    let ecn = transmit.ecn.map_or(0, |x| x as libc::c_int);
-    if transmit.destination.is_ipv4() {
-        encoder.push(libc::IPPROTO_IP, libc::IP_TOS, ecn as IpTosTy);
-    } else {
-        encoder.push(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, ecn);
-    }
+    encode_ip_cmsgs(
+        &mut encoder,
+        dst_addr,
+        transmit.ecn,
+        transmit.src_ip,
+        encode_src_ip,
+        0,
+    );
 
     if let Some(segment_size) = transmit.segment_size {
         gso::set_segment_size(&mut encoder, segment_size as u16);
     }
 
-    if let Some(ip) = &transmit.src_ip {
+    encoder.finish();
+}
+
+/// Encodes the ECN codepoint and source address of a datagram sent to `dst_addr`
+///
+/// A non-zero `ifindex` pins the outgoing interface along with the source address. IPv4
+/// datagrams only honor it on Linux.
+pub(crate) fn encode_ip_cmsgs(
+    encoder: &mut cmsg::Encoder<'_>,
+    dst_addr: &socket2::SockAddr,
+    ecn: Option<EcnCodepoint>,
+    src_ip: Option<IpAddr>,
+    #[allow(unused_variables)] // only used on FreeBSD & macOS
+    encode_src_ip: bool,
+    #[allow(unused_variables)] // IPv4 only on Linux
+    ifindex: u32,
+) {
+    let ecn = ecn.map_or(0, |x| x as libc::c_int);
+    let is_ipv4 = match dst_addr.as_socket() {
+        Some(SocketAddr::V4(_)) => true,
+        // Linux sends datagrams to IPv4-mapped destinations through the IPv4 stack, which
+        // silently ignores IPV6_TCLASS
+        Some(SocketAddr::V6(addr)) => {
+            cfg!(target_os = "linux") && addr.ip().to_ipv4_mapped().is_some()
+        }
+        None => false,
+    };
+    if is_ipv4 {
+        encoder.push(libc::IPPROTO_IP, libc::IP_TOS, ecn as IpTosTy);
+    } else {
+        encoder.push(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, ecn);
+    }
+
+    let src_ip = match src_ip {
+        // The IPv6 stack accepts IPv4-mapped source addresses for IPv4-mapped destinations
+        Some(IpAddr::V4(v4)) if dst_addr.is_ipv6() => Some(IpAddr::V6(v4.to_ipv6_mapped())),
+        src_ip => src_ip,
+    };
+    if let Some(ip) = &src_ip {
         match ip {
             IpAddr::V4(v4) => {
                 #[cfg(target_os = "linux")]
                 {
                     let pktinfo = libc::in_pktinfo {
-                        ipi_ifindex: 0,
+                        ipi_ifindex: ifindex as _,
                         ipi_spec_dst: libc::in_addr {
                             s_addr: u32::from_ne_bytes(v4.octets()),
                         },
@@ -438,7 +792,7 @@ fn prepare_msg(
             }
             IpAddr::V6(v6) => {
                 let pktinfo = libc::in6_pktinfo {
-                    ipi6_ifindex: 0,
+                    ipi6_ifindex: ifindex as _,
                     ipi6_addr: libc::in6_addr {
                         s6_addr: v6.octets(),
                     },
@@ -447,8 +801,6 @@ fn prepare_msg(
             }
         }
     }
-
-    encoder.finish();
 }
 
 fn prepare_recv(
@@ -456,9 +808,15 @@ fn prepare_recv(
     name: &mut MaybeUninit<libc::sockaddr_storage>,
     ctrl: &mut cmsg::Aligned<MaybeUninit<[u8; CMSG_LEN]>>,
     hdr: &mut libc::msghdr,
+    decode_name: bool,
 ) {
-    hdr.msg_name = name.as_mut_ptr() as _;
-    hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+    if decode_name {
+        hdr.msg_name = name.as_mut_ptr() as _;
+        hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+    } else {
+        hdr.msg_name = ptr::null_mut();
+        hdr.msg_namelen = 0;
+    }
     hdr.msg_iov = buf as *mut IoSliceMut<'_> as *mut libc::iovec;
     hdr.msg_iovlen = 1;
     hdr.msg_control = ctrl.0.as_mut_ptr() as _;
@@ -466,25 +824,82 @@ fn prepare_recv(
     hdr.msg_flags = 0;
 }
 
-fn decode_recv(
+/// Returns the address a transmit is sent to, and whether it must be passed to the kernel
+///
+/// Transmits without a destination go to the peer of a connected socket. Its address is only
+/// used to pick the right control messages. Fails with [`io::ErrorKind::NotConnected`] if there
+/// is no peer either.
+pub(crate) fn destination(
+    transmit: &Transmit,
+    map_ipv4: bool,
+    peer: Option<SocketAddr>,
+) -> io::Result<(SocketAddr, bool)> {
+    let (addr, encode) = match (transmit.destination, peer) {
+        (Some(addr), _) => (addr, true),
+        (None, Some(peer)) => (peer, false),
+        (None, None) => return Err(not_connected()),
+    };
+    Ok(match map_ipv4 {
+        true => (to_mapped(addr), encode),
+        false => (addr, encode),
+    })
+}
+
+pub(crate) fn decode_recv(
     name: &MaybeUninit<libc::sockaddr_storage>,
     hdr: &libc::msghdr,
     len: usize,
+    map_ipv4: bool,
+    peer: Option<SocketAddr>,
 ) -> RecvMeta {
-    let name = unsafe { name.assume_init() };
+    let cmsgs = unsafe { decode_ip_cmsgs(hdr) };
+
+    // Connected sockets only receive datagrams from their peer
+    let addr = match peer {
+        Some(peer) => peer,
+        None => decode_name(name),
+    };
+
+    let (addr, dst_ip) = match map_ipv4 {
+        true => (to_canonical(addr), cmsgs.dst_ip.map(|ip| ip.to_canonical())),
+        false => (addr, cmsgs.dst_ip),
+    };
+
+    RecvMeta {
+        len,
+        stride: cmsgs.stride.unwrap_or(len),
+        addr,
+        ecn: cmsgs.ecn,
+        dst_ip,
+    }
+}
+
+/// IP level information carried by the control messages of a received datagram
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct IpCmsgs {
+    pub(crate) ecn: Option<EcnCodepoint>,
+    pub(crate) dst_ip: Option<IpAddr>,
+    /// TTL or hop limit the datagram arrived with, if `IP_RECVTTL`/`IPV6_RECVHOPLIMIT` is set
+    pub(crate) ttl: Option<u8>,
+    /// GRO segment size, if the datagram was coalesced
+    pub(crate) stride: Option<usize>,
+}
+
+/// # Safety
+///
+/// `hdr` must satisfy the requirements of [`cmsg::Iter::new`]
+pub(crate) unsafe fn decode_ip_cmsgs(hdr: &libc::msghdr) -> IpCmsgs {
     let mut ecn_bits = 0;
-    let mut dst_ip = None;
-    #[allow(unused_mut)] // only mutable on Linux
-    let mut stride = len;
+    let mut cmsgs = IpCmsgs::default();
 
-    let cmsg_iter = unsafe { cmsg::Iter::new(hdr) };
+    let cmsg_iter = cmsg::Iter::new(hdr);
     for cmsg in cmsg_iter {
         match (cmsg.cmsg_level, cmsg.cmsg_type) {
             // FreeBSD uses IP_RECVTOS here, and we can be liberal because cmsgs are opt-in.
-            (libc::IPPROTO_IP, libc::IP_TOS) | (libc::IPPROTO_IP, libc::IP_RECVTOS) => unsafe {
+            (libc::IPPROTO_IP, libc::IP_TOS) | (libc::IPPROTO_IP, libc::IP_RECVTOS) => {
                 ecn_bits = cmsg::decode::<u8>(cmsg);
-            },
-            (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => unsafe {
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
                 // Temporary hack around broken macos ABI. Remove once upstream fixes it.
                 // https://bugreport.apple.com/web/?problemID=48761855
                 #[allow(clippy::unnecessary_cast)] // cmsg.cmsg_len defined as size_t
@@ -495,32 +910,49 @@ fn decode_recv(
                 } else {
                     ecn_bits = cmsg::decode::<libc::c_int>(cmsg) as u8;
                 }
-            },
+            }
             #[cfg(target_os = "linux")]
             (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
-                let pktinfo = unsafe { cmsg::decode::<libc::in_pktinfo>(cmsg) };
-                dst_ip = Some(IpAddr::V4(Ipv4Addr::from(
+                let pktinfo = cmsg::decode::<libc::in_pktinfo>(cmsg);
+                cmsgs.dst_ip = Some(IpAddr::V4(Ipv4Addr::from(
                     pktinfo.ipi_addr.s_addr.to_ne_bytes(),
                 )));
             }
             #[cfg(any(target_os = "freebsd", target_os = "macos"))]
             (libc::IPPROTO_IP, libc::IP_RECVDSTADDR) => {
-                let in_addr = unsafe { cmsg::decode::<libc::in_addr>(cmsg) };
-                dst_ip = Some(IpAddr::V4(Ipv4Addr::from(in_addr.s_addr.to_ne_bytes())));
+                let in_addr = cmsg::decode::<libc::in_addr>(cmsg);
+                cmsgs.dst_ip = Some(IpAddr::V4(Ipv4Addr::from(in_addr.s_addr.to_ne_bytes())));
             }
             (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
-                let pktinfo = unsafe { cmsg::decode::<libc::in6_pktinfo>(cmsg) };
-                dst_ip = Some(IpAddr::V6(Ipv6Addr::from(pktinfo.ipi6_addr.s6_addr)));
+                let pktinfo = cmsg::decode::<libc::in6_pktinfo>(cmsg);
+                cmsgs.dst_ip = Some(IpAddr::V6(Ipv6Addr::from(pktinfo.ipi6_addr.s6_addr)));
+            }
+            #[cfg(target_os = "linux")]
+            (libc::IPPROTO_IP, libc::IP_TTL) => {
+                cmsgs.ttl = Some(cmsg::decode::<libc::c_int>(cmsg) as u8);
+            }
+            // The BSDs report the TTL as a single byte
+            #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+            (libc::IPPROTO_IP, libc::IP_RECVTTL) => {
+                cmsgs.ttl = Some(cmsg::decode::<u8>(cmsg));
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                cmsgs.ttl = Some(cmsg::decode::<libc::c_int>(cmsg) as u8);
             }
             #[cfg(target_os = "linux")]
-            (libc::SOL_UDP, libc::UDP_GRO) => unsafe {
-                stride = cmsg::decode::<libc::c_int>(cmsg) as usize;
-            },
+            (libc::SOL_UDP, libc::UDP_GRO) => {
+                cmsgs.stride = Some(cmsg::decode::<libc::c_int>(cmsg) as usize);
+            }
             _ => {}
         }
     }
+    cmsgs.ecn = EcnCodepoint::from_bits(ecn_bits);
+    cmsgs
+}
 
-    let addr = match libc::c_int::from(name.ss_family) {
+pub(crate) fn decode_name(name: &MaybeUninit<libc::sockaddr_storage>) -> SocketAddr {
+    let name = unsafe { name.assume_init() };
+    match libc::c_int::from(name.ss_family) {
         libc::AF_INET => {
             // Safety: if the ss_family field is AF_INET then storage must be a sockaddr_in.
             let addr: &libc::sockaddr_in =
@@ -542,14 +974,6 @@ fn decode_recv(
             ))
         }
         _ => unreachable!(),
-    };
-
-    RecvMeta {
-        len,
-        stride,
-        addr,
-        ecn: EcnCodepoint::from_bits(ecn_bits),
-        dst_ip,
     }
 }
 
@@ -628,7 +1052,151 @@ mod gro {
     }
 }
 
-fn set_socket_option(
+/// Detects optional socket features by enabling them on throwaway sockets, like
+/// `gso::max_gso_segments()` and `gro::gro_segments()` do
+mod probe {
+    use super::*;
+    use socket2::{Domain, Socket, Type};
+    use std::time::Duration;
+
+    fn socket(domain: Domain) -> Option<Socket> {
+        Socket::new(domain, Type::DGRAM, None).ok()
+    }
+
+    fn supported(domain: Domain, level: libc::c_int, name: libc::c_int) -> bool {
+        socket(domain)
+            .is_some_and(|socket| set_socket_option(&socket, level, name, OPTION_ON).is_ok())
+    }
+
+    /// Binds an IPv4 socket to the unspecified address, since FreeBSD only honors a source
+    /// address on such sockets
+    fn loopback() -> Option<(Socket, socket2::SockAddr)> {
+        let socket = socket(Domain::IPV4)?;
+        socket
+            .bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into())
+            .ok()?;
+        socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .ok()?;
+        let port = socket.local_addr().ok()?.as_socket()?.port();
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port)).into();
+        Some((socket, addr))
+    }
+
+    /// Sends `transmit` to the socket itself through the regular send path and returns how it
+    /// was received
+    fn send_to_self(
+        socket: &Socket,
+        addr: &socket2::SockAddr,
+        transmit: &Transmit,
+    ) -> Option<RecvMeta> {
+        let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
+        let mut iov = unsafe { mem::zeroed::<libc::iovec>() };
+        let mut ctrl = cmsg::Aligned([0u8; CMSG_LEN]);
+        prepare_msg(transmit, addr, true, &mut hdr, &mut iov, &mut ctrl, true);
+        if unsafe { libc::sendmsg(socket.as_raw_fd(), &hdr, 0) } == -1 {
+            return None;
+        }
+
+        let mut buf = [0u8; 1];
+        let mut meta = [RecvMeta::default()];
+        recv(
+            SockRef::from(socket),
+            &mut [IoSliceMut::new(&mut buf)],
+            &mut meta,
+            false,
+            None,
+        )
+        .ok()?;
+        Some(meta[0])
+    }
+
+    /// Whether a datagram sent with an ECN codepoint arrives with it
+    ///
+    /// Platforms which cannot report received ECN bits only check that the codepoint is
+    /// accepted.
+    pub fn send_ecn() -> bool {
+        let Some((socket, addr)) = loopback() else {
+            return false;
+        };
+        let verify =
+            set_socket_option(&socket, libc::IPPROTO_IP, libc::IP_RECVTOS, OPTION_ON).is_ok();
+        let transmit = Transmit {
+            destination: None,
+            ecn: Some(EcnCodepoint::Ect0),
+            contents: vec![0],
+            segment_size: None,
+            src_ip: None,
+        };
+        send_to_self(&socket, &addr, &transmit)
+            .is_some_and(|meta| !verify || meta.ecn == Some(EcnCodepoint::Ect0))
+    }
+
+    /// Whether a datagram sent with a source address is accepted and delivered
+    pub fn send_src_ip() -> bool {
+        // Other platforms don't encode a source address for IPv4
+        if !cfg!(any(
+            target_os = "linux",
+            target_os = "freebsd",
+            target_os = "macos"
+        )) {
+            return false;
+        }
+        let Some((socket, addr)) = loopback() else {
+            return false;
+        };
+        let transmit = Transmit {
+            destination: None,
+            ecn: None,
+            contents: vec![0],
+            segment_size: None,
+            src_ip: Some(Ipv4Addr::LOCALHOST.into()),
+        };
+        send_to_self(&socket, &addr, &transmit).is_some()
+    }
+
+    pub fn recv_ecn() -> bool {
+        supported(Domain::IPV4, libc::IPPROTO_IP, libc::IP_RECVTOS)
+            || supported(Domain::IPV6, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS)
+    }
+
+    pub fn recv_dst_ip() -> bool {
+        #[cfg(target_os = "linux")]
+        let v4 = supported(Domain::IPV4, libc::IPPROTO_IP, libc::IP_PKTINFO);
+        #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+        let v4 = supported(Domain::IPV4, libc::IPPROTO_IP, libc::IP_RECVDSTADDR);
+        #[cfg(not(any(target_os = "linux", target_os = "freebsd", target_os = "macos")))]
+        let v4 = false;
+
+        v4 || supported(Domain::IPV6, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO)
+    }
+
+    pub fn timestamp_modes() -> TimestampModes {
+        TimestampModes {
+            microseconds: supported(Domain::IPV4, libc::SOL_SOCKET, libc::SO_TIMESTAMP),
+            #[cfg(target_os = "linux")]
+            nanoseconds: supported(Domain::IPV4, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS),
+            #[cfg(not(target_os = "linux"))]
+            nanoseconds: false,
+            // SO_TIMESTAMPING takes a set of flags; requesting software receive timestamps is
+            // enough to detect kernel support
+            #[cfg(target_os = "linux")]
+            timestamping: socket(Domain::IPV4).is_some_and(|socket| {
+                set_socket_option(
+                    &socket,
+                    libc::SOL_SOCKET,
+                    libc::SO_TIMESTAMPING,
+                    (libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE) as _,
+                )
+                .is_ok()
+            }),
+            #[cfg(not(target_os = "linux"))]
+            timestamping: false,
+        }
+    }
+}
+
+pub(crate) fn set_socket_option(
     socket: &impl AsRawFd,
     level: libc::c_int,
     name: libc::c_int,
@@ -650,7 +1218,8 @@ fn set_socket_option(
     }
 }
 
-const OPTION_ON: libc::c_int = 1;
+pub(crate) const OPTION_ON: libc::c_int = 1;
+pub(crate) const OPTION_OFF: libc::c_int = 0;
 
 #[cfg(not(target_os = "linux"))]
 mod gro {
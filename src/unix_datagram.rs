@@ -0,0 +1,582 @@
+use std::{
+    ffi::OsStr,
+    io::{self, IoSliceMut},
+    mem::{self, MaybeUninit},
+    os::{
+        fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{cmsg, BATCH_SIZE};
+
+/// Address of a Unix domain socket
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UnixSocketAddr {
+    /// Socket without a name, e.g. one created by `socketpair` or never bound
+    Unnamed,
+    /// Socket bound to a filesystem path
+    Pathname(PathBuf),
+    /// Socket bound to a name in the Linux abstract namespace, without the leading NUL byte
+    Abstract(Vec<u8>),
+}
+
+impl UnixSocketAddr {
+    pub fn pathname<P: AsRef<Path>>(path: P) -> Self {
+        Self::Pathname(path.as_ref().to_path_buf())
+    }
+
+    pub fn abstract_name<N: AsRef<[u8]>>(name: N) -> Self {
+        Self::Abstract(name.as_ref().to_vec())
+    }
+
+    pub fn as_pathname(&self) -> Option<&Path> {
+        match self {
+            Self::Pathname(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn as_abstract_name(&self) -> Option<&[u8]> {
+        match self {
+            Self::Abstract(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn is_unnamed(&self) -> bool {
+        matches!(self, Self::Unnamed)
+    }
+
+    fn to_raw(&self) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as _;
+        let offset = sun_path_offset(&addr);
+        let len = match self {
+            Self::Unnamed => offset,
+            Self::Pathname(path) => {
+                let bytes = path.as_os_str().as_bytes();
+                // Leave room for the terminating NUL byte
+                if bytes.len() >= addr.sun_path.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "path must be shorter than SUN_LEN",
+                    ));
+                }
+                copy_name(&mut addr.sun_path, bytes);
+                offset + bytes.len() + 1
+            }
+            Self::Abstract(name) => {
+                if !cfg!(any(target_os = "linux", target_os = "android")) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "abstract unix socket addresses are only supported on Linux",
+                    ));
+                }
+                if name.len() >= addr.sun_path.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "abstract name must be shorter than SUN_LEN",
+                    ));
+                }
+                copy_name(&mut addr.sun_path[1..], name);
+                offset + 1 + name.len()
+            }
+        };
+        Ok((addr, len as _))
+    }
+
+    fn from_raw(addr: &libc::sockaddr_un, len: libc::socklen_t) -> Self {
+        let path_len = (len as usize)
+            .saturating_sub(sun_path_offset(addr))
+            .min(addr.sun_path.len());
+        let path =
+            unsafe { &*(&addr.sun_path[..path_len] as *const [libc::c_char] as *const [u8]) };
+        match path.first() {
+            None => Self::Unnamed,
+            Some(0) if cfg!(any(target_os = "linux", target_os = "android")) => {
+                Self::Abstract(path[1..].to_vec())
+            }
+            // Some platforms report the whole `sun_path` of unnamed sockets
+            Some(0) => Self::Unnamed,
+            Some(_) => {
+                let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+                Self::Pathname(PathBuf::from(OsStr::from_bytes(&path[..end])))
+            }
+        }
+    }
+}
+
+fn sun_path_offset(addr: &libc::sockaddr_un) -> usize {
+    addr.sun_path.as_ptr() as usize - addr as *const _ as usize
+}
+
+fn copy_name(dst: &mut [libc::c_char], src: &[u8]) {
+    for (dst, src) in dst.iter_mut().zip(src) {
+        *dst = *src as libc::c_char;
+    }
+}
+
+/// A datagram to be sent through a Unix datagram socket
+#[derive(Debug)]
+pub struct UnixTransmit {
+    /// The socket this datagram should be sent to
+    ///
+    /// `None` sends the datagram to the peer of a connected socket.
+    pub destination: Option<UnixSocketAddr>,
+    /// Contents of the datagram
+    pub contents: Vec<u8>,
+    /// File descriptors passed along with the datagram via `SCM_RIGHTS`
+    ///
+    /// The receiver gets duplicates of these, so they remain open on the sending side.
+    pub fds: Vec<OwnedFd>,
+}
+
+/// Credentials of the process that sent a datagram, as reported by `SCM_CREDENTIALS`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct UnixCredentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Metadata of a datagram received through a Unix datagram socket
+#[derive(Debug)]
+pub struct UnixRecvMeta {
+    /// The socket the datagram was sent from
+    pub addr: UnixSocketAddr,
+    pub len: usize,
+    /// File descriptors passed along with the datagram
+    ///
+    /// At most [`UnixDatagramState::max_recv_fds`] descriptors are received; any beyond that are
+    /// closed.
+    pub fds: Vec<OwnedFd>,
+    /// Credentials of the sender, if [`UnixDatagramState::set_pass_credentials`] was enabled
+    pub credentials: Option<UnixCredentials>,
+}
+
+impl Default for UnixRecvMeta {
+    /// Constructs a value with arbitrary fields, intended to be overwritten
+    fn default() -> Self {
+        Self {
+            addr: UnixSocketAddr::Unnamed,
+            len: 0,
+            fds: Vec::new(),
+            credentials: None,
+        }
+    }
+}
+
+/// Unix datagram socket state shared by all runtimes
+#[derive(Debug, Default)]
+pub struct UnixDatagramState {
+    max_recv_fds: AtomicUsize,
+}
+
+impl UnixDatagramState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of file descriptors that can be received with each datagram
+    ///
+    /// The control buffer of every received datagram is sized to fit this many descriptors, so
+    /// this should be kept as small as the protocol allows. Defaults to zero, in which case
+    /// descriptors sent to this socket are closed on receipt.
+    pub fn set_max_recv_fds(&self, max_fds: usize) {
+        self.max_recv_fds.store(max_fds, Ordering::Relaxed);
+    }
+
+    pub fn max_recv_fds(&self) -> usize {
+        self.max_recv_fds.load(Ordering::Relaxed)
+    }
+
+    /// Enables receiving the credentials of the sender with every datagram
+    ///
+    /// Only supported on Linux and Android, where this sets `SO_PASSCRED`.
+    pub fn set_pass_credentials(&self, io: BorrowedFd<'_>, enabled: bool) -> io::Result<()> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let value: libc::c_int = enabled as _;
+            let rc = unsafe {
+                libc::setsockopt(
+                    io.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    libc::SO_PASSCRED,
+                    &value as *const _ as _,
+                    mem::size_of_val(&value) as _,
+                )
+            };
+            match rc == 0 {
+                true => Ok(()),
+                false => Err(io::Error::last_os_error()),
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            let _ = (io, enabled);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "passing credentials is only supported on Linux",
+            ))
+        }
+    }
+
+    /// Sends datagrams from `transmits`, returning the number of datagrams sent
+    ///
+    /// Unlike UDP, errors are not swallowed: Unix datagram sockets are reliable, so a failure
+    /// such as a missing destination is reported for the first transmit and nothing is sent.
+    pub fn send(&self, io: BorrowedFd<'_>, transmits: &[UnixTransmit]) -> io::Result<usize> {
+        send(io, transmits)
+    }
+
+    pub fn recv(
+        &self,
+        io: BorrowedFd<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [UnixRecvMeta],
+    ) -> io::Result<usize> {
+        let max_fds = self.max_recv_fds();
+        let msg_count = recv(io, bufs, meta, recv_ctrl_len(max_fds))?;
+        // The control buffer is padded, so the kernel may have fit in a few more; close those
+        for meta in &mut meta[..msg_count] {
+            meta.fds.truncate(max_fds);
+        }
+        Ok(msg_count)
+    }
+}
+
+/// Creates a non-blocking Unix datagram socket bound to `addr`
+pub(crate) fn bind(addr: &UnixSocketAddr) -> io::Result<std::os::unix::net::UnixDatagram> {
+    let socket = unbound()?;
+    if !addr.is_unnamed() {
+        let (raw, len) = addr.to_raw()?;
+        let rc = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &raw as *const _ as *const libc::sockaddr,
+                len,
+            )
+        };
+        if rc == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(socket)
+}
+
+/// Creates a non-blocking Unix datagram socket which is not bound to any address
+pub(crate) fn unbound() -> io::Result<std::os::unix::net::UnixDatagram> {
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Creates a pair of connected, non-blocking Unix datagram sockets
+pub(crate) fn pair() -> io::Result<(
+    std::os::unix::net::UnixDatagram,
+    std::os::unix::net::UnixDatagram,
+)> {
+    let (a, b) = std::os::unix::net::UnixDatagram::pair()?;
+    a.set_nonblocking(true)?;
+    b.set_nonblocking(true)?;
+    Ok((a, b))
+}
+
+pub(crate) fn connect(io: BorrowedFd<'_>, addr: &UnixSocketAddr) -> io::Result<()> {
+    let (raw, len) = addr.to_raw()?;
+    let rc = unsafe {
+        libc::connect(
+            io.as_raw_fd(),
+            &raw as *const _ as *const libc::sockaddr,
+            len,
+        )
+    };
+    match rc == 0 {
+        true => Ok(()),
+        false => Err(io::Error::last_os_error()),
+    }
+}
+
+pub(crate) fn local_addr(io: BorrowedFd<'_>) -> io::Result<UnixSocketAddr> {
+    sockname(|addr, len| unsafe { libc::getsockname(io.as_raw_fd(), addr, len) })
+}
+
+pub(crate) fn peer_addr(io: BorrowedFd<'_>) -> io::Result<UnixSocketAddr> {
+    sockname(|addr, len| unsafe { libc::getpeername(io.as_raw_fd(), addr, len) })
+}
+
+fn sockname(
+    f: impl FnOnce(*mut libc::sockaddr, *mut libc::socklen_t) -> libc::c_int,
+) -> io::Result<UnixSocketAddr> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+    if f(&mut addr as *mut _ as *mut libc::sockaddr, &mut len) == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(UnixSocketAddr::from_raw(&addr, len))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn send(io: BorrowedFd<'_>, transmits: &[UnixTransmit]) -> io::Result<usize> {
+    let mut msgs: [libc::mmsghdr; BATCH_SIZE] = unsafe { mem::zeroed() };
+    let mut iovecs: [libc::iovec; BATCH_SIZE] = unsafe { mem::zeroed() };
+    let mut names: [MaybeUninit<libc::sockaddr_un>; BATCH_SIZE] =
+        [MaybeUninit::uninit(); BATCH_SIZE];
+    let num_transmits = transmits.len().min(BATCH_SIZE);
+    let ctrl_len = transmits[..num_transmits]
+        .iter()
+        .map(|t| send_ctrl_len(t.fds.len()))
+        .max()
+        .unwrap_or(0);
+    let mut ctrls = ctrl_buffer(ctrl_len, num_transmits);
+    for i in 0..num_transmits {
+        prepare_msg(
+            &transmits[i],
+            &mut names[i],
+            &mut msgs[i].msg_hdr,
+            &mut iovecs[i],
+            &mut ctrls[i * ctrl_len / CTRL_ALIGN..(i + 1) * ctrl_len / CTRL_ALIGN],
+        )?;
+    }
+
+    loop {
+        let n = unsafe { libc::sendmmsg(io.as_raw_fd(), msgs.as_mut_ptr(), num_transmits as _, 0) };
+        if n == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        return Ok(n as usize);
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn send(io: BorrowedFd<'_>, transmits: &[UnixTransmit]) -> io::Result<usize> {
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    let mut iov: libc::iovec = unsafe { mem::zeroed() };
+    let mut name = MaybeUninit::<libc::sockaddr_un>::uninit();
+    let mut sent = 0;
+
+    while sent < transmits.len() {
+        let mut ctrl = ctrl_buffer(send_ctrl_len(transmits[sent].fds.len()), 1);
+        prepare_msg(&transmits[sent], &mut name, &mut hdr, &mut iov, &mut ctrl)?;
+        let n = unsafe { libc::sendmsg(io.as_raw_fd(), &hdr, 0) };
+        if n == -1 {
+            let e = io::Error::last_os_error();
+            match e.kind() {
+                io::ErrorKind::Interrupted => {}
+                _ if sent != 0 => return Ok(sent),
+                _ => return Err(e),
+            }
+        } else {
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn recv(
+    io: BorrowedFd<'_>,
+    bufs: &mut [IoSliceMut<'_>],
+    meta: &mut [UnixRecvMeta],
+    ctrl_len: usize,
+) -> io::Result<usize> {
+    let mut names = [unsafe { mem::zeroed::<libc::sockaddr_un>() }; BATCH_SIZE];
+    let mut hdrs = unsafe { mem::zeroed::<[libc::mmsghdr; BATCH_SIZE]>() };
+    let max_msg_count = bufs.len().min(meta.len()).min(BATCH_SIZE);
+    let mut ctrls = ctrl_buffer(ctrl_len, max_msg_count);
+    for i in 0..max_msg_count {
+        prepare_recv(
+            &mut bufs[i],
+            &mut names[i],
+            &mut ctrls[i * ctrl_len / CTRL_ALIGN..(i + 1) * ctrl_len / CTRL_ALIGN],
+            &mut hdrs[i].msg_hdr,
+        );
+    }
+    let msg_count = loop {
+        let n = unsafe {
+            libc::recvmmsg(
+                io.as_raw_fd(),
+                hdrs.as_mut_ptr(),
+                max_msg_count as _,
+                RECV_FLAGS,
+                ptr::null_mut(),
+            )
+        };
+        if n == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        break n as usize;
+    };
+    for i in 0..msg_count {
+        meta[i] = decode_recv(&names[i], &hdrs[i].msg_hdr, hdrs[i].msg_len as usize);
+    }
+    Ok(msg_count)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn recv(
+    io: BorrowedFd<'_>,
+    bufs: &mut [IoSliceMut<'_>],
+    meta: &mut [UnixRecvMeta],
+    ctrl_len: usize,
+) -> io::Result<usize> {
+    let mut name = unsafe { mem::zeroed::<libc::sockaddr_un>() };
+    let mut ctrl = ctrl_buffer(ctrl_len, 1);
+    let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
+    prepare_recv(&mut bufs[0], &mut name, &mut ctrl, &mut hdr);
+    let n = loop {
+        let n = unsafe { libc::recvmsg(io.as_raw_fd(), &mut hdr, RECV_FLAGS) };
+        if n == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        break n;
+    };
+    meta[0] = decode_recv(&name, &hdr, n as usize);
+    Ok(1)
+}
+
+/// Received descriptors are marked close-on-exec atomically where the platform allows it
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+const RECV_FLAGS: libc::c_int = 0;
+
+const CTRL_ALIGN: usize = mem::size_of::<cmsg::Aligned<[u8; 8]>>();
+
+/// Allocates `count` control buffers of `len` bytes each, which must be a multiple of
+/// [`CTRL_ALIGN`]
+fn ctrl_buffer(len: usize, count: usize) -> Vec<cmsg::Aligned<[u8; 8]>> {
+    debug_assert_eq!(len % CTRL_ALIGN, 0);
+    vec![cmsg::Aligned([0; 8]); len / CTRL_ALIGN * count]
+}
+
+fn send_ctrl_len(num_fds: usize) -> usize {
+    match num_fds {
+        0 => 0,
+        n => cmsg_space(n * mem::size_of::<libc::c_int>()),
+    }
+}
+
+fn recv_ctrl_len(max_fds: usize) -> usize {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let credentials = cmsg_space(mem::size_of::<libc::ucred>());
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let credentials = 0;
+    send_ctrl_len(max_fds) + credentials
+}
+
+fn cmsg_space(len: usize) -> usize {
+    let space = unsafe { libc::CMSG_SPACE(len as _) as usize };
+    space.div_ceil(CTRL_ALIGN) * CTRL_ALIGN
+}
+
+fn prepare_msg(
+    transmit: &UnixTransmit,
+    name: &mut MaybeUninit<libc::sockaddr_un>,
+    hdr: &mut libc::msghdr,
+    iov: &mut libc::iovec,
+    ctrl: &mut [cmsg::Aligned<[u8; 8]>],
+) -> io::Result<()> {
+    iov.iov_base = transmit.contents.as_ptr() as *const _ as *mut _;
+    iov.iov_len = transmit.contents.len();
+
+    match &transmit.destination {
+        Some(destination) => {
+            let (addr, len) = destination.to_raw()?;
+            hdr.msg_name = name.write(addr) as *mut _ as *mut _;
+            hdr.msg_namelen = len;
+        }
+        // Connected sockets send to their peer when no name is given
+        None => {
+            hdr.msg_name = ptr::null_mut();
+            hdr.msg_namelen = 0;
+        }
+    }
+    hdr.msg_iov = iov;
+    hdr.msg_iovlen = 1;
+
+    if transmit.fds.is_empty() {
+        hdr.msg_control = ptr::null_mut();
+        hdr.msg_controllen = 0;
+        return Ok(());
+    }
+    hdr.msg_control = ctrl.as_mut_ptr() as _;
+    hdr.msg_controllen = mem::size_of_val(ctrl) as _;
+    let fds = transmit
+        .fds
+        .iter()
+        .map(|fd| fd.as_raw_fd())
+        .collect::<Vec<_>>();
+    let mut encoder = unsafe { cmsg::Encoder::new(hdr) };
+    encoder.push_slice(libc::SOL_SOCKET, libc::SCM_RIGHTS, &fds);
+    encoder.finish();
+    Ok(())
+}
+
+fn prepare_recv(
+    buf: &mut IoSliceMut<'_>,
+    name: &mut libc::sockaddr_un,
+    ctrl: &mut [cmsg::Aligned<[u8; 8]>],
+    hdr: &mut libc::msghdr,
+) {
+    hdr.msg_name = name as *mut _ as _;
+    hdr.msg_namelen = mem::size_of::<libc::sockaddr_un>() as _;
+    hdr.msg_iov = buf as *mut IoSliceMut<'_> as *mut libc::iovec;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = match ctrl.is_empty() {
+        true => ptr::null_mut(),
+        false => ctrl.as_mut_ptr() as _,
+    };
+    hdr.msg_controllen = mem::size_of_val(ctrl) as _;
+    hdr.msg_flags = 0;
+}
+
+fn decode_recv(name: &libc::sockaddr_un, hdr: &libc::msghdr, len: usize) -> UnixRecvMeta {
+    let mut fds = Vec::new();
+    let mut credentials = None;
+    let cmsg_iter = unsafe { cmsg::Iter::new(hdr) };
+    for cmsg in cmsg_iter {
+        match (cmsg.cmsg_level, cmsg.cmsg_type) {
+            // Take ownership right away, so the descriptors are closed even if the metadata
+            // is dropped without being looked at
+            (libc::SOL_SOCKET, libc::SCM_RIGHTS) => fds.extend(
+                unsafe { cmsg::decode_slice::<libc::c_int>(cmsg) }
+                    .into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            ),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            (libc::SOL_SOCKET, libc::SCM_CREDENTIALS) => {
+                let ucred = unsafe { cmsg::decode::<libc::ucred>(cmsg) };
+                credentials = Some(UnixCredentials {
+                    pid: ucred.pid,
+                    uid: ucred.uid,
+                    gid: ucred.gid,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    UnixRecvMeta {
+        addr: UnixSocketAddr::from_raw(name, hdr.msg_namelen),
+        len,
+        fds,
+        credentials,
+    }
+}
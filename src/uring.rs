@@ -0,0 +1,479 @@
+//! io_uring backend of [`UdpSocketState`](crate::UdpSocketState)
+//!
+//! Datagrams are received by a single multishot `recvmsg` which picks its buffers from a
+//! provided buffer ring, and sent by batches of `sendmsg` submissions. Control messages are
+//! encoded and decoded exactly like those of `sendmmsg`/`recvmmsg`.
+
+use crate::imp::{decode_recv, destination, prepare_msg, CMSG_LEN};
+use crate::{cmsg, log_sendmsg_error, RecvMeta, Transmit, BATCH_SIZE};
+use io_uring::{cqueue, opcode, types, IoUring};
+use socket2::SockRef;
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    io::IoSliceMut,
+    mem::{self, MaybeUninit},
+    net::SocketAddr,
+    os::fd::{AsRawFd, BorrowedFd, RawFd},
+    ptr,
+    sync::{
+        atomic::{AtomicU16, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+use tracing::debug;
+
+/// Number of submission queue entries
+const RING_ENTRIES: u32 = 256;
+/// Number of sends which may be in flight at the same time
+const SEND_SLOTS: usize = 128;
+/// Number of receive buffers, must be a power of two
+const RECV_BUFFERS: u16 = 64;
+/// Room for the `io_uring_recvmsg_out` header, the source address, the control messages and a
+/// payload of the largest possible (GRO) datagram
+const RECV_BUFFER_LEN: usize = mem::size_of::<io_uring::types::RecvMsgOut<'static>>()
+    + mem::size_of::<libc::sockaddr_storage>()
+    + CMSG_LEN
+    + u16::MAX as usize;
+const BUFFER_GROUP: u16 = 0;
+
+const RECV_USER_DATA: u64 = u64::MAX;
+const WAKE_USER_DATA: u64 = u64::MAX - 1;
+const CANCEL_USER_DATA: u64 = u64::MAX - 2;
+
+pub(crate) struct Uring {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    ring: IoUring,
+    fd: RawFd,
+    buffers: BufferRing,
+    /// Template of the multishot receive, which the kernel reads the lengths of the source
+    /// address and control messages from
+    recv_hdr: Box<libc::msghdr>,
+    recv_armed: bool,
+    /// Buffer IDs and lengths of received datagrams which have not been returned yet
+    received: VecDeque<(u16, usize)>,
+    recv_error: Option<io::Error>,
+    /// Sends which failed since the last call to [`Uring::send`], which reports them
+    send_errors: Vec<(io::Error, Transmit)>,
+    /// One per slot, never resized so that the buffers stay in place
+    sends: Vec<SendOp>,
+    sends_in_flight: usize,
+    wake_in_flight: bool,
+}
+
+// The raw pointers refer to memory owned by `Inner` itself
+unsafe impl Send for Inner {}
+
+/// The buffers of a pending `sendmsg`, which must stay in place until it completes
+///
+/// Each slot keeps its buffers between sends, so copying a transmit only allocates while its
+/// contents outgrow the ones sent through the slot before.
+struct SendOp {
+    in_flight: bool,
+    transmit: Transmit,
+    name: socket2::SockAddr,
+    iov: libc::iovec,
+    ctrl: cmsg::Aligned<[u8; CMSG_LEN]>,
+    hdr: libc::msghdr,
+}
+
+impl SendOp {
+    fn new() -> Self {
+        Self {
+            in_flight: false,
+            transmit: Transmit {
+                destination: None,
+                ecn: None,
+                contents: Vec::new(),
+                segment_size: None,
+                src_ip: None,
+            },
+            name: SocketAddr::from(([0, 0, 0, 0], 0)).into(),
+            iov: unsafe { mem::zeroed() },
+            ctrl: cmsg::Aligned([0u8; CMSG_LEN]),
+            hdr: unsafe { mem::zeroed() },
+        }
+    }
+}
+
+impl Uring {
+    /// Sets up a ring for `socket` and starts receiving
+    ///
+    /// Fails if io_uring, provided buffer rings or multishot `recvmsg` are not available,
+    /// e.g. on kernels before 6.0 or where io_uring is disabled.
+    pub(crate) fn new(socket: SockRef<'_>) -> io::Result<Self> {
+        let ring = IoUring::builder().build(RING_ENTRIES)?;
+        let buffers = BufferRing::new(&ring)?;
+        let mut recv_hdr: Box<libc::msghdr> = Box::new(unsafe { mem::zeroed() });
+        recv_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+        recv_hdr.msg_controllen = CMSG_LEN as _;
+        let mut inner = Inner {
+            ring,
+            fd: socket.as_raw_fd(),
+            buffers,
+            recv_hdr,
+            recv_armed: false,
+            received: VecDeque::new(),
+            recv_error: None,
+            send_errors: Vec::new(),
+            sends: (0..SEND_SLOTS).map(|_| SendOp::new()).collect(),
+            sends_in_flight: 0,
+            wake_in_flight: false,
+        };
+        inner.arm_recv()?;
+        // Kernels without multishot support reject the receive right away
+        inner.reap();
+        match inner.recv_error.take() {
+            Some(e) => Err(e),
+            None => Ok(Self {
+                inner: Mutex::new(inner),
+            }),
+        }
+    }
+
+    /// The file descriptor of the ring, which becomes readable when datagrams were received
+    pub(crate) fn as_fd(&self) -> BorrowedFd<'_> {
+        let fd = self.inner.lock().unwrap().ring.as_raw_fd();
+        // The ring lives as long as `self`
+        unsafe { BorrowedFd::borrow_raw(fd) }
+    }
+
+    pub(crate) fn send(
+        &self,
+        epoch: &Instant,
+        last_send_error: &AtomicU64,
+        map_ipv4: bool,
+        peer: Option<SocketAddr>,
+        transmits: &[Transmit],
+    ) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.reap_sends(epoch, last_send_error);
+
+        let mut count = 0;
+        for transmit in transmits.iter().take(BATCH_SIZE) {
+            let slot = match inner.sends.iter().position(|op| !op.in_flight) {
+                Some(slot) => slot,
+                None => break,
+            };
+            let (dst_addr, encode_dst_addr) = match destination(transmit, map_ipv4, peer) {
+                Ok(destination) => destination,
+                Err(e) if count == 0 => return Err(e),
+                Err(_) => break,
+            };
+            let fd = inner.fd;
+            let op = &mut inner.sends[slot];
+            op.transmit.destination = transmit.destination;
+            op.transmit.ecn = transmit.ecn;
+            op.transmit.contents.clear();
+            op.transmit.contents.extend_from_slice(&transmit.contents);
+            op.transmit.segment_size = transmit.segment_size;
+            op.transmit.src_ip = transmit.src_ip;
+            op.name = dst_addr.into();
+            prepare_msg(
+                &op.transmit,
+                &op.name,
+                encode_dst_addr,
+                &mut op.hdr,
+                &mut op.iov,
+                &mut op.ctrl,
+                true,
+            );
+            let entry = opcode::SendMsg::new(types::Fd(fd), &op.hdr)
+                .build()
+                .user_data(slot as u64);
+            match inner.push(&entry) {
+                Ok(()) => {}
+                Err(e) if count == 0 => return Err(e),
+                // The transmits queued so far are sent, the caller retries the rest
+                Err(_) => break,
+            }
+            inner.sends[slot].in_flight = true;
+            inner.sends_in_flight += 1;
+            count += 1;
+        }
+        if count == 0 && !transmits.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        // Queued entries stay in the submission queue and go out with the next submission if
+        // this one fails, so the transmits count as sent either way
+        if let Err(e) = inner.ring.submit() {
+            debug!("failed to submit sends to the ring: {}", e);
+        }
+        Ok(count)
+    }
+
+    pub(crate) fn recv(
+        &self,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+        map_ipv4: bool,
+        peer: Option<SocketAddr>,
+    ) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.reap();
+
+        let mut count = 0;
+        for (buf, meta) in bufs.iter_mut().zip(meta.iter_mut()) {
+            let (bid, len) = match inner.received.pop_front() {
+                Some(received) => received,
+                None => break,
+            };
+            *meta = inner.decode(bid, len, buf, map_ipv4, peer);
+            inner.buffers.recycle(bid);
+            count += 1;
+        }
+
+        if !inner.recv_armed {
+            inner.arm_recv()?;
+        }
+        match (count, inner.recv_error.take()) {
+            (0, Some(e)) => Err(e),
+            (0, None) => Err(io::ErrorKind::WouldBlock.into()),
+            _ => Ok(count),
+        }
+    }
+}
+
+impl Inner {
+    fn push(&mut self, entry: &io_uring::squeue::Entry) -> io::Result<()> {
+        loop {
+            // Safety: the buffers referenced by the entry outlive its completion
+            if unsafe { self.ring.submission().push(entry) }.is_ok() {
+                return Ok(());
+            }
+            self.ring.submit()?;
+        }
+    }
+
+    fn arm_recv(&mut self) -> io::Result<()> {
+        let entry = opcode::RecvMsgMulti::new(types::Fd(self.fd), &*self.recv_hdr, BUFFER_GROUP)
+            .build()
+            .user_data(RECV_USER_DATA);
+        self.push(&entry)?;
+        self.ring.submit()?;
+        self.recv_armed = true;
+        Ok(())
+    }
+
+    /// Like [`Inner::reap`], and logs the failed sends reaped since the last call
+    ///
+    /// Receive completions consumed here do not wake up a receiver waiting for the ring to
+    /// become readable, so a no-op is submitted to post another completion.
+    fn reap_sends(&mut self, epoch: &Instant, last_send_error: &AtomicU64) {
+        self.reap();
+        for (e, transmit) in self.send_errors.drain(..) {
+            log_sendmsg_error(epoch, last_send_error, e, &transmit);
+        }
+        let recv_ready = !self.received.is_empty() || !self.recv_armed || self.recv_error.is_some();
+        if recv_ready && !self.wake_in_flight {
+            let entry = opcode::Nop::new().build().user_data(WAKE_USER_DATA);
+            if self.push(&entry).is_ok() {
+                self.wake_in_flight = true;
+            }
+        }
+    }
+
+    /// Processes all completions, queueing received datagrams and the errors of failed sends,
+    /// and releasing finished sends
+    fn reap(&mut self) {
+        let completions = self.ring.completion().collect::<Vec<_>>();
+        for cqe in completions {
+            match cqe.user_data() {
+                RECV_USER_DATA => {
+                    if !cqueue::more(cqe.flags()) {
+                        self.recv_armed = false;
+                    }
+                    match (cqe.result(), cqueue::buffer_select(cqe.flags())) {
+                        (len, Some(bid)) if len >= 0 => {
+                            self.received.push_back((bid, len as usize));
+                        }
+                        // Out of buffers, receiving resumes once some are returned
+                        (e, _) if e == -libc::ENOBUFS => {}
+                        (e, _) if e < 0 => {
+                            self.recv_error = Some(io::Error::from_raw_os_error(-e));
+                        }
+                        _ => {}
+                    }
+                }
+                WAKE_USER_DATA => self.wake_in_flight = false,
+                CANCEL_USER_DATA => {}
+                slot => {
+                    let op = &mut self.sends[slot as usize];
+                    op.in_flight = false;
+                    self.sends_in_flight -= 1;
+                    if cqe.result() < 0 {
+                        let e = io::Error::from_raw_os_error(-cqe.result());
+                        let transmit = Transmit {
+                            destination: op.transmit.destination,
+                            ecn: op.transmit.ecn,
+                            contents: op.transmit.contents.clone(),
+                            segment_size: op.transmit.segment_size,
+                            src_ip: op.transmit.src_ip,
+                        };
+                        self.send_errors.push((e, transmit));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copies a received datagram into `buf` and decodes its metadata
+    fn decode(
+        &self,
+        bid: u16,
+        len: usize,
+        buf: &mut IoSliceMut<'_>,
+        map_ipv4: bool,
+        peer: Option<SocketAddr>,
+    ) -> RecvMeta {
+        let out = types::RecvMsgOut::parse(&self.buffers.get(bid)[..len], &self.recv_hdr)
+            .expect("the kernel fills in a complete header");
+        let payload = out.payload_data();
+        let n = payload.len().min(buf.len());
+        buf[..n].copy_from_slice(&payload[..n]);
+
+        let mut name = MaybeUninit::<libc::sockaddr_storage>::zeroed();
+        let name_data = out.name_data();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                name_data.as_ptr(),
+                name.as_mut_ptr() as *mut u8,
+                name_data
+                    .len()
+                    .min(mem::size_of::<libc::sockaddr_storage>()),
+            );
+        }
+        let control = out.control_data();
+        let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+        hdr.msg_control = control.as_ptr() as *mut _;
+        hdr.msg_controllen = control.len() as _;
+        decode_recv(&name, &hdr, n, map_ipv4, peer)
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Wait for the kernel to let go of the buffers before they are freed
+        let cancel = opcode::AsyncCancel::new(RECV_USER_DATA)
+            .build()
+            .user_data(CANCEL_USER_DATA);
+        if self.recv_armed && self.push(&cancel).is_err() {
+            return;
+        }
+        while self.recv_armed || self.sends_in_flight > 0 {
+            if self.ring.submit_and_wait(1).is_err() {
+                // Leak the buffers rather than let the kernel write into freed memory
+                mem::forget(mem::take(&mut self.sends));
+                self.buffers.leak = true;
+                return;
+            }
+            self.reap();
+        }
+    }
+}
+
+impl fmt::Debug for Uring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Uring").finish_non_exhaustive()
+    }
+}
+
+/// Receive buffers shared with the kernel through a provided buffer ring
+///
+/// The ring of buffer descriptors occupies the first page of a single mapping, followed by the
+/// buffers themselves.
+struct BufferRing {
+    base: *mut u8,
+    len: usize,
+    tail: u16,
+    leak: bool,
+}
+
+impl BufferRing {
+    fn new(ring: &IoUring) -> io::Result<Self> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let descriptors = usize::from(RECV_BUFFERS) * mem::size_of::<types::BufRingEntry>();
+        let buffers_offset = descriptors.div_ceil(page_size) * page_size;
+        let len = buffers_offset + usize::from(RECV_BUFFERS) * Self::stride();
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buffers = Self {
+            base: base as *mut u8,
+            len,
+            tail: 0,
+            leak: false,
+        };
+        unsafe {
+            ring.submitter().register_buf_ring_with_flags(
+                base as u64,
+                RECV_BUFFERS,
+                BUFFER_GROUP,
+                0,
+            )?;
+        }
+        for bid in 0..RECV_BUFFERS {
+            buffers.recycle(bid);
+        }
+        Ok(buffers)
+    }
+
+    /// Distance between buffers, keeping each of them aligned for the header
+    fn stride() -> usize {
+        RECV_BUFFER_LEN.next_multiple_of(mem::align_of::<libc::sockaddr_storage>())
+    }
+
+    fn buffers_offset(&self) -> usize {
+        self.len - usize::from(RECV_BUFFERS) * Self::stride()
+    }
+
+    fn get(&self, bid: u16) -> &[u8] {
+        let offset = self.buffers_offset() + usize::from(bid) * Self::stride();
+        unsafe { std::slice::from_raw_parts(self.base.add(offset), RECV_BUFFER_LEN) }
+    }
+
+    /// Hands a buffer (back) to the kernel
+    fn recycle(&mut self, bid: u16) {
+        let entries = self.base as *mut types::BufRingEntry;
+        let addr = self.get(bid).as_ptr() as u64;
+        unsafe {
+            let entry = &mut *entries.add(usize::from(self.tail & (RECV_BUFFERS - 1)));
+            entry.set_addr(addr);
+            entry.set_len(RECV_BUFFER_LEN as u32);
+            entry.set_bid(bid);
+            self.tail = self.tail.wrapping_add(1);
+            let tail = types::BufRingEntry::tail(entries) as *const AtomicU16;
+            (*tail).store(self.tail, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for BufferRing {
+    fn drop(&mut self) {
+        if !self.leak {
+            unsafe { libc::munmap(self.base as *mut _, self.len) };
+        }
+    }
+}
+
+/// Logs why io_uring could not be used, before falling back to `sendmmsg`/`recvmmsg`
+pub(crate) fn log_unavailable(e: &io::Error) {
+    debug!(
+        "io_uring is unavailable, falling back to sendmmsg/recvmmsg: {}",
+        e
+    );
+}
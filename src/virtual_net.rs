@@ -0,0 +1,627 @@
+use crate::rng::Rng;
+use crate::runtime::{AsyncTimer, AsyncUdpSocket, Runtime};
+use crate::{not_connected, Capabilities, EcnCodepoint, RecvMeta, Transmit};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    fmt,
+    future::poll_fn,
+    io::{self, IoSliceMut},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// First port handed out to sockets bound to port 0
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// Number of datagrams a socket queues before dropping further ones, like a full receive buffer
+const RECV_QUEUE_LEN: usize = 4096;
+
+/// Impairments applied to datagrams travelling over a link of a [`VirtualNetwork`]
+///
+/// The default configuration delivers every datagram immediately and unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualLinkConfig {
+    /// Delay added to every datagram
+    pub latency: Duration,
+    /// Upper bound of a uniformly distributed delay added on top of `latency`
+    ///
+    /// Datagrams overtake each other if the jitter exceeds the time between sending them.
+    pub jitter: Duration,
+    /// Probability in `0.0..=1.0` that a datagram is dropped
+    pub loss: f64,
+    /// Probability that a datagram is delivered twice
+    pub duplicate: f64,
+    /// Probability that a datagram is held back by [`Self::reorder_delay`], so that datagrams
+    /// sent after it arrive first
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+    /// Largest payload that can cross the link, larger datagrams are dropped
+    pub mtu: usize,
+    /// Clears the ECN codepoint of every datagram, like a router which does not support ECN
+    pub ecn_bleach: bool,
+    /// Probability that an ECN-capable datagram is marked as having experienced congestion
+    pub ce_mark: f64,
+}
+
+impl Default for VirtualLinkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::ZERO,
+            mtu: u16::MAX as usize,
+            ecn_bleach: false,
+            ce_mark: 0.0,
+        }
+    }
+}
+
+/// An in-memory network connecting [`VirtualUdpSocket`]s
+///
+/// Datagrams never touch the operating system. They are subject to the impairments of the
+/// [`VirtualLinkConfig`] between their source and destination, which are drawn from a random
+/// number generator seeded by [`VirtualNetwork::new`]. Sending the same datagrams in the same
+/// order therefore results in the same losses, duplicates, delays and ECN marks on every run.
+///
+/// Delays are measured by a virtual clock owned by the network. A network created by
+/// [`VirtualNetwork::new`] only moves its clock forward in [`VirtualNetwork::advance`], so that
+/// datagrams are received in the same order and batches on every run. One created by
+/// [`VirtualNetwork::with_runtime`] follows [`Runtime::now`] and waits for delays with the timers
+/// of the runtime.
+#[derive(Clone)]
+pub struct VirtualNetwork {
+    state: Arc<Mutex<NetworkState>>,
+}
+
+struct NetworkState {
+    rng: Rng,
+    clock: Clock,
+    config: VirtualLinkConfig,
+    links: HashMap<(IpAddr, IpAddr), VirtualLinkConfig>,
+    sockets: HashMap<SocketAddr, Arc<Mutex<Endpoint>>>,
+    next_port: u16,
+    next_seq: u64,
+}
+
+impl VirtualNetwork {
+    /// Creates a network whose clock only moves in [`VirtualNetwork::advance`]
+    pub fn new(seed: u64) -> Self {
+        Self::with_clock(
+            seed,
+            Clock {
+                runtime: None,
+                start: Instant::now(),
+                offset: Duration::ZERO,
+            },
+        )
+    }
+
+    /// Creates a network whose clock follows the current time of `runtime`
+    pub fn with_runtime(seed: u64, runtime: Arc<dyn Runtime>) -> Self {
+        let start = runtime.now();
+        Self::with_clock(
+            seed,
+            Clock {
+                runtime: Some(runtime),
+                start,
+                offset: Duration::ZERO,
+            },
+        )
+    }
+
+    fn with_clock(seed: u64, clock: Clock) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(NetworkState {
+                rng: Rng::new(seed),
+                clock,
+                config: VirtualLinkConfig::default(),
+                links: HashMap::new(),
+                sockets: HashMap::new(),
+                next_port: EPHEMERAL_PORT_START,
+                next_seq: 0,
+            })),
+        }
+    }
+
+    /// Returns the time on the clock of the network
+    pub fn now(&self) -> Instant {
+        self.state.lock().unwrap().clock.now()
+    }
+
+    /// Moves the clock of the network forward by `duration`, waking sockets whose datagrams
+    /// became due
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.clock.offset += duration;
+        let now = state.clock.now();
+        let mut wakers = Vec::new();
+        for endpoint in state.sockets.values() {
+            let mut endpoint = endpoint.lock().unwrap();
+            if endpoint.queue.peek().is_some_and(|d| d.deliver_at <= now) {
+                wakers.extend(endpoint.waker.take());
+            }
+        }
+        drop(state);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    fn clock(&self) -> Clock {
+        self.state.lock().unwrap().clock.clone()
+    }
+
+    /// Sets the configuration of all links without a more specific one
+    pub fn set_config(&self, config: VirtualLinkConfig) {
+        self.state.lock().unwrap().config = config;
+    }
+
+    /// Sets the configuration of datagrams sent from `src` to `dst`
+    ///
+    /// Links are directional, so the reverse path keeps its own configuration.
+    pub fn set_link_config(&self, src: IpAddr, dst: IpAddr, config: VirtualLinkConfig) {
+        self.state.lock().unwrap().links.insert((src, dst), config);
+    }
+
+    /// Creates a socket bound to `addr`
+    ///
+    /// A port of 0 picks an unused port. Sockets bound to an unspecified address receive
+    /// datagrams sent to any address with their port, and send from the loopback address of
+    /// their family unless [`Transmit::src_ip`] is set.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<VirtualUdpSocket> {
+        let mut state = self.state.lock().unwrap();
+        let addr = match addr.port() {
+            0 => {
+                let port = state.unused_port(addr.ip())?;
+                SocketAddr::new(addr.ip(), port)
+            }
+            _ => addr,
+        };
+        if state.sockets.contains_key(&addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "address is already bound on this virtual network",
+            ));
+        }
+        let endpoint = Arc::new(Mutex::new(Endpoint {
+            queue: BinaryHeap::new(),
+            waker: None,
+            timer: None,
+            peer: None,
+            gro_segments: 1,
+        }));
+        state.sockets.insert(addr, endpoint.clone());
+        Ok(VirtualUdpSocket {
+            network: self.clone(),
+            addr,
+            endpoint,
+        })
+    }
+}
+
+impl fmt::Debug for VirtualNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("VirtualNetwork")
+            .field("advanced", &state.clock.offset)
+            .field("config", &state.config)
+            .field("links", &state.links)
+            .field("sockets", &state.sockets.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The virtual clock of a [`VirtualNetwork`]
+#[derive(Clone)]
+struct Clock {
+    /// Runtime whose current time the clock follows, it stands still without one
+    runtime: Option<Arc<dyn Runtime>>,
+    start: Instant,
+    /// Time the clock was moved forward by [`VirtualNetwork::advance`]
+    offset: Duration,
+}
+
+impl Clock {
+    fn now(&self) -> Instant {
+        match &self.runtime {
+            Some(runtime) => runtime.now() + self.offset,
+            None => self.start + self.offset,
+        }
+    }
+}
+
+impl NetworkState {
+    fn unused_port(&mut self, ip: IpAddr) -> io::Result<u16> {
+        for _ in EPHEMERAL_PORT_START..=u16::MAX {
+            let port = self.next_port;
+            self.next_port = self
+                .next_port
+                .checked_add(1)
+                .unwrap_or(EPHEMERAL_PORT_START);
+            if !self.sockets.contains_key(&SocketAddr::new(ip, port)) {
+                return Ok(port);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "no free ports left on this virtual network",
+        ))
+    }
+
+    fn lookup(&self, dst: SocketAddr) -> Option<&Arc<Mutex<Endpoint>>> {
+        let unspecified = match dst.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        self.sockets
+            .get(&dst)
+            .or_else(|| self.sockets.get(&SocketAddr::new(unspecified, dst.port())))
+    }
+
+    /// Applies the impairments of the link from `src` to `dst`, returning the copies of the
+    /// datagram to deliver
+    fn impair(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        ecn: Option<EcnCodepoint>,
+        contents: &[u8],
+        now: Instant,
+    ) -> Vec<Datagram> {
+        let config = self
+            .links
+            .get(&(src.ip(), dst.ip()))
+            .unwrap_or(&self.config)
+            .clone();
+        if contents.len() > config.mtu || self.rng.chance(config.loss) {
+            return Vec::new();
+        }
+
+        let ecn = match ecn {
+            _ if config.ecn_bleach => None,
+            Some(EcnCodepoint::Ect0 | EcnCodepoint::Ect1) if self.rng.chance(config.ce_mark) => {
+                Some(EcnCodepoint::Ce)
+            }
+            ecn => ecn,
+        };
+        let copies = match self.rng.chance(config.duplicate) {
+            true => 2,
+            false => 1,
+        };
+        (0..copies)
+            .map(|_| {
+                let mut delay = config.latency;
+                if !config.jitter.is_zero() {
+                    delay += config.jitter.mul_f64(self.rng.next_f64());
+                }
+                if self.rng.chance(config.reorder) {
+                    delay += config.reorder_delay;
+                }
+                self.next_seq += 1;
+                Datagram {
+                    deliver_at: now + delay,
+                    seq: self.next_seq,
+                    src,
+                    dst_ip: dst.ip(),
+                    ecn,
+                    contents: contents.to_vec(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A UDP socket attached to a [`VirtualNetwork`]
+pub struct VirtualUdpSocket {
+    network: VirtualNetwork,
+    addr: SocketAddr,
+    endpoint: Arc<Mutex<Endpoint>>,
+}
+
+struct Endpoint {
+    queue: BinaryHeap<Datagram>,
+    waker: Option<Waker>,
+    /// Timer of the runtime the clock follows, waiting for the next datagram to become due
+    timer: Option<Pin<Box<dyn AsyncTimer>>>,
+    peer: Option<SocketAddr>,
+    gro_segments: usize,
+}
+
+impl Endpoint {
+    /// Dequeues the datagrams which are due, returning how many buffers were filled
+    fn recv(&mut self, now: Instant, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> usize {
+        let max_segments = self.gro_segments;
+        let mut count = 0;
+        for (buf, meta) in bufs.iter_mut().zip(meta.iter_mut()) {
+            let first = match self.queue.peek() {
+                Some(datagram) if datagram.deliver_at <= now => self.queue.pop().unwrap(),
+                _ => break,
+            };
+            let stride = first.contents.len();
+            let mut len = copy(buf, 0, &first.contents);
+            let mut segments = 1;
+            // Coalesce like GRO: same source, size and ECN, until a shorter datagram ends it
+            while segments < max_segments && len == segments * stride && stride > 0 {
+                match self.queue.peek() {
+                    Some(next)
+                        if next.deliver_at <= now
+                            && next.src == first.src
+                            && next.dst_ip == first.dst_ip
+                            && next.ecn == first.ecn
+                            && next.contents.len() <= stride
+                            && len + next.contents.len() <= buf.len() => {}
+                    _ => break,
+                }
+                let next = self.queue.pop().unwrap();
+                len += copy(buf, len, &next.contents);
+                segments += 1;
+            }
+            *meta = RecvMeta {
+                addr: first.src,
+                len,
+                stride: stride.min(len),
+                ecn: first.ecn,
+                dst_ip: Some(first.dst_ip),
+            };
+            count += 1;
+        }
+        count
+    }
+
+    /// Wakes the task behind `cx` once a datagram arrives or a queued one becomes due
+    ///
+    /// Returns [`Poll::Ready`] if the timer of the runtime finds the next datagram already due.
+    fn register(&mut self, clock: &Clock, cx: &mut Context<'_>) -> Poll<()> {
+        self.waker = Some(cx.waker().clone());
+        let (Some(runtime), Some(next)) = (&clock.runtime, self.queue.peek()) else {
+            return Poll::Pending;
+        };
+        // The clock runs ahead of the runtime by the time it was advanced
+        let deadline = next
+            .deliver_at
+            .checked_sub(clock.offset)
+            .unwrap_or(clock.start);
+        match &mut self.timer {
+            Some(timer) => timer.as_mut().reset(deadline),
+            None => self.timer = Some(runtime.new_timer(deadline)),
+        }
+        self.timer.as_mut().unwrap().as_mut().poll(cx)
+    }
+
+    /// Receives the datagrams which are due, or registers to be woken
+    fn poll_recv(
+        &mut self,
+        clock: &Clock,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<usize> {
+        loop {
+            match self.recv(clock.now(), bufs, meta) {
+                0 => ready!(self.register(clock, cx)),
+                count => return Poll::Ready(count),
+            }
+        }
+    }
+}
+
+impl VirtualUdpSocket {
+    /// Only receives datagrams from `addr` from now on, and sends transmits without a
+    /// destination to it
+    pub fn connect(&self, addr: SocketAddr) -> io::Result<()> {
+        self.endpoint.lock().unwrap().peer = Some(addr);
+        Ok(())
+    }
+
+    /// Dissolves the association set up by [`VirtualUdpSocket::connect`]
+    pub fn disconnect(&self) -> io::Result<()> {
+        self.endpoint.lock().unwrap().peer = None;
+        Ok(())
+    }
+
+    /// Sets the number of datagrams which may be coalesced into a single receive buffer
+    ///
+    /// Like GRO, consecutive datagrams from the same source with the same size and ECN codepoint
+    /// are combined, and [`RecvMeta::stride`] reports their size. Defaults to 1, which disables
+    /// coalescing.
+    pub fn set_gro_segments(&self, segments: usize) {
+        self.endpoint.lock().unwrap().gro_segments = segments.max(1);
+    }
+
+    pub fn network(&self) -> &VirtualNetwork {
+        &self.network
+    }
+
+    pub async fn send(
+        &self,
+        capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_send(cx, capabilities, transmits)).await
+    }
+
+    pub async fn recv(
+        &self,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_recv(cx, bufs, meta)).await
+    }
+
+    fn src_addr(&self, src_ip: Option<IpAddr>) -> SocketAddr {
+        let ip = match (src_ip, self.addr.ip()) {
+            (Some(ip), _) => ip,
+            (None, IpAddr::V4(ip)) if ip.is_unspecified() => Ipv4Addr::LOCALHOST.into(),
+            (None, IpAddr::V6(ip)) if ip.is_unspecified() => Ipv6Addr::LOCALHOST.into(),
+            (None, ip) => ip,
+        };
+        SocketAddr::new(ip, self.addr.port())
+    }
+}
+
+impl AsyncUdpSocket for VirtualUdpSocket {
+    /// Hands datagrams to the network, which never blocks
+    ///
+    /// Transmits with a [`Transmit::segment_size`] are split into separate datagrams, each of
+    /// which is impaired on its own.
+    fn poll_send(
+        &self,
+        _cx: &mut Context<'_>,
+        _capabilities: &Capabilities,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        let peer = self.endpoint.lock().unwrap().peer;
+        let mut state = self.network.state.lock().unwrap();
+        let now = state.clock.now();
+        let mut wakers = Vec::new();
+        for transmit in transmits {
+            let dst = match transmit.destination.or(peer) {
+                Some(dst) => dst,
+                None => return Poll::Ready(Err(not_connected())),
+            };
+            let src = self.src_addr(transmit.src_ip);
+            let segment_size = match transmit.segment_size {
+                Some(size) if size > 0 => size,
+                _ => transmit.contents.len().max(1),
+            };
+            for segment in transmit.contents.chunks(segment_size) {
+                let datagrams = state.impair(src, dst, transmit.ecn, segment, now);
+                let endpoint = match state.lookup(dst) {
+                    Some(endpoint) => endpoint,
+                    // Nobody is listening, the datagram is lost
+                    None => continue,
+                };
+                let mut endpoint = endpoint.lock().unwrap();
+                if endpoint.peer.is_some_and(|peer| peer != src) {
+                    continue;
+                }
+                for datagram in datagrams {
+                    if endpoint.queue.len() < RECV_QUEUE_LEN {
+                        endpoint.queue.push(datagram);
+                    }
+                }
+                wakers.extend(endpoint.waker.take());
+            }
+        }
+        drop(state);
+        for waker in wakers {
+            waker.wake();
+        }
+        Poll::Ready(Ok(transmits.len()))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let clock = self.network.clock();
+        let mut endpoint = self.endpoint.lock().unwrap();
+        endpoint.poll_recv(&clock, cx, bufs, meta).map(Ok)
+    }
+
+    /// Never waits, as sending never blocks
+    fn poll_writable(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let clock = self.network.clock();
+        let mut endpoint = self.endpoint.lock().unwrap();
+        loop {
+            match endpoint.queue.peek() {
+                Some(datagram) if datagram.deliver_at <= clock.now() => return Poll::Ready(Ok(())),
+                _ => ready!(endpoint.register(&clock, cx)),
+            }
+        }
+    }
+
+    fn try_send(&self, capabilities: &Capabilities, transmits: &[Transmit]) -> io::Result<usize> {
+        crate::runtime::try_poll(|cx| self.poll_send(cx, capabilities, transmits))
+    }
+
+    fn try_recv(&self, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> io::Result<usize> {
+        let now = self.network.now();
+        match self.endpoint.lock().unwrap().recv(now, bufs, meta) {
+            0 => Err(io::ErrorKind::WouldBlock.into()),
+            count => Ok(count),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint
+            .lock()
+            .unwrap()
+            .peer
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "socket is not connected"))
+    }
+}
+
+impl Drop for VirtualUdpSocket {
+    fn drop(&mut self) {
+        self.network
+            .state
+            .lock()
+            .unwrap()
+            .sockets
+            .remove(&self.addr);
+    }
+}
+
+impl fmt::Debug for VirtualUdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VirtualUdpSocket")
+            .field("addr", &self.addr)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Copies as much of `data` as fits into `buf` at `offset`, like a truncating `recvmsg`
+fn copy(buf: &mut [u8], offset: usize, data: &[u8]) -> usize {
+    let n = data.len().min(buf.len().saturating_sub(offset));
+    buf[offset..offset + n].copy_from_slice(&data[..n]);
+    n
+}
+
+/// A datagram in flight, ordered by delivery time and then by the order it was sent in
+struct Datagram {
+    deliver_at: Instant,
+    seq: u64,
+    src: SocketAddr,
+    dst_ip: IpAddr,
+    ecn: Option<EcnCodepoint>,
+    contents: Vec<u8>,
+}
+
+// Ordered so that the next datagram to deliver is at the top of the max-heap
+impl Ord for Datagram {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deliver_at, other.seq).cmp(&(self.deliver_at, self.seq))
+    }
+}
+
+impl PartialOrd for Datagram {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Datagram {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Datagram {}
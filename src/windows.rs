@@ -1,15 +1,20 @@
 use std::{
     io::{self, IoSliceMut},
     mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     os::windows::io::AsRawSocket,
-    sync::atomic::AtomicU64,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        RwLock,
+    },
     time::Instant,
 };
 
 use windows_sys::Win32::Networking::WinSock;
 
 use super::{
-    log_sendmsg_error, Capabilities, RecvMeta, Transmit, UdpSockRef, IO_ERROR_LOG_INTERVAL,
+    log_sendmsg_error, not_connected, to_canonical, to_mapped, Capabilities, RecvMeta, Transmit,
+    UdpSockRef, IO_ERROR_LOG_INTERVAL,
 };
 
 /// QUIC-friendly UDP interface for Windows
@@ -17,6 +22,8 @@ use super::{
 pub struct UdpSocketState {
     epoch: Instant,
     last_send_error: AtomicU64,
+    canonicalize_mapped_ipv4: AtomicBool,
+    peer: RwLock<Option<SocketAddr>>,
 }
 
 impl UdpSocketState {
@@ -25,6 +32,8 @@ impl UdpSocketState {
         Self {
             epoch: now.checked_sub(2 * IO_ERROR_LOG_INTERVAL).unwrap_or(now),
             last_send_error: AtomicU64::new(0),
+            canonicalize_mapped_ipv4: AtomicBool::new(false),
+            peer: RwLock::new(None),
         }
     }
 
@@ -84,18 +93,82 @@ impl UdpSocketState {
         Ok(())
     }
 
+    /// Reports IPv4 peers of a dual-stack IPv6 socket as [`std::net::SocketAddr::V4`]
+    ///
+    /// When enabled, [`RecvMeta::addr`] holds plain IPv4 addresses instead of IPv4-mapped IPv6
+    /// addresses, and IPv4 [`Transmit::destination`]s are mapped back before sending. This has
+    /// no effect on IPv4 sockets.
+    pub fn set_canonicalize_mapped_ipv4(
+        &self,
+        socket: UdpSockRef<'_>,
+        enabled: bool,
+    ) -> io::Result<()> {
+        let is_ipv6 = socket.0.local_addr()?.is_ipv6();
+        self.canonicalize_mapped_ipv4
+            .store(enabled && is_ipv6, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Whether IPv4-mapped IPv6 addresses are converted to plain IPv4 addresses
+    pub fn canonicalize_mapped_ipv4(&self) -> bool {
+        self.canonicalize_mapped_ipv4.load(Ordering::Relaxed)
+    }
+
+    /// Connects the socket to `addr`
+    ///
+    /// Afterwards, transmits without a [`Transmit::destination`] are sent to `addr`.
+    pub fn connect(&self, socket: UdpSockRef<'_>, addr: SocketAddr) -> io::Result<()> {
+        let target = match self.canonicalize_mapped_ipv4() {
+            true => to_mapped(addr),
+            false => addr,
+        };
+        socket.0.connect(&target.into())?;
+        *self.peer.write().unwrap() = Some(addr);
+        Ok(())
+    }
+
+    /// Dissolves the association set up by [`UdpSocketState::connect`]
+    pub fn disconnect(&self, socket: UdpSockRef<'_>) -> io::Result<()> {
+        // Connecting to the unspecified address dissolves the association on Windows
+        let unspecified = match socket.0.local_addr()?.is_ipv6() {
+            true => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            false => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        };
+        socket.0.connect(&unspecified.into())?;
+        *self.peer.write().unwrap() = None;
+        Ok(())
+    }
+
+    /// The address the socket is connected to, if any
+    pub fn peer(&self) -> Option<SocketAddr> {
+        *self.peer.read().unwrap()
+    }
+
     pub fn send(
         &self,
         socket: UdpSockRef<'_>,
         _capabilities: &Capabilities,
         transmits: &[Transmit],
     ) -> Result<usize, io::Error> {
+        let map_ipv4 = self.canonicalize_mapped_ipv4();
         let mut sent = 0;
         for transmit in transmits {
-            match socket.0.send_to(
-                &transmit.contents,
-                &socket2::SockAddr::from(transmit.destination),
-            ) {
+            let result = match transmit.destination {
+                Some(destination) => {
+                    let destination = match map_ipv4 {
+                        true => to_mapped(destination),
+                        false => destination,
+                    };
+                    socket
+                        .0
+                        .send_to(&transmit.contents, &socket2::SockAddr::from(destination))
+                }
+                // Sent to the peer of a connected socket
+                None if self.peer().is_some() => socket.0.send(&transmit.contents),
+                None if sent == 0 => return Err(not_connected()),
+                None => return Ok(sent),
+            };
+            match result {
                 Ok(_) => {
                     sent += 1;
                 }
@@ -131,11 +204,21 @@ impl UdpSocketState {
         let bufs = unsafe {
             &mut *(bufs as *mut [IoSliceMut<'_>] as *mut [socket2::MaybeUninitSlice<'_>])
         };
-        let (len, _flags, addr) = socket.0.recv_from_vectored(bufs)?;
+        let (len, addr) = match self.peer() {
+            // Connected sockets only receive datagrams from their peer
+            Some(peer) => (socket.0.recv_vectored(bufs)?.0, peer),
+            None => {
+                let (len, _flags, addr) = socket.0.recv_from_vectored(bufs)?;
+                (len, addr.as_socket().unwrap())
+            }
+        };
         meta[0] = RecvMeta {
             len,
             stride: len,
-            addr: addr.as_socket().unwrap(),
+            addr: match self.canonicalize_mapped_ipv4() {
+                true => to_canonical(addr),
+                false => addr,
+            },
             ecn: None,
             dst_ip: None,
         };
@@ -154,6 +237,14 @@ pub fn capabilities() -> super::Capabilities {
     super::Capabilities {
         max_gso_segments: std::sync::atomic::AtomicUsize::new(1),
         gro_segments: 1,
+        send_ecn: false,
+        recv_ecn: false,
+        recv_dst_ip: false,
+        send_src_ip: false,
+        timestamp_modes: super::TimestampModes::default(),
+        send_batching: false,
+        recv_batching: false,
+        batch_size: BATCH_SIZE,
     }
 }
 
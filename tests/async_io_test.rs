@@ -1,17 +1,20 @@
-#[cfg(not(feature = "metal-io"))]
+#[macro_use]
+mod common;
+
 #[cfg(test)]
-mod tests {
+runtime_tests! {
     use anyhow::Result;
     use async_transport::{
-        AsyncUdpSocket, Capabilities, EcnCodepoint, RecvMeta, Transmit, UdpSocket, BATCH_SIZE,
+        AsyncUdpSocket, Capabilities, EcnCodepoint, RecvMeta, Transmit, BATCH_SIZE,
     };
     use std::io::IoSliceMut;
     use std::net::Ipv4Addr;
     use std::time::Instant;
+    use rt::UdpSocket;
 
-    #[tokio::test]
+    #[test]
     async fn test_ecn() -> Result<()> {
-        env_logger::init();
+        let _ = env_logger::try_init();
         let capabilities = Capabilities::new();
         let socket1 = UdpSocket::bind("127.0.0.1:0").await?;
         let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
@@ -21,7 +24,7 @@ mod tests {
         for i in 0..1 {
             let contents = (i as u64).to_be_bytes().to_vec();
             transmits.push(Transmit {
-                destination: addr2,
+                destination: Some(addr2),
                 ecn: Some(EcnCodepoint::Ce),
                 segment_size: None,
                 contents,
@@ -29,9 +32,7 @@ mod tests {
             });
         }
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
-
-        tokio::spawn(async move {
+        let receiver = exec::spawn(async move {
             let mut storage = [[0u8; 1200]; BATCH_SIZE];
             let mut buffers = Vec::with_capacity(BATCH_SIZE);
             let mut rest = &mut storage[..];
@@ -51,7 +52,7 @@ mod tests {
                     &meta[i]
                 );
             }
-            let _ = tx.send(meta[0].ecn).await;
+            meta[0].ecn
         });
 
         let start = Instant::now();
@@ -62,7 +63,7 @@ mod tests {
 
         println!("sent {} packets in {}ms", 1, start.elapsed().as_millis());
 
-        let ecn = rx.recv().await.unwrap();
+        let ecn = receiver.await;
 
         #[cfg(not(windows))]
         {
@@ -0,0 +1,99 @@
+#[cfg(any(unix, windows))]
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use async_transport::{blocking::UdpSocket, Capabilities, EcnCodepoint, RecvMeta, Transmit};
+    use std::io::{self, IoSliceMut};
+    use std::net::Ipv4Addr;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn recv(socket: &UdpSocket) -> io::Result<Vec<(RecvMeta, Vec<u8>)>> {
+        let mut storage = vec![[0u8; u16::MAX as usize]; 4];
+        let mut buffers = storage
+            .iter_mut()
+            .map(|b| IoSliceMut::new(b))
+            .collect::<Vec<_>>();
+        let mut meta = [RecvMeta::default(); 4];
+        let n = socket.recv(&mut buffers, &mut meta)?;
+        Ok((0..n)
+            .map(|i| (meta[i], buffers[i][..meta[i].len].to_vec()))
+            .collect())
+    }
+
+    #[test]
+    fn test_send_recv() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let socket1 = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let socket2 = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+
+        // The receiver blocks until the datagram arrives from another thread
+        let receiver = thread::spawn(move || recv(&socket2));
+        thread::sleep(Duration::from_millis(50));
+        let segment_size = match capabilities.max_gso_segments() > 1 {
+            true => Some(1000),
+            false => None,
+        };
+        let transmit = Transmit {
+            destination: Some(addr2),
+            ecn: Some(EcnCodepoint::Ect0),
+            contents: vec![7; 2500],
+            segment_size,
+            src_ip: None,
+        };
+        assert_eq!(socket1.send(&capabilities, &[transmit])?, 1);
+
+        let received = receiver.join().unwrap()?;
+        let (meta, contents) = &received[0];
+        assert_eq!(meta.addr, addr1);
+        assert!(contents.iter().all(|&b| b == 7));
+        #[cfg(not(windows))]
+        assert_eq!(meta.ecn, Some(EcnCodepoint::Ect0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_timeout() -> Result<()> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        socket.set_read_timeout(Some(Duration::from_millis(100)));
+        assert_eq!(socket.read_timeout(), Some(Duration::from_millis(100)));
+
+        let start = Instant::now();
+        let err = recv(&socket).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        socket.set_read_timeout(Some(Duration::ZERO));
+        assert_eq!(recv(&socket).unwrap_err().kind(), io::ErrorKind::TimedOut);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recv_timeout_and_deadline() -> Result<()> {
+        let socket1 = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let socket2 = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let mut buf = [0u8; 1500];
+        let mut meta = [RecvMeta::default()];
+
+        let start = Instant::now();
+        let err = socket2
+            .recv_timeout(
+                &mut [IoSliceMut::new(&mut buf)],
+                &mut meta,
+                Duration::from_millis(50),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Queued datagrams are returned even after the deadline
+        socket1.send_to(b"hello", socket2.local_addr()?)?;
+        thread::sleep(Duration::from_millis(20));
+        let n = socket2.recv_deadline(&mut [IoSliceMut::new(&mut buf)], &mut meta, start)?;
+        assert_eq!(n, 1);
+        assert_eq!(&buf[..meta[0].len], b"hello");
+        Ok(())
+    }
+}
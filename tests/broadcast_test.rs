@@ -0,0 +1,64 @@
+#[macro_use]
+mod common;
+
+#[cfg(target_os = "linux")]
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{interface_addrs, AsyncUdpSocket, RecvMeta};
+    use std::io::{self, IoSliceMut};
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use rt::UdpSocket;
+
+    #[test]
+    async fn test_send_broadcast() -> Result<()> {
+        let receiver = UdpSocket::bind("0.0.0.0:0").await?;
+        let sender = UdpSocket::bind("0.0.0.0:0").await?;
+        let port = receiver.local_addr()?.port();
+
+        assert!(!sender.broadcast()?);
+        sender.set_broadcast(true)?;
+        assert!(sender.broadcast()?);
+
+        let interfaces = interface_addrs()?
+            .into_iter()
+            .filter(|addr| addr.broadcast.is_some())
+            .collect::<Vec<_>>();
+        let sent = sender.send_broadcast(b"hello", port).await?;
+        assert_eq!(sent, interfaces.len());
+
+        let mut buf = [0u8; 1500];
+        let mut meta = [RecvMeta::default()];
+        for _ in 0..sent {
+            receiver
+                .recv_timeout(
+                    &mut [IoSliceMut::new(&mut buf)],
+                    &mut meta,
+                    Duration::from_secs(1),
+                )
+                .await?;
+            assert_eq!(&buf[..meta[0].len], b"hello");
+            let interface = interfaces
+                .iter()
+                .find(|interface| interface.addr == meta[0].addr.ip())
+                .expect("sent from a broadcast interface");
+            assert_eq!(meta[0].dst_ip, interface.broadcast.map(IpAddr::V4));
+        }
+        Ok(())
+    }
+
+    #[test]
+    async fn test_send_broadcast_disabled() -> Result<()> {
+        let sender = UdpSocket::bind("0.0.0.0:0").await?;
+        if interface_addrs()?
+            .iter()
+            .all(|addr| addr.broadcast.is_none())
+        {
+            return Ok(());
+        }
+        let err = sender.send_broadcast(b"hello", 9).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        Ok(())
+    }
+}
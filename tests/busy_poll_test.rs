@@ -0,0 +1,51 @@
+#[macro_use]
+mod common;
+
+#[cfg(target_os = "linux")]
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{AsyncUdpSocket, BusyPollConfig, RecvMeta};
+    use std::io::IoSliceMut;
+    use std::thread;
+    use std::time::Duration;
+    use rt::UdpSocket;
+
+    #[test]
+    async fn test_busy_poll() -> Result<()> {
+        let socket1 = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr2 = socket2.local_addr()?;
+
+        // Lowering the kernel options needs no privileges, unlike raising them
+        let config = BusyPollConfig {
+            busy_poll: Some(Duration::ZERO),
+            prefer_busy_poll: Some(true),
+            budget: None,
+            spin: Duration::from_millis(5),
+        };
+        socket2.set_busy_poll(&config)?;
+        let applied = socket2.busy_poll()?;
+        assert_eq!(applied.busy_poll, Some(Duration::ZERO));
+        assert_eq!(applied.prefer_busy_poll, Some(true));
+        assert_eq!(applied.spin, Duration::from_millis(5));
+
+        let mut buf = [0u8; 1500];
+        let mut meta = [RecvMeta::default()];
+        // Arrives while spinning, then once the socket waits on the reactor again
+        for delay in [1, 20] {
+            let sender = socket1.try_clone()?;
+            let handle = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(delay));
+                sender.send_to(b"hello", addr2)
+            });
+            let n = socket2
+                .recv(&mut [IoSliceMut::new(&mut buf)], &mut meta)
+                .await?;
+            assert_eq!(n, 1);
+            assert_eq!(&buf[..meta[0].len], b"hello");
+            handle.join().unwrap()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+#[cfg(test)]
+mod tests {
+    use async_transport::{Capabilities, BATCH_SIZE};
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = Capabilities::new();
+
+        assert_eq!(capabilities.batch_size(), BATCH_SIZE);
+        assert_eq!(capabilities.send_batching(), BATCH_SIZE > 1);
+        assert_eq!(capabilities.recv_batching(), BATCH_SIZE > 1);
+        assert!(capabilities.max_gso_segments() >= 1);
+        assert!(capabilities.gro_segments() >= 1);
+
+        #[cfg(target_os = "linux")]
+        {
+            assert!(capabilities.send_ecn());
+            assert!(capabilities.recv_ecn());
+            assert!(capabilities.recv_dst_ip());
+            assert!(capabilities.send_src_ip());
+            assert!(capabilities.timestamp_modes().microseconds);
+            assert!(capabilities.timestamp_modes().nanoseconds);
+        }
+
+        #[cfg(windows)]
+        {
+            assert!(!capabilities.send_ecn());
+            assert!(!capabilities.recv_ecn());
+        }
+    }
+}
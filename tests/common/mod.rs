@@ -0,0 +1,192 @@
+//! Runs test suites against every enabled runtime backend
+//!
+//! Not every test crate runs a suite in every configuration.
+#![allow(dead_code, unused_imports, unused_macros)]
+
+use std::future::{poll_fn, Future};
+use std::io;
+use std::pin::pin;
+use std::task::Poll;
+
+/// Expands the suite once for every enabled runtime, in a module named after it
+///
+/// Within the suite, `rt` is the module with the sockets of the runtime, such as
+/// `async_transport::smol`, and `exec` the matching module of this file to spawn, sleep and time
+/// out with. Functions marked `#[test] async fn` run to completion on the executor of the
+/// runtime, a fresh one for every test on tokio.
+macro_rules! runtime_tests {
+    (@runtime $feature:literal, $runtime:ident, $($suite:tt)*) => {
+        #[cfg(feature = $feature)]
+        mod $runtime {
+            #[allow(unused_imports)]
+            use async_transport::$runtime as rt;
+            #[allow(unused_imports)]
+            use $crate::common::$runtime as exec;
+
+            runtime_tests!(@items $($suite)*);
+        }
+    };
+    (@items
+        $(#[doc = $doc:expr])*
+        $(#[cfg($($cfg:tt)*)])*
+        #[test]
+        $(#[$attr:meta])*
+        async fn $name:ident() -> $ret:ty $body:block
+        $($rest:tt)*
+    ) => {
+        $(#[doc = $doc])*
+        $(#[cfg($($cfg)*)])*
+        #[test]
+        $(#[$attr])*
+        fn $name() -> $ret {
+            exec::block_on(async move $body)
+        }
+
+        runtime_tests!(@items $($rest)*);
+    };
+    (@items $item:item $($rest:tt)*) => {
+        $item
+
+        runtime_tests!(@items $($rest)*);
+    };
+    (@items) => {};
+    ($($suite:tt)*) => {
+        runtime_tests!(@runtime "runtime-smol", smol, $($suite)*);
+        runtime_tests!(@runtime "runtime-tokio", tokio, $($suite)*);
+        runtime_tests!(@runtime "runtime-async-std", async_std, $($suite)*);
+    };
+}
+
+/// Runs `future` on the executor of the `smol` runtime
+#[cfg(feature = "runtime-smol")]
+pub mod smol {
+    use std::future::Future;
+    use std::io;
+    use std::time::Duration;
+
+    pub fn block_on<T>(future: impl Future<Output = T>) -> T {
+        ::smol::block_on(future)
+    }
+
+    pub fn spawn<T: Send + 'static>(
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> impl Future<Output = T> {
+        super::spawn_with(future, |task| ::smol::spawn(task).detach())
+    }
+
+    pub async fn sleep(duration: Duration) {
+        ::smol::Timer::after(duration).await;
+    }
+
+    pub async fn timeout<T>(duration: Duration, future: impl Future<Output = T>) -> io::Result<T> {
+        super::timeout_with(sleep(duration), future).await
+    }
+
+    pub use super::yield_now;
+}
+
+/// Runs `future` on a current thread `tokio` runtime, like `#[tokio::test]`
+#[cfg(feature = "runtime-tokio")]
+pub mod tokio {
+    use std::future::Future;
+    use std::io;
+    use std::time::Duration;
+
+    pub fn block_on<T>(future: impl Future<Output = T>) -> T {
+        ::tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build tokio runtime")
+            .block_on(future)
+    }
+
+    pub fn spawn<T: Send + 'static>(
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> impl Future<Output = T> {
+        super::spawn_with(future, |task| drop(::tokio::spawn(task)))
+    }
+
+    pub async fn sleep(duration: Duration) {
+        ::tokio::time::sleep(duration).await;
+    }
+
+    pub async fn timeout<T>(duration: Duration, future: impl Future<Output = T>) -> io::Result<T> {
+        super::timeout_with(sleep(duration), future).await
+    }
+
+    pub use super::yield_now;
+}
+
+/// Runs `future` on the executor of the `async-std` runtime
+#[cfg(feature = "runtime-async-std")]
+pub mod async_std {
+    use std::future::Future;
+    use std::io;
+    use std::time::Duration;
+
+    pub fn block_on<T>(future: impl Future<Output = T>) -> T {
+        ::async_std::task::block_on(future)
+    }
+
+    pub fn spawn<T: Send + 'static>(
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> impl Future<Output = T> {
+        super::spawn_with(future, |task| drop(::async_std::task::spawn(task)))
+    }
+
+    pub async fn sleep(duration: Duration) {
+        ::async_std::task::sleep(duration).await;
+    }
+
+    pub async fn timeout<T>(duration: Duration, future: impl Future<Output = T>) -> io::Result<T> {
+        super::timeout_with(sleep(duration), future).await
+    }
+
+    pub use super::yield_now;
+}
+
+/// Hands `future` to `spawn` as a detached task, returning a future for its output
+fn spawn_with<T: Send + 'static>(
+    future: impl Future<Output = T> + Send + 'static,
+    spawn: impl FnOnce(std::pin::Pin<Box<dyn Future<Output = ()> + Send>>),
+) -> impl Future<Output = T> {
+    let (tx, rx) = futures::channel::oneshot::channel();
+    spawn(Box::pin(async move {
+        let _ = tx.send(future.await);
+    }));
+    async move { rx.await.expect("spawned task panicked") }
+}
+
+/// Completes `future`, unless `sleep` completes first which fails with
+/// [`io::ErrorKind::TimedOut`]
+async fn timeout_with<T>(
+    sleep: impl Future<Output = ()>,
+    future: impl Future<Output = T>,
+) -> io::Result<T> {
+    let mut sleep = pin!(sleep);
+    let mut future = pin!(future);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(io::ErrorKind::TimedOut.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Lets other tasks of the executor run before continuing
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
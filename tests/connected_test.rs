@@ -0,0 +1,152 @@
+#[macro_use]
+mod common;
+
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, EcnCodepoint, RecvMeta, Transmit, BATCH_SIZE,
+    };
+    use std::io::IoSliceMut;
+    use rt::UdpSocket;
+
+    async fn recv(socket: &UdpSocket) -> Result<Vec<(RecvMeta, Vec<u8>)>> {
+        let mut storage = [[0u8; 1200]; BATCH_SIZE];
+        let mut buffers = storage
+            .iter_mut()
+            .map(|b| IoSliceMut::new(b))
+            .collect::<Vec<_>>();
+        let mut meta = [RecvMeta::default(); BATCH_SIZE];
+        let n = socket.recv(&mut buffers, &mut meta).await?;
+        Ok((0..n)
+            .map(|i| (meta[i], buffers[i][..meta[i].len].to_vec()))
+            .collect())
+    }
+
+    #[test]
+    async fn test_connected() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let socket1 = UdpSocket::bind("127.0.0.1:0").await?;
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+        let socket3 = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+        let addr3 = socket3.local_addr()?;
+
+        socket1.connect(addr2).await?;
+        socket2.connect(addr1).await?;
+        assert_eq!(socket1.peer_addr()?, addr2);
+
+        // Transmits without a destination go to the peer
+        let transmits = (0..BATCH_SIZE.min(4))
+            .map(|i| Transmit {
+                destination: None,
+                ecn: Some(EcnCodepoint::Ect0),
+                contents: vec![i as u8; 8],
+                segment_size: None,
+                src_ip: None,
+            })
+            .collect::<Vec<_>>();
+        let mut sent = 0;
+        while sent < transmits.len() {
+            sent += socket1.send(&capabilities, &transmits[sent..]).await?;
+        }
+        let mut received = Vec::new();
+        while received.len() < transmits.len() {
+            received.extend(recv(&socket2).await?);
+        }
+        for (i, (meta, contents)) in received.into_iter().enumerate() {
+            assert_eq!(meta.addr, addr1);
+            assert_eq!(contents, vec![i as u8; 8]);
+            #[cfg(not(windows))]
+            assert_eq!(meta.ecn, Some(EcnCodepoint::Ect0));
+        }
+
+        // Once disconnected, datagrams from any source are received again
+        socket2.disconnect()?;
+        assert!(socket2.peer_addr().is_err());
+        assert_eq!(socket2.local_addr()?, addr2);
+        socket3
+            .send(
+                &capabilities,
+                &[Transmit {
+                    destination: Some(addr2),
+                    ecn: None,
+                    contents: b"hello".to_vec(),
+                    segment_size: None,
+                    src_ip: None,
+                }],
+            )
+            .await?;
+        let received = recv(&socket2).await?;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0.addr, addr3);
+        assert_eq!(received[0].1, b"hello");
+
+        // A disconnected socket can be connected to a different peer
+        socket2.connect(addr3).await?;
+        assert_eq!(socket2.peer_addr()?, addr3);
+
+        Ok(())
+    }
+    #[test]
+    async fn test_disconnect_wildcard() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let socket1 = UdpSocket::bind("0.0.0.0:0").await?;
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+
+        // While connected, the socket is bound to the address the route picked
+        socket1.connect(addr2).await?;
+        socket1.disconnect()?;
+        assert_eq!(socket1.local_addr()?, addr1);
+
+        socket2
+            .send(
+                &capabilities,
+                &[Transmit {
+                    destination: Some(([127, 0, 0, 1], addr1.port()).into()),
+                    ecn: None,
+                    contents: b"hello".to_vec(),
+                    segment_size: None,
+                    src_ip: None,
+                }],
+            )
+            .await?;
+        let received = recv(&socket1).await?;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0.addr, addr2);
+        assert_eq!(received[0].1, b"hello");
+
+        Ok(())
+    }
+
+    #[test]
+    async fn test_unconnected_without_destination() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let socket1 = UdpSocket::bind("127.0.0.1:0").await?;
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr2 = socket2.local_addr()?;
+
+        let transmit = |destination| Transmit {
+            destination,
+            ecn: None,
+            contents: vec![1; 8],
+            segment_size: None,
+            src_ip: None,
+        };
+        let err = socket1
+            .send(&capabilities, &[transmit(None)])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotConnected);
+
+        // Transmits ahead of the one without a destination are still sent
+        let transmits = [transmit(Some(addr2)), transmit(None)];
+        assert_eq!(socket1.send(&capabilities, &transmits).await?, 1);
+        assert_eq!(recv(&socket2).await?.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+#[macro_use]
+mod common;
+
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, EcnCodepoint, RecvMeta, Transmit, BATCH_SIZE,
+    };
+    use std::io::IoSliceMut;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use rt::UdpSocket;
+
+    async fn recv_one(socket: &UdpSocket) -> Result<RecvMeta> {
+        let mut storage = [[0u8; 1200]; BATCH_SIZE];
+        let mut buffers = storage
+            .iter_mut()
+            .map(|b| IoSliceMut::new(b))
+            .collect::<Vec<_>>();
+        let mut meta = [RecvMeta::default(); BATCH_SIZE];
+        let n = socket.recv(&mut buffers, &mut meta).await?;
+        assert_eq!(n, 1);
+        Ok(meta[0])
+    }
+
+    #[test]
+    async fn test_canonicalize_mapped_ipv4() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let dual = match UdpSocket::bind("[::]:0").await {
+            Ok(socket) => socket,
+            // No IPv6 support on this host
+            Err(_) => return Ok(()),
+        };
+        dual.set_canonicalize_mapped_ipv4(true)?;
+        let dual_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), dual.local_addr()?.port());
+
+        let v4 = UdpSocket::bind("127.0.0.1:0").await?;
+        let v4_addr = v4.local_addr()?;
+
+        // IPv4 destination and source are mapped back for the dual-stack socket
+        dual.send(
+            &capabilities,
+            &[Transmit {
+                destination: Some(v4_addr),
+                ecn: Some(EcnCodepoint::Ce),
+                contents: b"ping".to_vec(),
+                segment_size: None,
+                src_ip: Some(Ipv4Addr::LOCALHOST.into()),
+            }],
+        )
+        .await?;
+        let meta = recv_one(&v4).await?;
+        assert_eq!(meta.addr, dual_addr);
+        #[cfg(target_os = "linux")]
+        assert_eq!(meta.ecn, Some(EcnCodepoint::Ce));
+
+        v4.send(
+            &capabilities,
+            &[Transmit {
+                destination: Some(dual_addr),
+                ecn: Some(EcnCodepoint::Ect0),
+                contents: b"pong".to_vec(),
+                segment_size: None,
+                src_ip: None,
+            }],
+        )
+        .await?;
+        let meta = recv_one(&dual).await?;
+        assert_eq!(meta.addr, v4_addr);
+        #[cfg(not(windows))]
+        assert_eq!(meta.ecn, Some(EcnCodepoint::Ect0));
+        #[cfg(target_os = "linux")]
+        assert_eq!(meta.dst_ip, Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+
+        // Without canonicalization, IPv4 peers show up as IPv4-mapped addresses
+        dual.set_canonicalize_mapped_ipv4(false)?;
+        v4.send_to(b"pong", dual_addr).await?;
+        let meta = recv_one(&dual).await?;
+        assert_eq!(
+            meta.addr.ip(),
+            IpAddr::V6(Ipv4Addr::LOCALHOST.to_ipv6_mapped())
+        );
+
+        Ok(())
+    }
+}
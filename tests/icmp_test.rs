@@ -0,0 +1,116 @@
+#[macro_use]
+mod common;
+
+#[cfg(unix)]
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{EcnCodepoint, IcmpEcho, IcmpRecvMeta, IcmpTransmit};
+    use std::io::{self, IoSliceMut};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use rt::IcmpSocket;
+
+    /// Binds an ICMP socket, or returns `None` if unprivileged ICMP sockets are not allowed
+    fn bind(addr: IpAddr) -> Result<Option<IcmpSocket>> {
+        match IcmpSocket::bind(addr) {
+            Ok(socket) => Ok(Some(socket)),
+            Err(e)
+                if e.kind() == io::ErrorKind::PermissionDenied
+                    || e.raw_os_error() == Some(libc::EPROTONOSUPPORT)
+                    || e.raw_os_error() == Some(libc::EAFNOSUPPORT)
+                    || e.raw_os_error() == Some(libc::EADDRNOTAVAIL) =>
+            {
+                eprintln!("skipping, unprivileged ICMP sockets are unavailable: {e}");
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn ping(addr: IpAddr) -> Result<()> {
+        let socket = match bind(addr)? {
+            Some(socket) => socket,
+            None => return Ok(()),
+        };
+        let ipv6 = addr.is_ipv6();
+        let request = IcmpEcho {
+            identifier: socket.local_addr()?.port(),
+            sequence: 7,
+            payload: b"async-transport".to_vec(),
+        };
+        socket
+            .send(&[IcmpTransmit {
+                destination: addr,
+                ecn: Some(EcnCodepoint::Ect0),
+                contents: request.encode_request(ipv6),
+                src_ip: None,
+            }])
+            .await?;
+
+        let mut storage = [0u8; 1500];
+        let mut meta = [IcmpRecvMeta::default()];
+        let reply = loop {
+            let mut buffers = [IoSliceMut::new(&mut storage)];
+            socket.recv(&mut buffers, &mut meta).await?;
+            // Skip anything that is not our reply, such as the request looping back over IPv6
+            if let Some(reply) = IcmpEcho::decode_reply(&storage[..meta[0].len], ipv6) {
+                break reply;
+            }
+        };
+        assert_eq!(reply, request);
+        assert_eq!(meta[0].addr, addr);
+        assert_eq!(meta[0].dst_ip, Some(addr));
+        assert_eq!(meta[0].ecn, Some(EcnCodepoint::Ect0));
+        assert!(meta[0].ttl.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_echo_encoding() {
+        let echo = IcmpEcho {
+            identifier: 0x1234,
+            sequence: 1,
+            payload: vec![1, 2, 3],
+        };
+        let request = echo.encode_request(false);
+        assert_eq!(&request[..2], &[8, 0]);
+        // A correct checksum sums to zero
+        let sum = request
+            .chunks(2)
+            .map(|c| u32::from(c[0]) << 8 | u32::from(*c.get(1).unwrap_or(&0)))
+            .sum::<u32>();
+        assert_eq!((sum & 0xffff) + (sum >> 16), 0xffff);
+
+        // Requests are not replies
+        assert_eq!(IcmpEcho::decode_reply(&request, false), None);
+        let mut reply = request;
+        reply[0] = 0;
+        assert_eq!(IcmpEcho::decode_reply(&reply, false), Some(echo.clone()));
+
+        let request = echo.encode_request(true);
+        assert_eq!(&request[..4], &[128, 0, 0, 0]);
+        let mut reply = request;
+        reply[0] = 129;
+        assert_eq!(IcmpEcho::decode_reply(&reply, true), Some(echo));
+    }
+
+    #[test]
+    async fn test_ping_v4() -> Result<()> {
+        ping(Ipv4Addr::LOCALHOST.into()).await
+    }
+
+    #[test]
+    async fn test_ping_v6() -> Result<()> {
+        ping(Ipv6Addr::LOCALHOST.into()).await
+    }
+
+    #[test]
+    async fn test_recv_without_buffers() -> Result<()> {
+        let socket = match bind(Ipv4Addr::LOCALHOST.into())? {
+            Some(socket) => socket,
+            None => return Ok(()),
+        };
+        assert_eq!(socket.recv(&mut [], &mut []).await?, 0);
+        Ok(())
+    }
+}
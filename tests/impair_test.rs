@@ -0,0 +1,265 @@
+#[macro_use]
+mod common;
+
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, EcnCodepoint, GilbertElliott, ImpairedSocket,
+        ImpairmentConfig, RecvMeta, Transmit,
+    };
+    use std::io::{self, IoSliceMut};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::time::{Duration, Instant};
+    use rt::UdpSocket;
+
+    async fn impaired(seed: u64) -> Result<ImpairedSocket<UdpSocket>> {
+        let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await?;
+        Ok(ImpairedSocket::new(
+            socket,
+            Arc::new(Capabilities::new()),
+            seed,
+        ))
+    }
+
+    fn transmit(destination: SocketAddr, contents: Vec<u8>) -> Transmit {
+        Transmit {
+            destination: Some(destination),
+            ecn: Some(EcnCodepoint::Ect0),
+            contents,
+            segment_size: None,
+            src_ip: None,
+        }
+    }
+
+    async fn send<S: AsyncUdpSocket>(
+        socket: &ImpairedSocket<S>,
+        transmits: &[Transmit],
+    ) -> Result<()> {
+        let capabilities = Capabilities::new();
+        let mut sent = 0;
+        while sent < transmits.len() {
+            sent += socket.send(&capabilities, &transmits[sent..]).await?;
+        }
+        Ok(())
+    }
+
+    /// Receives until nothing arrives for a while
+    async fn recv_all<S: AsyncUdpSocket>(
+        socket: &ImpairedSocket<S>,
+    ) -> Result<Vec<(RecvMeta, Vec<u8>)>> {
+        let mut received = Vec::new();
+        loop {
+            let mut storage = [[0u8; 1500]; 8];
+            let mut buffers = storage
+                .iter_mut()
+                .map(|b| IoSliceMut::new(b))
+                .collect::<Vec<_>>();
+            let mut meta = [RecvMeta::default(); 8];
+            let n = match exec::timeout(
+                Duration::from_millis(200),
+                socket.recv(&mut buffers, &mut meta),
+            )
+            .await
+            {
+                Ok(n) => n?,
+                Err(_) => return Ok(received),
+            };
+            for i in 0..n {
+                received.push((meta[i], buffers[i][..meta[i].len].to_vec()));
+            }
+        }
+    }
+
+    #[test]
+    async fn test_passthrough() -> Result<()> {
+        let socket1 = impaired(1).await?;
+        let socket2 = impaired(1).await?;
+        let addr2 = socket2.local_addr()?;
+
+        send(&socket1, &[transmit(addr2, b"hello".to_vec())]).await?;
+        let received = recv_all(&socket2).await?;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0.addr, socket1.local_addr()?);
+        assert_eq!(received[0].1, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    async fn test_send_impairments() -> Result<()> {
+        let socket1 = impaired(7).await?;
+        let socket2 = impaired(7).await?;
+        let addr2 = socket2.local_addr()?;
+        socket1.set_send_config(ImpairmentConfig {
+            delay: Duration::from_millis(50),
+            strip_ecn: true,
+            ..ImpairmentConfig::default()
+        });
+
+        // Receiving on the sender keeps handing delayed datagrams to the kernel
+        let start = Instant::now();
+        send(&socket1, &[transmit(addr2, vec![1; 100])]).await?;
+        let (_, received) = futures::join!(recv_all(&socket1), recv_all(&socket2));
+        let received = received?;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(received.len(), 1);
+        assert_ne!(received[0].0.ecn, Some(EcnCodepoint::Ect0));
+
+        // Reconfigured at runtime to drop everything
+        socket1.set_send_config(ImpairmentConfig {
+            loss: 1.0,
+            ..ImpairmentConfig::default()
+        });
+        send(&socket1, &[transmit(addr2, vec![2; 100])]).await?;
+        assert!(recv_all(&socket2).await?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    async fn test_recv_impairments() -> Result<()> {
+        let socket1 = impaired(3).await?;
+        let socket2 = impaired(3).await?;
+        let addr2 = socket2.local_addr()?;
+        socket2.set_recv_config(ImpairmentConfig {
+            burst_loss: Some(GilbertElliott {
+                p: 0.1,
+                r: 0.3,
+                loss_good: 0.0,
+                loss_bad: 1.0,
+            }),
+            duplicate: 0.2,
+            truncate: 0.2,
+            ce_mark: 1.0,
+            ..ImpairmentConfig::default()
+        });
+
+        let transmits = (0..50u8)
+            .map(|i| transmit(addr2, vec![i; 100]))
+            .collect::<Vec<_>>();
+        send(&socket1, &transmits).await?;
+        let received = recv_all(&socket2).await?;
+
+        let mut ids = received.iter().map(|(_, c)| c.first()).collect::<Vec<_>>();
+        assert!(received
+            .iter()
+            .all(|(m, _)| m.ecn == Some(EcnCodepoint::Ce)));
+        assert!(received.iter().any(|(m, _)| m.len < 100));
+        let total = ids.len();
+        ids.dedup();
+        assert!(ids.len() < total, "nothing was duplicated");
+        assert!(
+            received.iter().filter(|(m, _)| m.len == 100).count() < 50,
+            "nothing was lost"
+        );
+        Ok(())
+    }
+
+    /// A socket which never becomes writable or readable, and wakes the task which last polled
+    /// it to send when asked to
+    #[derive(Debug, Default)]
+    struct Unwritable {
+        send_waker: Mutex<Option<Waker>>,
+    }
+
+    impl AsyncUdpSocket for Unwritable {
+        fn poll_send(
+            &self,
+            cx: &mut Context<'_>,
+            _capabilities: &Capabilities,
+            _transmits: &[Transmit],
+        ) -> Poll<io::Result<usize>> {
+            *self.send_waker.lock().unwrap() = Some(cx.waker().clone());
+            Poll::Pending
+        }
+
+        fn poll_recv(
+            &self,
+            _cx: &mut Context<'_>,
+            _bufs: &mut [IoSliceMut<'_>],
+            _meta: &mut [RecvMeta],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+
+        fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            *self.send_waker.lock().unwrap() = Some(cx.waker().clone());
+            Poll::Pending
+        }
+
+        fn poll_readable(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Pending
+        }
+
+        fn try_send(
+            &self,
+            _capabilities: &Capabilities,
+            _transmits: &[Transmit],
+        ) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+
+        fn try_recv(
+            &self,
+            _bufs: &mut [IoSliceMut<'_>],
+            _meta: &mut [RecvMeta],
+        ) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 1))
+        }
+
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_recv_keeps_sender_woken() {
+        let socket = ImpairedSocket::new(Unwritable::default(), Arc::new(Capabilities::new()), 1);
+        socket.set_send_config(ImpairmentConfig {
+            delay: Duration::from_millis(10),
+            ..ImpairmentConfig::default()
+        });
+        let sender = Arc::new(CountingWaker::default());
+        let sender_waker = Waker::from(sender.clone());
+        let receiver_waker = Waker::from(Arc::new(CountingWaker::default()));
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 2);
+
+        // The datagram is held back, then the sender waits for the wrapped socket to take it
+        let mut cx = Context::from_waker(&sender_waker);
+        let capabilities = Capabilities::new();
+        assert!(matches!(
+            socket.poll_send(&mut cx, &capabilities, &[transmit(addr, vec![1])]),
+            Poll::Ready(Ok(1))
+        ));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(socket.poll_writable(&mut cx).is_pending());
+
+        // Receiving flushes too, which must not leave the sender without a wake-up
+        let mut cx = Context::from_waker(&receiver_waker);
+        let mut storage = [0u8; 16];
+        let mut meta = [RecvMeta::default()];
+        assert!(socket
+            .poll_recv(&mut cx, &mut [IoSliceMut::new(&mut storage)], &mut meta)
+            .is_pending());
+
+        let before = sender.0.load(Ordering::SeqCst);
+        let waker = socket.get_ref().send_waker.lock().unwrap().take().unwrap();
+        waker.wake();
+        assert!(sender.0.load(Ordering::SeqCst) > before);
+    }
+}
@@ -0,0 +1,38 @@
+#[cfg(unix)]
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use async_transport::{interface_addrs, is_local_addr};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_interface_addrs() -> Result<()> {
+        let addrs = interface_addrs()?;
+        let lo = addrs
+            .iter()
+            .find(|addr| addr.addr == IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .expect("loopback interface");
+        assert_eq!(lo.broadcast, None);
+        assert_ne!(lo.index, 0);
+        assert!(!lo.name.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_local_addr() -> Result<()> {
+        assert!(is_local_addr(Ipv4Addr::LOCALHOST.into())?);
+        assert!(is_local_addr(Ipv4Addr::LOCALHOST.to_ipv6_mapped().into())?);
+        assert!(is_local_addr(Ipv4Addr::UNSPECIFIED.into())?);
+        assert!(is_local_addr(Ipv6Addr::UNSPECIFIED.into())?);
+
+        // Documentation addresses are never assigned to a local interface
+        let remote = Ipv4Addr::new(203, 0, 113, 9);
+        assert!(interface_addrs()?
+            .iter()
+            .all(|addr| addr.addr != IpAddr::V4(remote)));
+        assert!(!is_local_addr(remote.into())?);
+        assert!(!is_local_addr(remote.to_ipv6_mapped().into())?);
+        assert!(!is_local_addr("2001:db8::9".parse::<Ipv6Addr>()?.into())?);
+        Ok(())
+    }
+}
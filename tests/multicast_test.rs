@@ -0,0 +1,113 @@
+#[macro_use]
+mod common;
+
+#[cfg(target_os = "linux")]
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{AsyncUdpSocket, Capabilities, RecvMeta, Transmit};
+    use std::io::{self, IoSliceMut};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::time::Duration;
+    use rt::UdpSocket;
+
+    async fn sender() -> Result<UdpSocket> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        socket.set_multicast_if_v4(Ipv4Addr::LOCALHOST)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(1)?;
+        Ok(socket)
+    }
+
+    async fn send(socket: &UdpSocket, destination: SocketAddr, contents: &[u8]) -> Result<()> {
+        let transmits = [Transmit {
+            destination: Some(destination),
+            ecn: None,
+            contents: contents.to_vec(),
+            segment_size: None,
+            src_ip: None,
+        }];
+        socket.send(&Capabilities::new(), &transmits).await?;
+        Ok(())
+    }
+
+    async fn recv(socket: &UdpSocket) -> io::Result<(Vec<u8>, RecvMeta)> {
+        let mut buf = [0u8; 1500];
+        let mut meta = [RecvMeta::default()];
+        socket
+            .recv_timeout(
+                &mut [IoSliceMut::new(&mut buf)],
+                &mut meta,
+                Duration::from_millis(500),
+            )
+            .await?;
+        Ok((buf[..meta[0].len].to_vec(), meta[0]))
+    }
+
+    #[test]
+    async fn test_multicast_v4() -> Result<()> {
+        let group = Ipv4Addr::new(239, 255, 0, 1);
+        let receiver = UdpSocket::bind("0.0.0.0:0").await?;
+        receiver.join_multicast_v4(group, Ipv4Addr::LOCALHOST)?;
+        let destination = SocketAddr::new(group.into(), receiver.local_addr()?.port());
+        let sender = sender().await?;
+
+        send(&sender, destination, b"hello").await?;
+        let (contents, meta) = recv(&receiver).await?;
+        assert_eq!(contents, b"hello");
+        assert_eq!(meta.addr, sender.local_addr()?);
+        assert_eq!(meta.dst_ip, Some(IpAddr::V4(group)));
+
+        receiver.leave_multicast_v4(group, Ipv4Addr::LOCALHOST)?;
+        send(&sender, destination, b"world").await?;
+        let err = recv(&receiver).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        Ok(())
+    }
+
+    #[test]
+    async fn test_source_specific_multicast_v4() -> Result<()> {
+        let group = Ipv4Addr::new(232, 1, 1, 1);
+        let receiver = UdpSocket::bind("0.0.0.0:0").await?;
+        receiver.join_ssm_v4(Ipv4Addr::LOCALHOST, group, Ipv4Addr::LOCALHOST)?;
+        let destination = SocketAddr::new(group.into(), receiver.local_addr()?.port());
+        let sender = sender().await?;
+
+        send(&sender, destination, b"hello").await?;
+        let (contents, meta) = recv(&receiver).await?;
+        assert_eq!(contents, b"hello");
+        assert_eq!(meta.dst_ip, Some(IpAddr::V4(group)));
+
+        receiver.leave_ssm_v4(Ipv4Addr::LOCALHOST, group, Ipv4Addr::LOCALHOST)?;
+        Ok(())
+    }
+
+    #[test]
+    async fn test_multicast_v6_membership() -> Result<()> {
+        let Ok(socket) = UdpSocket::bind("[::]:0").await else {
+            return Ok(());
+        };
+        socket.set_multicast_if_v6(1)?;
+        socket.set_multicast_loop_v6(true)?;
+        socket.set_multicast_hops_v6(1)?;
+
+        let group = "ff12::1234".parse::<Ipv6Addr>()?;
+        socket.join_multicast_v6(&group, 1)?;
+        socket.leave_multicast_v6(&group, 1)?;
+
+        let group = "ff32::8000:1".parse::<Ipv6Addr>()?;
+        socket.join_ssm_v6(&Ipv6Addr::LOCALHOST, &group, 1)?;
+        socket.leave_ssm_v6(&Ipv6Addr::LOCALHOST, &group, 1)?;
+        Ok(())
+    }
+
+    #[test]
+    async fn test_join_unicast_address() -> Result<()> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        let err = socket
+            .join_multicast_v4(Ipv4Addr::LOCALHOST, Ipv4Addr::UNSPECIFIED)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+#[macro_use]
+mod common;
+
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+#[cfg(test)]
+mod decode_tests {
+    use async_transport::{AddressChange, NetlinkEvent, RouteChange};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    /// Encodes a netlink message of type `ty` with a fixed `header` and route attributes
+    fn message(ty: u16, header: &[u8], attributes: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut payload = header.to_vec();
+        for (attribute, value) in attributes {
+            payload.extend_from_slice(&(4 + value.len() as u16).to_ne_bytes());
+            payload.extend_from_slice(&attribute.to_ne_bytes());
+            payload.extend_from_slice(value);
+            payload.resize((payload.len() + 3) & !3, 0);
+        }
+        let mut message = Vec::new();
+        message.extend_from_slice(&(16 + payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&ty.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(&payload);
+        message
+    }
+
+    fn ifaddrmsg(family: i32, prefix_len: u8, index: u32) -> Vec<u8> {
+        let mut header = vec![family as u8, prefix_len, 0, 0];
+        header.extend_from_slice(&index.to_ne_bytes());
+        header
+    }
+
+    #[test]
+    fn test_decode_recorded_messages() {
+        let mut datagram = message(
+            libc::RTM_NEWADDR,
+            &ifaddrmsg(libc::AF_INET, 24, 2),
+            &[
+                (1, &[192, 0, 2, 1]),
+                (2, &[192, 0, 2, 1]),
+                // IFA_LABEL, which is skipped
+                (3, b"eth0\0"),
+            ],
+        );
+        datagram.extend(message(
+            libc::RTM_DELADDR,
+            &ifaddrmsg(libc::AF_INET6, 64, 3),
+            &[(1, &"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets())],
+        ));
+        // RTM_NEWLINK, which is skipped
+        datagram.extend(message(16, &[0; 16], &[]));
+        datagram.extend(message(
+            libc::RTM_NEWROUTE,
+            &[libc::AF_INET as u8, 24, 0, 0, 254, 3, 0, 1, 0, 0, 0, 0],
+            &[
+                (1, &[203, 0, 113, 0]),
+                (5, &[192, 0, 2, 254]),
+                (4, &2u32.to_ne_bytes()),
+            ],
+        ));
+        datagram.extend(message(
+            libc::RTM_DELROUTE,
+            &[libc::AF_INET6 as u8, 0, 0, 0, 252, 3, 0, 1, 0, 0, 0, 0],
+            &[(15, &1000u32.to_ne_bytes())],
+        ));
+        // A truncated message ends decoding
+        datagram.extend(&message(libc::RTM_NEWADDR, &ifaddrmsg(libc::AF_INET, 8, 1), &[])[..12]);
+
+        assert_eq!(
+            NetlinkEvent::decode(&datagram),
+            vec![
+                NetlinkEvent::AddressAdded(AddressChange {
+                    index: 2,
+                    addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                    prefix_len: 24,
+                }),
+                NetlinkEvent::AddressRemoved(AddressChange {
+                    index: 3,
+                    addr: "2001:db8::1".parse().unwrap(),
+                    prefix_len: 64,
+                }),
+                NetlinkEvent::RouteAdded(RouteChange {
+                    destination: IpAddr::V4(Ipv4Addr::new(203, 0, 113, 0)),
+                    prefix_len: 24,
+                    gateway: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 254))),
+                    index: Some(2),
+                    table: 254,
+                }),
+                NetlinkEvent::RouteRemoved(RouteChange {
+                    destination: IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                    prefix_len: 0,
+                    gateway: None,
+                    index: None,
+                    table: 1000,
+                }),
+            ]
+        );
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{is_local_addr, AddressChange, NetlinkEvent};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::process::Command;
+    use std::time::Duration;
+    use rt::NetlinkMonitor;
+
+    /// Runs `ip` with `args`, returning whether it succeeded
+    fn ip(args: &[&str]) -> bool {
+        Command::new("ip")
+            .args(args)
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    async fn next_address_event(monitor: &mut NetlinkMonitor) -> Result<NetlinkEvent> {
+        loop {
+            let event =
+                exec::timeout(Duration::from_secs(5), monitor.next_event()).await??;
+            if let NetlinkEvent::AddressAdded(_) | NetlinkEvent::AddressRemoved(_) = event {
+                return Ok(event);
+            }
+        }
+    }
+
+    #[test]
+    async fn test_monitor_address_changes() -> Result<()> {
+        let addr = Ipv4Addr::new(198, 51, 100, 7);
+        let mut monitor = NetlinkMonitor::new()?;
+        assert!(!is_local_addr(addr.into())?);
+
+        // Changing addresses requires CAP_NET_ADMIN, e.g. within a network namespace
+        if !ip(&["addr", "add", "198.51.100.7/32", "dev", "lo"]) {
+            return Ok(());
+        }
+        let added = next_address_event(&mut monitor).await;
+        let valid = is_local_addr(addr.into());
+        ip(&["addr", "del", "198.51.100.7/32", "dev", "lo"]);
+        let NetlinkEvent::AddressAdded(change) = added? else {
+            panic!("expected an added address");
+        };
+        assert_eq!(
+            change,
+            AddressChange {
+                index: 1,
+                addr: IpAddr::V4(addr),
+                prefix_len: 32,
+            }
+        );
+        assert!(valid?);
+
+        let removed = next_address_event(&mut monitor).await?;
+        assert_eq!(removed, NetlinkEvent::AddressRemoved(change));
+        assert!(!is_local_addr(addr.into())?);
+        Ok(())
+    }
+}
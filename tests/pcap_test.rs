@@ -0,0 +1,225 @@
+#[macro_use]
+mod common;
+
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, CaptureSocket, EcnCodepoint, RecvMeta, Transmit,
+    };
+    use std::io::{self, IoSliceMut, Write};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use rt::UdpSocket;
+
+    /// A capture destination which can be inspected while the socket still owns it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A socket whose addresses cannot be looked up
+    #[derive(Debug)]
+    struct Unaddressed(UdpSocket);
+
+    impl AsyncUdpSocket for Unaddressed {
+        fn poll_send(
+            &self,
+            cx: &mut Context<'_>,
+            capabilities: &Capabilities,
+            transmits: &[Transmit],
+        ) -> Poll<io::Result<usize>> {
+            self.0.poll_send(cx, capabilities, transmits)
+        }
+
+        fn poll_recv(
+            &self,
+            cx: &mut Context<'_>,
+            bufs: &mut [IoSliceMut<'_>],
+            meta: &mut [RecvMeta],
+        ) -> Poll<io::Result<usize>> {
+            self.0.poll_recv(cx, bufs, meta)
+        }
+
+        fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.0.poll_writable(cx)
+        }
+
+        fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.0.poll_readable(cx)
+        }
+
+        fn try_send(
+            &self,
+            capabilities: &Capabilities,
+            transmits: &[Transmit],
+        ) -> io::Result<usize> {
+            self.0.try_send(capabilities, transmits)
+        }
+
+        fn try_recv(
+            &self,
+            bufs: &mut [IoSliceMut<'_>],
+            meta: &mut [RecvMeta],
+        ) -> io::Result<usize> {
+            self.0.try_recv(bufs, meta)
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    /// Returns the direction flags and packet of every enhanced packet block
+    fn packets(capture: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let u32_at =
+            |offset: usize| u32::from_ne_bytes(capture[offset..offset + 4].try_into().unwrap());
+        assert_eq!(u32_at(0), 0x0a0d_0d0a);
+        assert_eq!(u32_at(8), 0x1a2b_3c4d);
+
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while offset < capture.len() {
+            let (ty, len) = (u32_at(offset), u32_at(offset + 4) as usize);
+            assert_eq!(u32_at(offset + len - 4) as usize, len);
+            match ty {
+                // Interface description block with LINKTYPE_RAW
+                1 => assert_eq!(capture[offset + 8], 101),
+                6 => {
+                    let captured = u32_at(offset + 20) as usize;
+                    let data = offset + 28;
+                    let packet = capture[data..data + captured].to_vec();
+                    // The flags option directly follows the padded packet
+                    let flags = u32_at(data + captured.next_multiple_of(4) + 4);
+                    packets.push((flags, packet));
+                }
+                _ => {}
+            }
+            offset += len;
+        }
+        packets
+    }
+
+    fn internet_checksum(data: &[u8]) -> u16 {
+        let mut sum = data
+            .chunks(2)
+            .map(|c| u32::from(u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])))
+            .sum::<u32>();
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        sum as u16
+    }
+
+    #[test]
+    async fn test_capture() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        let sent_capture = SharedBuffer::default();
+        let recv_capture = SharedBuffer::default();
+        let socket1 = CaptureSocket::new(UdpSocket::bind(addr).await?, sent_capture.clone())?;
+        let socket2 = CaptureSocket::new(UdpSocket::bind(addr).await?, recv_capture.clone())?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+
+        // GSO buffers are expanded into their segments, where the platform supports them
+        let segment_size = match capabilities.max_gso_segments() > 1 {
+            true => Some(100),
+            false => None,
+        };
+        let transmit = Transmit {
+            destination: Some(addr2),
+            ecn: Some(EcnCodepoint::Ect0),
+            contents: vec![7; 250],
+            segment_size,
+            src_ip: None,
+        };
+        socket1.send(&capabilities, &[transmit]).await?;
+
+        let sent = packets(&sent_capture.0.lock().unwrap());
+        let expected_lens = match segment_size {
+            Some(_) => vec![100, 100, 50],
+            None => vec![250],
+        };
+        assert_eq!(sent.len(), expected_lens.len());
+        for ((flags, packet), len) in sent.iter().zip(expected_lens) {
+            // Outbound IPv4 packet carrying ECT(0)
+            assert_eq!(*flags & 0b11, 0b10);
+            assert_eq!(packet[0], 0x45);
+            assert_eq!(packet[1] & 0b11, EcnCodepoint::Ect0 as u8);
+            assert_eq!(internet_checksum(&packet[..20]), 0xffff);
+            assert_eq!(&packet[12..16], &[127, 0, 0, 1]);
+            assert_eq!(&packet[20..22], &addr1.port().to_be_bytes());
+            assert_eq!(&packet[22..24], &addr2.port().to_be_bytes());
+            assert_eq!(packet.len(), 28 + len);
+            assert_eq!(&packet[28..], &vec![7; len][..]);
+        }
+
+        let mut received = 0;
+        while received < 250 {
+            let mut storage = [[0u8; 1500]; 8];
+            let mut buffers = storage
+                .iter_mut()
+                .map(|b| IoSliceMut::new(b))
+                .collect::<Vec<_>>();
+            let mut meta = [RecvMeta::default(); 8];
+            let n = socket2.recv(&mut buffers, &mut meta).await?;
+            received += meta[..n].iter().map(|m| m.len).sum::<usize>();
+        }
+        let recv = packets(&recv_capture.0.lock().unwrap());
+        assert_eq!(recv.len(), sent.len());
+        for ((flags, packet), (_, sent_packet)) in recv.iter().zip(&sent) {
+            assert_eq!(*flags & 0b11, 0b01);
+            assert_eq!(packet, sent_packet);
+        }
+        Ok(())
+    }
+    #[test]
+    async fn test_capture_without_addresses() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        let sent_capture = SharedBuffer::default();
+        let recv_capture = SharedBuffer::default();
+        let socket1 = CaptureSocket::new(
+            Unaddressed(UdpSocket::bind(addr).await?),
+            sent_capture.clone(),
+        )?;
+        let socket2 = CaptureSocket::new(
+            Unaddressed(UdpSocket::bind(addr).await?),
+            recv_capture.clone(),
+        )?;
+        let addr2 = socket2.get_ref().0.local_addr()?;
+
+        // The datagrams are sent and received, but left out of the captures
+        let transmit = Transmit {
+            destination: Some(addr2),
+            ecn: None,
+            contents: vec![7; 100],
+            segment_size: None,
+            src_ip: None,
+        };
+        assert_eq!(socket1.send(&capabilities, &[transmit]).await?, 1);
+        let mut storage = [0u8; 1500];
+        let mut buffers = [IoSliceMut::new(&mut storage)];
+        let mut meta = [RecvMeta::default()];
+        assert_eq!(socket2.recv(&mut buffers, &mut meta).await?, 1);
+        assert_eq!(meta[0].len, 100);
+
+        assert!(packets(&sent_capture.0.lock().unwrap()).is_empty());
+        assert!(packets(&recv_capture.0.lock().unwrap()).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+#[macro_use]
+mod common;
+
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, RecvMeta, Transmit, VirtualLinkConfig, VirtualNetwork,
+        BATCH_SIZE,
+    };
+    use std::future::poll_fn;
+    use std::io::{self, IoSliceMut};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+    use rt::UdpSocket;
+
+    /// Sends `count` datagrams and receives them again, driven only by readiness and `try_*`
+    async fn exchange(
+        sender: &dyn AsyncUdpSocket,
+        receiver: &dyn AsyncUdpSocket,
+        count: usize,
+    ) -> Result<Vec<Vec<u8>>> {
+        let capabilities = Capabilities::new();
+        let destination = receiver.local_addr()?;
+        let mut sent = 0;
+        while sent < count {
+            poll_fn(|cx| sender.poll_writable(cx)).await?;
+            // Transmits are only built once the socket can take them
+            let transmits = (sent..count.min(sent + BATCH_SIZE))
+                .map(|i| Transmit {
+                    destination: Some(destination),
+                    ecn: None,
+                    contents: (i as u32).to_be_bytes().to_vec(),
+                    segment_size: None,
+                    src_ip: None,
+                })
+                .collect::<Vec<_>>();
+            match sender.try_send(&capabilities, &transmits) {
+                Ok(n) => sent += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut storage = [[0u8; 1500]; BATCH_SIZE];
+        let mut received = Vec::new();
+        while received.len() < count {
+            poll_fn(|cx| receiver.poll_readable(cx)).await?;
+            loop {
+                let mut buffers = storage
+                    .iter_mut()
+                    .map(|b| IoSliceMut::new(b))
+                    .collect::<Vec<_>>();
+                let mut meta = [RecvMeta::default(); BATCH_SIZE];
+                match receiver.try_recv(&mut buffers, &mut meta) {
+                    Ok(n) => received.extend((0..n).map(|i| buffers[i][..meta[i].len].to_vec())),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        Ok(received)
+    }
+
+    #[test]
+    async fn test_readiness() -> Result<()> {
+        let socket1 = UdpSocket::bind("127.0.0.1:0").await?;
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+
+        let mut buf = [0u8; 1500];
+        let mut meta = [RecvMeta::default()];
+        let err = socket2
+            .try_recv(&mut [IoSliceMut::new(&mut buf)], &mut meta)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        let mut received = exchange(&socket1, &socket2, 100).await?;
+        received.sort();
+        let expected = (0..100u32)
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(received, expected);
+        Ok(())
+    }
+
+    #[test]
+    async fn test_virtual_readiness() -> Result<()> {
+        let network = VirtualNetwork::with_runtime(1, async_transport::default_runtime().unwrap());
+        network.set_config(VirtualLinkConfig {
+            latency: Duration::from_millis(20),
+            ..Default::default()
+        });
+        let addr = |host| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, host)), 0);
+        let socket1 = network.bind(addr(1))?;
+        let socket2 = network.bind(addr(2))?;
+
+        let received = exchange(&socket1, &socket2, 10).await?;
+        assert_eq!(received.len(), 10);
+        Ok(())
+    }
+}
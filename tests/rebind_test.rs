@@ -0,0 +1,148 @@
+#[macro_use]
+mod common;
+
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{AsyncUdpSocket, Capabilities, RecvMeta, Transmit};
+    use std::io::{self, IoSliceMut};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use rt::UdpSocket;
+
+    /// Whether sockets receive through io_uring, which cannot be rebound
+    fn uses_io_uring() -> bool {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Ok(socket) = std::net::UdpSocket::bind("127.0.0.1:0") {
+            let mut state = async_transport::UdpSocketState::new();
+            return state.enable_io_uring((&socket).into()).is_ok();
+        }
+        false
+    }
+
+    fn skip_rebind() -> bool {
+        let skip = uses_io_uring();
+        if skip {
+            eprintln!("skipping, sockets using io_uring cannot be rebound");
+        }
+        skip
+    }
+
+    fn transmit(destination: Option<SocketAddr>, contents: &[u8]) -> Transmit {
+        Transmit {
+            destination,
+            ecn: None,
+            contents: contents.to_vec(),
+            segment_size: None,
+            src_ip: None,
+        }
+    }
+
+    async fn recv(socket: &UdpSocket) -> Result<(Vec<u8>, SocketAddr)> {
+        let mut buf = [0u8; 1500];
+        let mut meta = [RecvMeta::default()];
+        socket
+            .recv_timeout(
+                &mut [IoSliceMut::new(&mut buf)],
+                &mut meta,
+                Duration::from_secs(1),
+            )
+            .await?;
+        Ok((buf[..meta[0].len].to_vec(), meta[0].addr))
+    }
+
+    #[test]
+    async fn test_rebind_drains_previous_socket() -> Result<()> {
+        if skip_rebind() {
+            return Ok(());
+        }
+        let capabilities = Capabilities::new();
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let peer = UdpSocket::bind("127.0.0.1:0").await?;
+        let old_addr = socket.local_addr()?;
+
+        peer.send(&capabilities, &[transmit(Some(old_addr), b"old")])
+            .await?;
+        exec::sleep(Duration::from_millis(20)).await;
+
+        socket.rebind(std::net::UdpSocket::bind("127.0.0.1:0")?)?;
+        let new_addr = socket.local_addr()?;
+        assert_ne!(new_addr, old_addr);
+        peer.send(&capabilities, &[transmit(Some(new_addr), b"new")])
+            .await?;
+
+        assert_eq!(recv(&socket).await?.0, b"old");
+        assert_eq!(recv(&socket).await?.0, b"new");
+
+        // Datagrams are sent from the new socket
+        socket
+            .send(&capabilities, &[transmit(Some(peer.local_addr()?), b"hi")])
+            .await?;
+        assert_eq!(recv(&peer).await?, (b"hi".to_vec(), new_addr));
+        Ok(())
+    }
+
+    #[test]
+    async fn test_rebind_wakes_pending_receiver() -> Result<()> {
+        if skip_rebind() {
+            return Ok(());
+        }
+        let capabilities = Capabilities::new();
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        let peer = UdpSocket::bind("127.0.0.1:0").await?;
+
+        let receiver = exec::spawn({
+            let socket = socket.clone();
+            async move { recv(&socket).await.map(|(contents, _)| contents) }
+        });
+        exec::sleep(Duration::from_millis(20)).await;
+
+        socket.rebind(std::net::UdpSocket::bind("127.0.0.1:0")?)?;
+        peer.send(
+            &capabilities,
+            &[transmit(Some(socket.local_addr()?), b"hello")],
+        )
+        .await?;
+        assert_eq!(receiver.await?, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    async fn test_rebind_keeps_peer() -> Result<()> {
+        if skip_rebind() {
+            return Ok(());
+        }
+        let capabilities = Capabilities::new();
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let peer = UdpSocket::bind("127.0.0.1:0").await?;
+        socket.connect(peer.local_addr()?).await?;
+
+        socket.rebind(std::net::UdpSocket::bind("127.0.0.1:0")?)?;
+        assert_eq!(socket.peer_addr()?, peer.local_addr()?);
+        socket
+            .send(&capabilities, &[transmit(None, b"hello")])
+            .await?;
+        assert_eq!(
+            recv(&peer).await?,
+            (b"hello".to_vec(), socket.local_addr()?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    async fn test_rebind_unsupported_with_io_uring() -> Result<()> {
+        if !uses_io_uring() {
+            return Ok(());
+        }
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = socket.local_addr()?;
+        let err = socket
+            .rebind(std::net::UdpSocket::bind("127.0.0.1:0")?)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(socket.local_addr()?, addr);
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, EcnCodepoint, PacketDirection, PcapngWriter, RecvMeta,
+        ReplayFilter, ReplaySocket, ReplayTiming, Transmit,
+    };
+    use std::io::IoSliceMut;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::time::{Duration, Instant, SystemTime};
+
+    fn addr(host: u8, port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, host)), port)
+    }
+
+    async fn recv(socket: &ReplaySocket) -> Result<Vec<(RecvMeta, Vec<u8>)>> {
+        let mut storage = [[0u8; 1500]; 8];
+        let mut buffers = storage
+            .iter_mut()
+            .map(|b| IoSliceMut::new(b))
+            .collect::<Vec<_>>();
+        let mut meta = [RecvMeta::default(); 8];
+        let n = socket.recv(&mut buffers, &mut meta).await?;
+        Ok((0..n)
+            .map(|i| (meta[i], buffers[i][..meta[i].len].to_vec()))
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn test_replay_pcapng() -> Result<()> {
+        let start = SystemTime::now();
+        let mut writer = PcapngWriter::new(Vec::new())?;
+        for (offset, src, ecn, payload) in [
+            (0, addr(1, 1000), Some(EcnCodepoint::Ect0), &b"first"[..]),
+            (50, addr(3, 3000), None, &b"other"[..]),
+            (100, addr(1, 1000), Some(EcnCodepoint::Ce), &b"second"[..]),
+        ] {
+            writer.write_datagram(
+                start + Duration::from_millis(offset),
+                PacketDirection::Inbound,
+                src,
+                addr(2, 2000),
+                ecn,
+                payload,
+            )?;
+        }
+        let capture = writer.into_inner();
+
+        let filter = ReplayFilter {
+            src_port: Some(1000),
+            ..ReplayFilter::default()
+        };
+        let socket = ReplaySocket::new(&capture[..], filter, ReplayTiming::Original)?;
+        assert_eq!(socket.local_addr()?, addr(2, 2000));
+        assert_eq!(socket.remaining(), 2);
+
+        let begin = Instant::now();
+        let first = recv(&socket).await?;
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].1, b"first");
+        assert_eq!(first[0].0.addr, addr(1, 1000));
+        assert_eq!(first[0].0.dst_ip, Some(addr(2, 0).ip()));
+        assert_eq!(first[0].0.ecn, Some(EcnCodepoint::Ect0));
+
+        let second = recv(&socket).await?;
+        assert!(begin.elapsed() >= Duration::from_millis(100));
+        assert_eq!(second[0].1, b"second");
+        assert_eq!(second[0].0.ecn, Some(EcnCodepoint::Ce));
+        assert_eq!(socket.remaining(), 0);
+
+        let transmit = Transmit {
+            destination: Some(addr(1, 1000)),
+            ecn: None,
+            contents: b"reply".to_vec(),
+            segment_size: None,
+            src_ip: None,
+        };
+        socket.send(&Capabilities::new(), &[transmit]).await?;
+        let sent = socket.take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].contents, b"reply");
+        assert!(socket.take_sent().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_pcap_ethernet() -> Result<()> {
+        let src = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+        let payload = b"hello";
+
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&0x86ddu16.to_be_bytes());
+        // IPv6 header with ECT(1) in the traffic class
+        frame.extend_from_slice(&(6u32 << 28 | 1 << 20).to_be_bytes());
+        frame.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[17, 64]);
+        frame.extend_from_slice(&src.octets());
+        frame.extend_from_slice(&dst.octets());
+        frame.extend_from_slice(&4433u16.to_be_bytes());
+        frame.extend_from_slice(&443u16.to_be_bytes());
+        frame.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+
+        // Big-endian pcap with microsecond timestamps and the Ethernet link type
+        let mut capture = Vec::new();
+        capture.extend_from_slice(&0xa1b2_c3d4u32.to_be_bytes());
+        capture.extend_from_slice(&[0, 2, 0, 4]);
+        capture.extend_from_slice(&[0; 8]);
+        capture.extend_from_slice(&65535u32.to_be_bytes());
+        capture.extend_from_slice(&1u32.to_be_bytes());
+        for seconds in [1u32, 100] {
+            capture.extend_from_slice(&seconds.to_be_bytes());
+            capture.extend_from_slice(&0u32.to_be_bytes());
+            capture.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            capture.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            capture.extend_from_slice(&frame);
+        }
+
+        // Replayed as fast as possible despite the 99 seconds in between
+        let socket = ReplaySocket::new(&capture[..], ReplayFilter::default(), ReplayTiming::Asap)?;
+        assert_eq!(socket.local_addr()?, SocketAddr::new(dst.into(), 443));
+        let received = tokio::time::timeout(Duration::from_secs(1), recv(&socket)).await??;
+        assert_eq!(received.len(), 2);
+        for (meta, contents) in received {
+            assert_eq!(contents, payload);
+            assert_eq!(meta.addr, SocketAddr::new(src.into(), 4433));
+            assert_eq!(meta.ecn, Some(EcnCodepoint::Ect1));
+        }
+        Ok(())
+    }
+
+    /// Encodes a pcapng block in native byte order
+    fn block(ty: u32, body: &[u8]) -> Vec<u8> {
+        let len = (12 + body.len()) as u32;
+        let mut block = ty.to_ne_bytes().to_vec();
+        block.extend_from_slice(&len.to_ne_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&len.to_ne_bytes());
+        block
+    }
+
+    #[test]
+    fn test_replay_timestamp_overflow() {
+        let mut section = 0x1a2b_3c4du32.to_ne_bytes().to_vec();
+        section.extend_from_slice(&[1, 0, 0, 0]);
+        section.extend_from_slice(&(-1i64).to_ne_bytes());
+        // Raw IP interface whose timestamp offset leaves no room for any packet
+        let mut interface = 101u16.to_ne_bytes().to_vec();
+        interface.extend_from_slice(&[0; 6]);
+        interface.extend_from_slice(&14u16.to_ne_bytes());
+        interface.extend_from_slice(&8u16.to_ne_bytes());
+        interface.extend_from_slice(&u64::MAX.to_ne_bytes());
+        interface.extend_from_slice(&[0; 4]);
+        let mut packet = 0u32.to_ne_bytes().to_vec();
+        packet.extend_from_slice(&u32::MAX.to_ne_bytes());
+        packet.extend_from_slice(&u32::MAX.to_ne_bytes());
+        packet.extend_from_slice(&[0; 8]);
+
+        let mut capture = block(0x0a0d_0d0a, &section);
+        capture.extend(block(1, &interface));
+        capture.extend(block(6, &packet));
+        let err = ReplaySocket::new(&capture[..], ReplayFilter::default(), ReplayTiming::Asap)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_write_oversized_datagram() -> Result<()> {
+        let mut writer = PcapngWriter::new(Vec::new())?;
+        let header_len = writer.get_ref().len();
+        // Too large for the IPv4 total length, but not for the IPv6 payload length
+        let payload = vec![0; u16::MAX as usize - 8];
+        let v6 = |host| SocketAddr::new(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, host).into(), 1);
+        for (src, dst) in [(addr(1, 1), addr(2, 2)), (v6(1), v6(2))] {
+            writer.write_datagram(
+                SystemTime::now(),
+                PacketDirection::Outbound,
+                src,
+                dst,
+                None,
+                &payload,
+            )?;
+        }
+        let capture = writer.into_inner();
+        assert!(capture.len() > header_len + payload.len());
+        assert!(capture.len() < header_len + 2 * payload.len());
+        Ok(())
+    }
+}
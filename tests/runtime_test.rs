@@ -0,0 +1,86 @@
+#[cfg(any(
+    feature = "runtime-smol",
+    feature = "runtime-tokio",
+    feature = "runtime-async-std"
+))]
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use async_transport::{Capabilities, RecvMeta, Runtime, Transmit};
+    use futures::channel::oneshot;
+    use std::future::poll_fn;
+    use std::io::IoSliceMut;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    /// Exercises `runtime` the way a protocol written against the trait would
+    async fn exercise(runtime: Arc<dyn Runtime>) -> Result<()> {
+        let socket1 =
+            runtime.wrap_udp_socket(std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?)?;
+        let mut socket2 =
+            runtime.wrap_udp_socket(std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?)?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+
+        // The receiver runs as a task of its own
+        let (tx, rx) = oneshot::channel();
+        runtime.spawn(Box::pin(async move {
+            let mut buf = [0u8; 1500];
+            let mut meta = [RecvMeta::default()];
+            // Borrowed mutably, as the socket may be sent but not shared between threads
+            let (socket, recv_buf, recv_meta) = (&mut socket2, &mut buf, &mut meta);
+            let res = poll_fn(move |cx| {
+                socket.poll_recv(cx, &mut [IoSliceMut::new(recv_buf)], recv_meta)
+            })
+            .await
+            .map(|_| (meta[0], buf[..meta[0].len].to_vec()));
+            let _ = tx.send(res);
+        }));
+
+        let transmits = [Transmit {
+            destination: Some(addr2),
+            ecn: None,
+            contents: b"hello".to_vec(),
+            segment_size: None,
+            src_ip: None,
+        }];
+        let capabilities = Capabilities::new();
+        poll_fn(|cx| socket1.poll_send(cx, &capabilities, &transmits)).await?;
+        let (meta, contents) = rx.await??;
+        assert_eq!(contents, b"hello");
+        assert_eq!(meta.addr, addr1);
+
+        // Timers fire at their deadline, also after being moved
+        let start = runtime.now();
+        let mut timer = runtime.new_timer(start + Duration::from_secs(60));
+        timer.as_mut().reset(start + Duration::from_millis(50));
+        poll_fn(|cx| timer.as_mut().poll(cx)).await;
+        assert!(Instant::now() >= start + Duration::from_millis(50));
+        Ok(())
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn test_tokio() -> Result<()> {
+        exercise(Arc::new(async_transport::TokioRuntime)).await
+    }
+
+    #[cfg(feature = "runtime-smol")]
+    #[test]
+    fn test_smol() -> Result<()> {
+        futures::executor::block_on(exercise(Arc::new(async_transport::SmolRuntime)))
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    #[test]
+    fn test_async_std() -> Result<()> {
+        futures::executor::block_on(exercise(Arc::new(async_transport::AsyncStdRuntime)))
+    }
+
+    #[tokio::test]
+    async fn test_default_runtime() -> Result<()> {
+        let runtime = async_transport::default_runtime().unwrap();
+        exercise(runtime).await
+    }
+}
@@ -0,0 +1,42 @@
+#[cfg(all(feature = "runtime-smol", feature = "runtime-tokio"))]
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use async_transport::smol::UdpSocket as SmolUdpSocket;
+    use async_transport::tokio::UdpSocket as TokioUdpSocket;
+    use async_transport::{AsyncUdpSocket, Capabilities, RecvMeta, Transmit};
+    use std::io::IoSliceMut;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    async fn exchange(sender: &dyn AsyncUdpSocket, receiver: &dyn AsyncUdpSocket) -> Result<()> {
+        let transmits = [Transmit {
+            destination: Some(receiver.local_addr()?),
+            ecn: None,
+            contents: b"hello".to_vec(),
+            segment_size: None,
+            src_ip: None,
+        }];
+        let capabilities = Capabilities::new();
+        std::future::poll_fn(|cx| sender.poll_send(cx, &capabilities, &transmits)).await?;
+
+        let mut buf = [0u8; 1500];
+        let mut meta = [RecvMeta::default()];
+        std::future::poll_fn(|cx| {
+            receiver.poll_recv(cx, &mut [IoSliceMut::new(&mut buf)], &mut meta)
+        })
+        .await?;
+        assert_eq!(&buf[..meta[0].len], b"hello");
+        assert_eq!(meta[0].addr, sender.local_addr()?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_smol_and_tokio() -> Result<()> {
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        let smol_socket = SmolUdpSocket::bind(addr).await?;
+        let tokio_socket = TokioUdpSocket::bind(addr).await?;
+
+        exchange(&smol_socket, &tokio_socket).await?;
+        exchange(&tokio_socket, &smol_socket).await
+    }
+}
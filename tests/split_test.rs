@@ -0,0 +1,69 @@
+#[macro_use]
+mod common;
+
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, EcnCodepoint, RecvMeta, Transmit, BATCH_SIZE,
+    };
+    use std::io::IoSliceMut;
+    use std::sync::Arc;
+    use rt::UdpSocket;
+
+    #[test]
+    async fn test_split() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let socket1 = UdpSocket::bind("127.0.0.1:0").await?;
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+
+        let (send1, recv1) = socket1.into_split();
+        let (send2, recv2) = socket2.into_split();
+
+        // Both receivers are parked before anything is sent
+        let receiver = exec::spawn(async move {
+            let mut storage = [[0u8; 1200]; BATCH_SIZE];
+            let mut buffers = storage
+                .iter_mut()
+                .map(|b| IoSliceMut::new(b))
+                .collect::<Vec<_>>();
+            let mut meta = [RecvMeta::default(); BATCH_SIZE];
+            let n = recv2.recv(&mut buffers, &mut meta).await.unwrap();
+            assert_eq!(n, 1);
+            (recv2, meta[0])
+        });
+
+        let transmits = [Transmit {
+            destination: Some(addr2),
+            ecn: Some(EcnCodepoint::Ect1),
+            contents: b"ping".to_vec(),
+            segment_size: None,
+            src_ip: None,
+        }];
+        send1.send(&capabilities, &transmits).await?;
+
+        let (recv2, meta) = receiver.await;
+        assert_eq!(meta.addr, addr1);
+        assert_eq!(meta.len, 4);
+        #[cfg(not(windows))]
+        assert_eq!(meta.ecn, Some(EcnCodepoint::Ect1));
+
+        // Halves of different sockets must not be reunited
+        let err = send1.reunite(recv2).unwrap_err();
+        let (send1, recv2) = (err.0, err.1);
+
+        let socket1 = send1.reunite(recv1)?;
+        let socket2 = recv2.reunite(send2)?;
+        assert_eq!(socket1.local_addr()?, addr1);
+        assert_eq!(socket2.local_addr()?, addr2);
+
+        // Splitting a shared socket keeps the caller's reference alive
+        let shared = Arc::new(socket1);
+        let (send, recv) = shared.split();
+        assert!(send.reunite(recv).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+#[macro_use]
+mod common;
+
+#[cfg(feature = "futures")]
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, EcnCodepoint, Transmit, UdpSink, UdpStream, BATCH_SIZE,
+    };
+    use futures::{SinkExt, StreamExt};
+    use std::{
+        io::{self, IoSliceMut},
+        net::SocketAddr,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+    use rt::UdpSocket;
+
+    #[test]
+    async fn test_stream_sink() -> Result<()> {
+        let capabilities = Arc::new(Capabilities::new());
+        let socket1 = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+
+        let mut sink = UdpSink::new(socket1.clone(), capabilities);
+        let mut stream = UdpStream::new(socket2);
+
+        let count = BATCH_SIZE * 2 + 1;
+        for i in 0..count {
+            sink.feed(Transmit {
+                destination: Some(addr2),
+                ecn: Some(EcnCodepoint::Ect0),
+                contents: (i as u64).to_be_bytes().to_vec(),
+                segment_size: None,
+                src_ip: None,
+            })
+            .await?;
+        }
+        sink.flush().await?;
+
+        for i in 0..count {
+            let (meta, datagram) = stream.next().await.unwrap()?;
+            assert_eq!(meta.addr, addr1);
+            assert_eq!(meta.len, datagram.len());
+            assert_eq!(&datagram[..], &(i as u64).to_be_bytes()[..]);
+            #[cfg(not(windows))]
+            assert_eq!(meta.ecn, Some(EcnCodepoint::Ect0));
+        }
+
+        Ok(())
+    }
+    #[test]
+    async fn test_stream_empty_datagram() -> Result<()> {
+        let capabilities = Arc::new(Capabilities::new());
+        let socket1 = UdpSocket::bind("127.0.0.1:0").await?;
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr2 = socket2.local_addr()?;
+
+        let mut sink = UdpSink::new(socket1, capabilities);
+        let mut stream = UdpStream::new(socket2);
+
+        for contents in [vec![], vec![1, 2, 3]] {
+            sink.feed(Transmit {
+                destination: Some(addr2),
+                ecn: None,
+                contents,
+                segment_size: None,
+                src_ip: None,
+            })
+            .await?;
+        }
+        sink.flush().await?;
+
+        let (meta, datagram) = stream.next().await.unwrap()?;
+        assert_eq!(meta.len, 0);
+        assert!(datagram.is_empty());
+        let (meta, datagram) = stream.next().await.unwrap()?;
+        assert_eq!(meta.len, 3);
+        assert_eq!(&datagram[..], &[1, 2, 3]);
+
+        Ok(())
+    }
+
+    /// A socket which is always ready but never sends anything
+    #[derive(Debug)]
+    struct Stalled;
+
+    impl AsyncUdpSocket for Stalled {
+        fn poll_send(
+            &self,
+            _cx: &mut Context<'_>,
+            _capabilities: &Capabilities,
+            _transmits: &[Transmit],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Ok(0))
+        }
+
+        fn poll_recv(
+            &self,
+            _cx: &mut Context<'_>,
+            _bufs: &mut [IoSliceMut<'_>],
+            _meta: &mut [async_transport::RecvMeta],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+
+        fn poll_writable(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_readable(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Pending
+        }
+
+        fn try_send(
+            &self,
+            _capabilities: &Capabilities,
+            _transmits: &[Transmit],
+        ) -> io::Result<usize> {
+            Ok(0)
+        }
+
+        fn try_recv(
+            &self,
+            _bufs: &mut [IoSliceMut<'_>],
+            _meta: &mut [async_transport::RecvMeta],
+        ) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    #[test]
+    async fn test_sink_write_zero() -> Result<()> {
+        let mut sink = UdpSink::new(Stalled, Arc::new(Capabilities::new()));
+        let err = sink
+            .send(Transmit {
+                destination: Some("127.0.0.1:1".parse()?),
+                ecn: None,
+                contents: vec![1],
+                segment_size: None,
+                src_ip: None,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn test_stream_buffer_count() -> Result<()> {
+        let capabilities = Arc::new(Capabilities::new());
+        let socket1 = UdpSocket::bind("127.0.0.1:0").await?;
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr2 = socket2.local_addr()?;
+
+        let mut sink = UdpSink::new(socket1, capabilities);
+        let mut stream = UdpStream::with_buffers(socket2, 1500, 1);
+
+        for i in 0..3u8 {
+            sink.feed(Transmit {
+                destination: Some(addr2),
+                ecn: None,
+                contents: vec![i; 10],
+                segment_size: None,
+                src_ip: None,
+            })
+            .await?;
+        }
+        sink.flush().await?;
+
+        for i in 0..3u8 {
+            let (_, datagram) = stream.next().await.unwrap()?;
+            assert_eq!(&datagram[..], &[i; 10]);
+        }
+
+        Ok(())
+    }
+}
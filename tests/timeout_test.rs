@@ -0,0 +1,64 @@
+#[macro_use]
+mod common;
+
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{AsyncUdpSocket, Capabilities, RecvMeta, Transmit};
+    use futures::FutureExt;
+    use std::io::{self, IoSliceMut};
+    use std::time::{Duration, Instant};
+    use rt::UdpSocket;
+
+    #[test]
+    async fn test_recv_timeout() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let socket1 = UdpSocket::bind("127.0.0.1:0").await?;
+        let socket2 = UdpSocket::bind("127.0.0.1:0").await?;
+        let mut buf = [0u8; 1500];
+        let mut meta = [RecvMeta::default()];
+
+        let start = Instant::now();
+        let err = socket2
+            .recv_timeout(
+                &mut [IoSliceMut::new(&mut buf)],
+                &mut meta,
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // A receive which is dropped while waiting does not hold on to later datagrams
+        let pending = socket2
+            .recv_timeout(
+                &mut [IoSliceMut::new(&mut buf)],
+                &mut meta,
+                Duration::from_secs(5),
+            )
+            .now_or_never();
+        assert!(pending.is_none());
+
+        let transmits = [Transmit {
+            destination: Some(socket2.local_addr()?),
+            ecn: None,
+            contents: b"hello".to_vec(),
+            segment_size: None,
+            src_ip: None,
+        }];
+        let n = socket1
+            .send_timeout(&capabilities, &transmits, Duration::from_secs(5))
+            .await?;
+        assert_eq!(n, 1);
+        exec::sleep(Duration::from_millis(20)).await;
+
+        // Queued datagrams are returned even after the deadline
+        let n = socket2
+            .recv_deadline(&mut [IoSliceMut::new(&mut buf)], &mut meta, start)
+            .await?;
+        assert_eq!(n, 1);
+        assert_eq!(&buf[..meta[0].len], b"hello");
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+#[macro_use]
+mod common;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, EcnCodepoint, RecvMeta, Transmit, BATCH_SIZE,
+    };
+    use std::io::{ErrorKind, IoSliceMut};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use rt::UdpLiteSocket;
+
+    #[test]
+    async fn test_checksum_coverage() -> Result<()> {
+        let socket = UdpLiteSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))?;
+        assert_eq!(socket.send_checksum_coverage()?, 0);
+        socket.set_send_checksum_coverage(20)?;
+        assert_eq!(socket.send_checksum_coverage()?, 20);
+        socket.set_recv_checksum_coverage(16)?;
+        assert_eq!(socket.recv_checksum_coverage()?, 16);
+
+        // The header must always be covered
+        assert_eq!(
+            socket.set_send_checksum_coverage(4).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        Ok(())
+    }
+
+    #[test]
+    async fn test_send_recv() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let socket1 = UdpLiteSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))?;
+        let socket2 = UdpLiteSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))?;
+        socket1.set_send_checksum_coverage(16)?;
+        socket2.set_recv_checksum_coverage(16)?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+
+        let count = BATCH_SIZE.min(4);
+        let transmits = (0..count)
+            .map(|i| Transmit {
+                destination: Some(addr2),
+                ecn: Some(EcnCodepoint::Ect0),
+                contents: vec![i as u8; 100],
+                segment_size: None,
+                src_ip: None,
+            })
+            .collect::<Vec<_>>();
+        let mut sent = 0;
+        while sent < transmits.len() {
+            sent += socket1.send(&capabilities, &transmits[sent..]).await?;
+        }
+
+        let mut received = 0;
+        while received < count {
+            let mut storage = [[0u8; 1200]; BATCH_SIZE];
+            let mut buffers = storage
+                .iter_mut()
+                .map(|b| IoSliceMut::new(b))
+                .collect::<Vec<_>>();
+            let mut meta = [RecvMeta::default(); BATCH_SIZE];
+            let n = socket2.recv(&mut buffers, &mut meta).await?;
+            for i in 0..n {
+                assert_eq!(meta[i].addr, addr1);
+                assert_eq!(meta[i].ecn, Some(EcnCodepoint::Ect0));
+                assert_eq!(meta[i].dst_ip, Some(Ipv4Addr::LOCALHOST.into()));
+                assert_eq!(&buffers[i][..meta[i].len], &[received as u8; 100][..]);
+                received += 1;
+            }
+        }
+        Ok(())
+    }
+    #[test]
+    async fn test_reject_segmentation() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let max_gso_segments = capabilities.max_gso_segments();
+        let socket1 = UdpLiteSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))?;
+        let socket2 = UdpLiteSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))?;
+        socket1.set_send_checksum_coverage(16)?;
+        let addr2 = socket2.local_addr()?;
+
+        let transmits = [Transmit {
+            destination: Some(addr2),
+            ecn: None,
+            contents: vec![0; 100],
+            segment_size: Some(50),
+            src_ip: None,
+        }];
+        assert_eq!(
+            socket1
+                .send(&capabilities, &transmits)
+                .await
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            socket1
+                .try_send(&capabilities, &transmits)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidInput
+        );
+        // Nothing was sent, so segmentation offload stays enabled
+        assert_eq!(capabilities.max_gso_segments(), max_gso_segments);
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+#[macro_use]
+mod common;
+
+#[cfg(unix)]
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{UnixRecvMeta, UnixSocketAddr, UnixTransmit, BATCH_SIZE};
+    use std::fs::File;
+    use std::io::{IoSliceMut, Read, Seek, SeekFrom, Write};
+    use std::os::fd::OwnedFd;
+    use std::path::PathBuf;
+    use rt::UnixDatagram;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "async-transport-{}-{}.sock",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    async fn recv_all(socket: &UnixDatagram, count: usize) -> Result<Vec<(UnixRecvMeta, Vec<u8>)>> {
+        let mut received = Vec::with_capacity(count);
+        while received.len() < count {
+            let mut storage = [[0u8; 1200]; BATCH_SIZE];
+            let mut buffers = storage
+                .iter_mut()
+                .map(|b| IoSliceMut::new(b))
+                .collect::<Vec<_>>();
+            let mut meta = std::iter::repeat_with(UnixRecvMeta::default)
+                .take(BATCH_SIZE)
+                .collect::<Vec<_>>();
+            let n = socket.recv(&mut buffers, &mut meta).await?;
+            for i in 0..n {
+                let contents = buffers[i][..meta[i].len].to_vec();
+                received.push((std::mem::take(&mut meta[i]), contents));
+            }
+        }
+        Ok(received)
+    }
+
+    #[test]
+    async fn test_pathname() -> Result<()> {
+        let path1 = temp_path("pathname1");
+        let path2 = temp_path("pathname2");
+        let socket1 = UnixDatagram::bind(&path1)?;
+        let socket2 = UnixDatagram::bind(&path2)?;
+        assert_eq!(socket2.local_addr()?, UnixSocketAddr::pathname(&path2));
+
+        // Stay below the default receive queue length (net.unix.max_dgram_qlen)
+        let count = BATCH_SIZE.min(8);
+        let transmits = (0..count)
+            .map(|i| UnixTransmit {
+                destination: Some(UnixSocketAddr::pathname(&path2)),
+                contents: (i as u64).to_be_bytes().to_vec(),
+                fds: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        let mut sent = 0;
+        while sent < transmits.len() {
+            sent += socket1.send(&transmits[sent..]).await?;
+        }
+
+        for (i, (meta, contents)) in recv_all(&socket2, count).await?.into_iter().enumerate() {
+            assert_eq!(meta.addr, UnixSocketAddr::pathname(&path1));
+            assert_eq!(contents, (i as u64).to_be_bytes());
+        }
+
+        // A missing destination is reported rather than dropped
+        let missing = temp_path("missing");
+        assert!(socket1
+            .send(&[UnixTransmit {
+                destination: Some(UnixSocketAddr::pathname(&missing)),
+                contents: vec![0],
+                fds: Vec::new(),
+            }])
+            .await
+            .is_err());
+
+        std::fs::remove_file(&path1)?;
+        std::fs::remove_file(&path2)?;
+        Ok(())
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    async fn test_abstract() -> Result<()> {
+        let name = format!("async-transport-abstract-{}", std::process::id());
+        let addr = UnixSocketAddr::abstract_name(&name);
+        let server = UnixDatagram::bind_addr(&addr)?;
+        assert_eq!(server.local_addr()?, addr);
+
+        let client = UnixDatagram::unbound()?;
+        client.connect_addr(&addr)?;
+        assert_eq!(client.peer_addr()?, addr);
+        client
+            .send(&[UnixTransmit {
+                destination: None,
+                contents: b"hello".to_vec(),
+                fds: Vec::new(),
+            }])
+            .await?;
+
+        let received = recv_all(&server, 1).await?;
+        assert_eq!(received[0].0.addr, UnixSocketAddr::Unnamed);
+        assert_eq!(received[0].1, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    async fn test_pair() -> Result<()> {
+        let (a, b) = UnixDatagram::pair()?;
+        a.send(&[UnixTransmit {
+            destination: None,
+            contents: b"ping".to_vec(),
+            fds: Vec::new(),
+        }])
+        .await?;
+        let received = recv_all(&b, 1).await?;
+        assert!(received[0].0.addr.is_unnamed());
+        assert_eq!(received[0].1, b"ping");
+        Ok(())
+    }
+
+    #[test]
+    async fn test_pass_fds() -> Result<()> {
+        let (a, b) = UnixDatagram::pair()?;
+        b.set_max_recv_fds(1);
+
+        let path = temp_path("fd");
+        let mut file = File::options()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        file.write_all(b"passed")?;
+        std::fs::remove_file(&path)?;
+
+        // Only as many descriptors as requested are received, the rest are discarded
+        a.send(&[UnixTransmit {
+            destination: None,
+            contents: b"fds".to_vec(),
+            fds: vec![OwnedFd::from(file.try_clone()?), OwnedFd::from(file)],
+        }])
+        .await?;
+        let (mut meta, contents) = recv_all(&b, 1).await?.remove(0);
+        assert_eq!(contents, b"fds");
+        assert_eq!(meta.fds.len(), 1);
+
+        let mut received = File::from(meta.fds.remove(0));
+        let mut buf = String::new();
+        received.seek(SeekFrom::Start(0))?;
+        received.read_to_string(&mut buf)?;
+        assert_eq!(buf, "passed");
+
+        // Without room in the control buffer, no descriptors are received
+        b.set_max_recv_fds(0);
+        a.send(&[UnixTransmit {
+            destination: None,
+            contents: b"none".to_vec(),
+            fds: vec![OwnedFd::from(received)],
+        }])
+        .await?;
+        let (meta, _) = recv_all(&b, 1).await?.remove(0);
+        assert!(meta.fds.is_empty());
+        Ok(())
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    async fn test_pass_credentials() -> Result<()> {
+        let (a, b) = UnixDatagram::pair()?;
+        b.set_pass_credentials(true)?;
+        a.send(&[UnixTransmit {
+            destination: None,
+            contents: b"creds".to_vec(),
+            fds: Vec::new(),
+        }])
+        .await?;
+        let (meta, _) = recv_all(&b, 1).await?.remove(0);
+        let credentials = meta.credentials.expect("credentials");
+        assert_eq!(credentials.pid as u32, std::process::id());
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+#[macro_use]
+mod common;
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[cfg(test)]
+runtime_tests! {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, EcnCodepoint, RecvMeta, Transmit, UdpSocketState,
+    };
+    use std::io::{self, IoSliceMut};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::os::fd::AsRawFd;
+    use rt::UdpSocket;
+
+    fn transmit(destination: SocketAddr, contents: Vec<u8>) -> Transmit {
+        Transmit {
+            destination: Some(destination),
+            ecn: Some(EcnCodepoint::Ect0),
+            contents,
+            segment_size: None,
+            src_ip: Some(Ipv4Addr::LOCALHOST.into()),
+        }
+    }
+
+    /// Receives datagrams until `len` bytes arrived, returning their metadata and contents
+    async fn recv_all(socket: &UdpSocket, len: usize) -> Result<Vec<(RecvMeta, Vec<u8>)>> {
+        let mut received = Vec::new();
+        let mut total = 0;
+        while total < len {
+            let mut storage = vec![[0u8; u16::MAX as usize]; 4];
+            let mut buffers = storage
+                .iter_mut()
+                .map(|b| IoSliceMut::new(b))
+                .collect::<Vec<_>>();
+            let mut meta = [RecvMeta::default(); 4];
+            let n = socket.recv(&mut buffers, &mut meta).await?;
+            for i in 0..n {
+                received.push((meta[i], buffers[i][..meta[i].len].to_vec()));
+                total += meta[i].len;
+            }
+        }
+        Ok(received)
+    }
+
+    #[test]
+    fn test_state() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let sender = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let receiver = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        UdpSocketState::configure((&sender).into())?;
+        UdpSocketState::configure((&receiver).into())?;
+        let send_state = UdpSocketState::new();
+        let mut recv_state = UdpSocketState::new();
+        if let Err(e) = recv_state.enable_io_uring((&receiver).into()) {
+            println!("io_uring is unavailable: {e}");
+            return Ok(());
+        }
+        let ring = recv_state.io_uring_fd().unwrap().as_raw_fd();
+
+        let transmits = [transmit(receiver.local_addr()?, b"hello".to_vec())];
+        assert_eq!(
+            send_state.send((&sender).into(), &capabilities, &transmits)?,
+            1
+        );
+
+        // Nothing is read from the socket itself, the ring signals received datagrams
+        let mut buf = [0u8; 1500];
+        let mut meta = [RecvMeta::default()];
+        let n = loop {
+            match recv_state.recv(
+                (&receiver).into(),
+                &mut [IoSliceMut::new(&mut buf)],
+                &mut meta,
+            ) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    let mut pollfd = libc::pollfd {
+                        fd: ring,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    };
+                    assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 1000) }, 1);
+                }
+                res => break res?,
+            }
+        };
+        assert_eq!(n, 1);
+        assert_eq!(&buf[..meta[0].len], b"hello");
+        assert_eq!(meta[0].addr, sender.local_addr()?);
+        assert_eq!(meta[0].ecn, Some(EcnCodepoint::Ect0));
+        assert_eq!(meta[0].dst_ip, Some(Ipv4Addr::LOCALHOST.into()));
+        Ok(())
+    }
+
+    #[test]
+    async fn test_send_recv() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        let socket1 = UdpSocket::bind(addr).await?;
+        let socket2 = UdpSocket::bind(addr).await?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+
+        // More datagrams than there are receive buffers, which are recycled on the way
+        let transmits = (0..200u32)
+            .map(|i| transmit(addr2, i.to_be_bytes().to_vec()))
+            .collect::<Vec<_>>();
+        let (sent, received) = futures::join!(
+            async {
+                let mut sent = 0;
+                while sent < transmits.len() {
+                    sent += socket1.send(&capabilities, &transmits[sent..]).await?;
+                    exec::yield_now().await;
+                }
+                io::Result::Ok(sent)
+            },
+            recv_all(&socket2, 4 * transmits.len())
+        );
+        assert_eq!(sent?, transmits.len());
+        let received = received?;
+        assert_eq!(received.len(), transmits.len());
+        for (i, (meta, contents)) in received.into_iter().enumerate() {
+            assert_eq!(contents, (i as u32).to_be_bytes());
+            assert_eq!(meta.addr, addr1);
+            assert_eq!(meta.ecn, Some(EcnCodepoint::Ect0));
+            assert_eq!(meta.dst_ip, Some(Ipv4Addr::LOCALHOST.into()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    async fn test_gso() -> Result<()> {
+        let capabilities = Capabilities::new();
+        if capabilities.max_gso_segments() < 2 {
+            return Ok(());
+        }
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        let socket1 = UdpSocket::bind(addr).await?;
+        let socket2 = UdpSocket::bind(addr).await?;
+
+        let mut transmit = transmit(socket2.local_addr()?, vec![7; 2500]);
+        transmit.segment_size = Some(1000);
+        socket1.send(&capabilities, &[transmit]).await?;
+
+        let received = recv_all(&socket2, 2500).await?;
+        let segments = received
+            .iter()
+            .flat_map(|(meta, contents)| contents.chunks(meta.stride))
+            .map(|segment| segment.len())
+            .collect::<Vec<_>>();
+        assert_eq!(segments, [1000, 1000, 500]);
+        Ok(())
+    }
+}
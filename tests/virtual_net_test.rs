@@ -0,0 +1,340 @@
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use async_transport::{
+        AsyncUdpSocket, Capabilities, EcnCodepoint, RecvMeta, Transmit, VirtualLinkConfig,
+        VirtualNetwork, VirtualUdpSocket,
+    };
+    use std::io::{self, IoSliceMut};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    fn addr(host: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, host)), 0)
+    }
+
+    fn transmit(destination: SocketAddr, contents: Vec<u8>) -> Transmit {
+        Transmit {
+            destination: Some(destination),
+            ecn: Some(EcnCodepoint::Ect0),
+            contents,
+            segment_size: None,
+            src_ip: None,
+        }
+    }
+
+    async fn recv(socket: &VirtualUdpSocket) -> Result<Batch> {
+        let mut storage = [[0u8; 4096]; 8];
+        let mut buffers = storage
+            .iter_mut()
+            .map(|b| IoSliceMut::new(b))
+            .collect::<Vec<_>>();
+        let mut meta = [RecvMeta::default(); 8];
+        let n = socket.recv(&mut buffers, &mut meta).await?;
+        Ok((0..n)
+            .map(|i| (meta[i], buffers[i][..meta[i].len].to_vec()))
+            .collect())
+    }
+
+    /// Datagrams received by a single call, with their metadata
+    type Batch = Vec<(RecvMeta, Vec<u8>)>;
+
+    /// Receives the batches which are due without waiting
+    fn try_recv(socket: &VirtualUdpSocket) -> Result<Vec<Batch>> {
+        let mut batches = Vec::new();
+        loop {
+            let mut storage = [[0u8; 4096]; 8];
+            let mut buffers = storage
+                .iter_mut()
+                .map(|b| IoSliceMut::new(b))
+                .collect::<Vec<_>>();
+            let mut meta = [RecvMeta::default(); 8];
+            let n = match socket.try_recv(&mut buffers, &mut meta) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(batches),
+                res => res?,
+            };
+            batches.push(
+                (0..n)
+                    .map(|i| (meta[i], buffers[i][..meta[i].len].to_vec()))
+                    .collect(),
+            );
+        }
+    }
+
+    /// Advances the clock of the network millisecond by millisecond for a second, receiving
+    /// the batches which become due
+    fn drain(network: &VirtualNetwork, socket: &VirtualUdpSocket) -> Result<Vec<Batch>> {
+        let mut batches = try_recv(socket)?;
+        for _ in 0..1000 {
+            network.advance(Duration::from_millis(1));
+            batches.extend(try_recv(socket)?);
+        }
+        Ok(batches)
+    }
+
+    /// Receives everything that arrives within a second of virtual time
+    fn recv_all(network: &VirtualNetwork, socket: &VirtualUdpSocket) -> Result<Batch> {
+        Ok(drain(network, socket)?.into_iter().flatten().collect())
+    }
+
+    #[tokio::test]
+    async fn test_delivery() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let network = VirtualNetwork::new(1);
+        let socket1 = network.bind(addr(1))?;
+        let socket2 = network.bind(addr(2))?;
+        let addr1 = socket1.local_addr()?;
+        let addr2 = socket2.local_addr()?;
+        assert_ne!(addr2.port(), 0);
+
+        socket1
+            .send(&capabilities, &[transmit(addr2, b"hello".to_vec())])
+            .await?;
+        let received = recv(&socket2).await?;
+        assert_eq!(received.len(), 1);
+        let (meta, contents) = &received[0];
+        assert_eq!(contents, b"hello");
+        assert_eq!(meta.addr, addr1);
+        assert_eq!(meta.dst_ip, Some(addr2.ip()));
+        assert_eq!(meta.ecn, Some(EcnCodepoint::Ect0));
+        assert_eq!(meta.stride, 5);
+
+        // Connected sockets drop datagrams from other peers
+        let socket3 = network.bind(addr(3))?;
+        socket2.connect(socket3.local_addr()?)?;
+        socket1
+            .send(&capabilities, &[transmit(addr2, b"ignored".to_vec())])
+            .await?;
+        socket3
+            .send(&capabilities, &[transmit(addr2, b"peer".to_vec())])
+            .await?;
+        let received = recv(&socket2).await?;
+        assert_eq!(received[0].1, b"peer");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_latency() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let network = VirtualNetwork::new(1);
+        network.set_config(VirtualLinkConfig {
+            latency: Duration::from_millis(50),
+            ..VirtualLinkConfig::default()
+        });
+        let socket1 = network.bind(addr(1))?;
+        let socket2 = network.bind(addr(2))?;
+
+        let start = network.now();
+        socket1
+            .send(&capabilities, &[transmit(socket2.local_addr()?, vec![0])])
+            .await?;
+        // Time only passes when the clock is advanced
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(try_recv(&socket2)?.is_empty());
+        network.advance(Duration::from_millis(49));
+        assert!(try_recv(&socket2)?.is_empty());
+
+        // Advancing the clock wakes a waiting receiver
+        let receiver = tokio::spawn(async move { recv(&socket2).await.map(|r| r.len()) });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        network.advance(Duration::from_millis(1));
+        assert_eq!(receiver.await??, 1);
+        assert_eq!(network.now() - start, Duration::from_millis(50));
+        Ok(())
+    }
+
+    #[cfg(any(
+        feature = "runtime-smol",
+        feature = "runtime-tokio",
+        feature = "runtime-async-std"
+    ))]
+    #[tokio::test]
+    async fn test_latency_with_runtime() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let runtime = async_transport::default_runtime().unwrap();
+        let network = VirtualNetwork::with_runtime(1, runtime);
+        network.set_config(VirtualLinkConfig {
+            latency: Duration::from_millis(50),
+            ..VirtualLinkConfig::default()
+        });
+        let socket1 = network.bind(addr(1))?;
+        let socket2 = network.bind(addr(2))?;
+
+        let start = std::time::Instant::now();
+        socket1
+            .send(&capabilities, &[transmit(socket2.local_addr()?, vec![0])])
+            .await?;
+        recv(&socket2).await?;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Advancing the clock moves it ahead of the runtime
+        socket1
+            .send(&capabilities, &[transmit(socket2.local_addr()?, vec![1])])
+            .await?;
+        network.advance(Duration::from_millis(50));
+        assert_eq!(try_recv(&socket2)?.len(), 1);
+        Ok(())
+    }
+
+    /// Sends 100 numbered datagrams over a lossy, reordering link and returns what arrived
+    async fn impaired_run(seed: u64) -> Result<Vec<u8>> {
+        let capabilities = Capabilities::new();
+        let network = VirtualNetwork::new(seed);
+        network.set_config(VirtualLinkConfig {
+            jitter: Duration::from_millis(5),
+            loss: 0.2,
+            duplicate: 0.1,
+            reorder: 0.1,
+            reorder_delay: Duration::from_millis(10),
+            ce_mark: 0.5,
+            ..VirtualLinkConfig::default()
+        });
+        let socket1 = network.bind(addr(1))?;
+        let socket2 = network.bind(addr(2))?;
+        let addr2 = socket2.local_addr()?;
+        let transmits = (0..100u8)
+            .map(|i| transmit(addr2, vec![i]))
+            .collect::<Vec<_>>();
+        socket1.send(&capabilities, &transmits).await?;
+
+        let received = recv_all(&network, &socket2)?;
+        assert!(received
+            .iter()
+            .any(|(meta, _)| meta.ecn == Some(EcnCodepoint::Ce)));
+        Ok(received
+            .into_iter()
+            .map(|(_, contents)| contents[0])
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn test_impairments_are_deterministic() -> Result<()> {
+        let first = impaired_run(42).await?;
+        let mut sorted = first.clone();
+        sorted.sort();
+        sorted.dedup();
+        // Some were lost, and some arrived twice or out of order
+        assert!(sorted.len() < 100);
+        assert!(sorted.len() < first.len() || sorted != first);
+
+        // The same seed leads to the same fate for every datagram
+        assert_eq!(first, impaired_run(42).await?);
+        Ok(())
+    }
+
+    /// Sends bursts of GSO batches over a jittery link to a socket with GRO, returning the
+    /// batches received
+    async fn jittery_gro_run(seed: u64) -> Result<Vec<Vec<(usize, usize, Vec<u8>)>>> {
+        let capabilities = Capabilities::new();
+        let network = VirtualNetwork::new(seed);
+        network.set_config(VirtualLinkConfig {
+            latency: Duration::from_millis(2),
+            jitter: Duration::from_millis(3),
+            ..VirtualLinkConfig::default()
+        });
+        let socket1 = network.bind(addr(1))?;
+        let socket2 = network.bind(addr(2))?;
+        socket2.set_gro_segments(4);
+        let addr2 = socket2.local_addr()?;
+
+        let mut batches = Vec::new();
+        for i in 0..20u8 {
+            let gso = Transmit {
+                segment_size: Some(100),
+                ..transmit(addr2, vec![i; 350])
+            };
+            socket1.send(&capabilities, &[gso]).await?;
+            network.advance(Duration::from_millis(1));
+            batches.extend(try_recv(&socket2)?);
+        }
+        batches.extend(drain(&network, &socket2)?);
+        Ok(batches
+            .into_iter()
+            .map(|batch| {
+                batch
+                    .into_iter()
+                    .map(|(meta, contents)| (meta.len, meta.stride, contents))
+                    .collect()
+            })
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn test_jitter_and_gro_are_deterministic() -> Result<()> {
+        let first = jittery_gro_run(7).await?;
+        let datagrams = first.iter().flatten().collect::<Vec<_>>();
+        assert_eq!(
+            datagrams.iter().map(|(len, _, _)| len).sum::<usize>(),
+            20 * 350
+        );
+        // Jitter reordered datagrams and split GSO batches, while GRO coalesced some again
+        assert!(datagrams.windows(2).any(|w| w[0].2[0] > w[1].2[0]));
+        assert!(datagrams.iter().any(|(len, stride, _)| len > stride));
+
+        assert_eq!(first, jittery_gro_run(7).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mtu_and_ecn_bleaching() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let network = VirtualNetwork::new(1);
+        network.set_config(VirtualLinkConfig {
+            mtu: 1200,
+            ecn_bleach: true,
+            ..VirtualLinkConfig::default()
+        });
+        let socket1 = network.bind(addr(1))?;
+        let socket2 = network.bind(addr(2))?;
+        let addr2 = socket2.local_addr()?;
+        socket1
+            .send(
+                &capabilities,
+                &[
+                    transmit(addr2, vec![1; 1201]),
+                    transmit(addr2, vec![2; 1200]),
+                ],
+            )
+            .await?;
+        let received = recv_all(&network, &socket2)?;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].1, vec![2; 1200]);
+        assert_eq!(received[0].0.ecn, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gso_gro() -> Result<()> {
+        let capabilities = Capabilities::new();
+        let network = VirtualNetwork::new(1);
+        // Segments are impaired individually, so the oversized GSO batch gets through
+        network.set_config(VirtualLinkConfig {
+            mtu: 1200,
+            ..VirtualLinkConfig::default()
+        });
+        let socket1 = network.bind(addr(1))?;
+        let socket2 = network.bind(addr(2))?;
+        let addr2 = socket2.local_addr()?;
+        let contents = (0..2500).map(|i| i as u8).collect::<Vec<_>>();
+        let gso = || Transmit {
+            segment_size: Some(1000),
+            ..transmit(addr2, contents.clone())
+        };
+
+        // Without GRO, every segment is its own datagram
+        socket1.send(&capabilities, &[gso()]).await?;
+        let received = recv_all(&network, &socket2)?;
+        let lens = received.iter().map(|(m, _)| m.len).collect::<Vec<_>>();
+        assert_eq!(lens, [1000, 1000, 500]);
+
+        // With GRO, they are coalesced into one buffer with a stride
+        socket2.set_gro_segments(8);
+        socket1.send(&capabilities, &[gso()]).await?;
+        let received = recv_all(&network, &socket2)?;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0.len, 2500);
+        assert_eq!(received[0].0.stride, 1000);
+        assert_eq!(received[0].1, contents);
+        Ok(())
+    }
+}